@@ -0,0 +1,198 @@
+//! Compares the piece table against the `Vec<EditorRow>` representation the editor used before,
+//! on opening, editing and saving a large log-like file. Run with `cargo bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use kilo_rs::buffer::TextBuffer;
+
+const LINES: usize = 500_000;
+const EDITS: usize = 1_000;
+const TAB_STOP: usize = 8;
+
+/// The system allocator, keeping count of the bytes currently allocated
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// A row as stored before the text buffer: the chars plus a full rendered copy
+struct Row {
+    chars: Vec<char>,
+    // never read here, but held by every row all the same
+    #[allow(dead_code)]
+    render: Vec<char>,
+}
+
+impl Row {
+    fn new(line: &str) -> Self {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut render = vec![];
+        for &c in &chars {
+            if c == '\t' {
+                render.push(' ');
+                while !render.len().is_multiple_of(TAB_STOP) {
+                    render.push(' ');
+                }
+            } else {
+                render.push(c);
+            }
+        }
+        Self { chars, render }
+    }
+}
+
+fn rows_open(path: &Path) -> Vec<Row> {
+    let reader = BufReader::new(File::open(path).unwrap());
+    reader
+        .lines()
+        .map(|line| Row::new(&line.unwrap()))
+        .collect()
+}
+
+fn rows_insert(rows: &mut [Row], edits: usize) {
+    for i in 0..edits {
+        let row = &mut rows[(i * 7919) % LINES];
+        row.chars.insert(row.chars.len() / 2, 'x');
+        *row = Row::new(&row.chars.iter().collect::<String>());
+    }
+}
+
+fn rows_save(rows: &[Row], path: &Path) {
+    let mut content = String::new();
+    for row in rows {
+        content.push_str(&row.chars.iter().collect::<String>());
+        content.push('\n');
+    }
+    fs::write(path, content).unwrap();
+}
+
+fn buffer_open(path: &Path) -> TextBuffer {
+    TextBuffer::from_bytes(fs::read(path).unwrap())
+}
+
+fn buffer_insert(buffer: &mut TextBuffer, edits: usize) {
+    for i in 0..edits {
+        let line = (i * 7919) % LINES;
+        let start = buffer.line_start(line);
+        let len = buffer.line_end(line) - start;
+        buffer.insert(start + len / 2, "x");
+    }
+}
+
+fn buffer_save(buffer: &TextBuffer, path: &Path) {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    buffer.write_to(&mut file).unwrap();
+    file.flush().unwrap();
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+/// Time a function, also returning the heap bytes still allocated once it's done
+fn time_and_measure<T>(f: impl FnOnce() -> T) -> (T, Duration, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let (result, elapsed) = time(f);
+    let retained = ALLOCATED.load(Ordering::Relaxed).saturating_sub(before);
+    (result, elapsed, retained)
+}
+
+fn report(name: &str, rows: Duration, buffer: Duration) {
+    println!(
+        "{:<8} rows {:>10.2?}   piece table {:>10.2?}   ({:.2}x)",
+        name,
+        rows,
+        buffer,
+        rows.as_secs_f64() / buffer.as_secs_f64()
+    );
+}
+
+fn scratch_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("kilo-bench-{}-{}", std::process::id(), name))
+}
+
+fn main() {
+    let input = scratch_file("input.log");
+    let mut file = BufWriter::new(File::create(&input).unwrap());
+    for i in 0..LINES {
+        writeln!(
+            file,
+            "2024-01-01T00:00:{:02}Z\tINFO\trequest {} served in {}ms from cache shard {}",
+            i % 60,
+            i,
+            i % 977,
+            i % 16
+        )
+        .unwrap();
+    }
+    file.flush().unwrap();
+    drop(file);
+    println!(
+        "{} lines, {} bytes, {} edits",
+        LINES,
+        fs::metadata(&input).unwrap().len(),
+        EDITS
+    );
+
+    let (mut rows, rows_open_time, rows_memory) = time_and_measure(|| rows_open(&input));
+    let (mut buffer, buffer_open_time, buffer_memory) = time_and_measure(|| buffer_open(&input));
+    report("open", rows_open_time, buffer_open_time);
+    println!(
+        "memory   rows {:>8} MB   piece table {:>8} MB",
+        rows_memory >> 20,
+        buffer_memory >> 20
+    );
+
+    let (_, rows_insert_time) = time(|| rows_insert(&mut rows, EDITS));
+    let (_, buffer_insert_time) = time(|| buffer_insert(&mut buffer, EDITS));
+    report("insert", rows_insert_time, buffer_insert_time);
+
+    let rows_output = scratch_file("rows.log");
+    let buffer_output = scratch_file("buffer.log");
+    let (_, rows_save_time) = time(|| rows_save(&rows, &rows_output));
+    let (_, buffer_save_time) = time(|| buffer_save(&buffer, &buffer_output));
+    report("save", rows_save_time, buffer_save_time);
+
+    assert_eq!(
+        fs::read(&rows_output).unwrap(),
+        fs::read(&buffer_output).unwrap(),
+        "both representations should save the same file"
+    );
+
+    for path in [input, rows_output, buffer_output] {
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//! A piece table text buffer.
+//!
+//! The file is kept as the bytes it was read with, plus an append-only buffer of inserted text.
+//! The document is a sequence of pieces, each a byte range of one of the two, so edits never copy
+//! the original and saving streams the pieces straight to disk. The offsets of line breaks in
+//! both buffers are indexed once so that lines can be found without scanning the text.
+//!
+//! Rows are decoded with one replacement character for each byte that isn't valid UTF-8, so a
+//! column always maps back to the bytes it came from and editing around them leaves them intact.
+//! A file whose lines end in "\r\n" keeps them: the '\r' isn't part of any row, and line breaks
+//! inserted into it are written the same way.
+
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Original,
+    Add,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+}
+
+pub struct TextBuffer {
+    original: Vec<u8>,
+    add: Vec<u8>,
+    // offsets of every '\n' in `original` and `add`
+    original_newlines: Vec<usize>,
+    add_newlines: Vec<usize>,
+    pieces: Vec<Piece>,
+    len: usize,
+    newlines: usize,
+    // whether line breaks are "\r\n", going by the first line of the file
+    crlf: bool,
+}
+
+impl Default for TextBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn newline_offsets(bytes: &[u8], base: usize) -> impl Iterator<Item = usize> + '_ {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .map(move |(i, _)| base + i)
+}
+
+/// Decode bytes as UTF-8, with a replacement character for each byte of an invalid sequence
+fn decode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        text.extend(std::iter::repeat_n(
+            char::REPLACEMENT_CHARACTER,
+            chunk.invalid().len(),
+        ));
+    }
+    text
+}
+
+/// Byte offset of char `cx` in bytes decoded by `decode`, or the length if there are fewer
+fn char_offset(bytes: &[u8], cx: usize) -> usize {
+    let mut offset = 0;
+    let mut remaining = cx;
+    for chunk in bytes.utf8_chunks() {
+        let char_lens = chunk
+            .valid()
+            .chars()
+            .map(char::len_utf8)
+            .chain(chunk.invalid().iter().map(|_| 1));
+        for len in char_lens {
+            if remaining == 0 {
+                return offset;
+            }
+            offset += len;
+            remaining -= 1;
+        }
+    }
+    offset
+}
+
+impl TextBuffer {
+    pub fn new() -> Self {
+        Self::from_bytes(vec![])
+    }
+
+    pub fn from_bytes(original: Vec<u8>) -> Self {
+        let original_newlines = newline_offsets(&original, 0).collect::<Vec<_>>();
+        let len = original.len();
+        let pieces = if len > 0 {
+            vec![Piece {
+                source: Source::Original,
+                start: 0,
+                len,
+            }]
+        } else {
+            vec![]
+        };
+
+        let crlf = original_newlines
+            .first()
+            .is_some_and(|&i| i > 0 && original[i - 1] == b'\r');
+
+        Self {
+            newlines: original_newlines.len(),
+            crlf,
+            original,
+            add: vec![],
+            original_newlines,
+            add_newlines: vec![],
+            pieces,
+            len,
+        }
+    }
+
+    /// Length of the document in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of rows: every line terminated by '\n', plus a final unterminated one if present
+    pub fn line_count(&self) -> usize {
+        if self.len > 0 && self.byte_at(self.len - 1) != b'\n' {
+            self.newlines + 1
+        } else {
+            self.newlines
+        }
+    }
+
+    fn bytes(&self, piece: &Piece) -> &[u8] {
+        let source = match piece.source {
+            Source::Original => &self.original,
+            Source::Add => &self.add,
+        };
+        &source[piece.start..piece.start + piece.len]
+    }
+
+    fn newline_index(&self, source: Source) -> &[usize] {
+        match source {
+            Source::Original => &self.original_newlines,
+            Source::Add => &self.add_newlines,
+        }
+    }
+
+    /// Newline offsets in the source buffer that fall within the piece
+    fn piece_newlines(&self, piece: &Piece) -> &[usize] {
+        let index = self.newline_index(piece.source);
+        let from = index.partition_point(|&o| o < piece.start);
+        let to = index.partition_point(|&o| o < piece.start + piece.len);
+        &index[from..to]
+    }
+
+    fn byte_at(&self, offset: usize) -> u8 {
+        let (i, inner) = self.find_piece(offset);
+        self.bytes(&self.pieces[i])[inner]
+    }
+
+    /// Index of the piece containing the byte at `offset` and the offset within it. An offset at
+    /// the end of the document gives the piece count.
+    fn find_piece(&self, offset: usize) -> (usize, usize) {
+        let mut piece_start = 0;
+        for (i, piece) in self.pieces.iter().enumerate() {
+            if offset < piece_start + piece.len {
+                return (i, offset - piece_start);
+            }
+            piece_start += piece.len;
+        }
+        (self.pieces.len(), 0)
+    }
+
+    /// Byte offset of the start of row `line`. Rows past the end start at the end.
+    pub fn line_start(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+        if line > self.newlines {
+            return self.len;
+        }
+
+        // find the piece holding the line'th newline
+        let mut remaining = line;
+        let mut piece_start = 0;
+        for piece in &self.pieces {
+            let newlines = self.piece_newlines(piece);
+            if remaining <= newlines.len() {
+                return piece_start + newlines[remaining - 1] - piece.start + 1;
+            }
+            remaining -= newlines.len();
+            piece_start += piece.len;
+        }
+
+        self.len
+    }
+
+    /// Byte offset of the end of row `line`, excluding its '\n' and a '\r' before it
+    pub fn line_end(&self, line: usize) -> usize {
+        if line < self.newlines {
+            let end = self.line_start(line + 1) - 1;
+            if end > 0 && self.byte_at(end - 1) == b'\r' {
+                end - 1
+            } else {
+                end
+            }
+        } else {
+            self.len
+        }
+    }
+
+    /// Copy out the bytes in a range
+    pub fn slice(&self, start: usize, end: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(end - start);
+        let (first, mut inner) = self.find_piece(start);
+        for piece in &self.pieces[first..] {
+            if out.len() >= end - start {
+                break;
+            }
+            let bytes = &self.bytes(piece)[inner..];
+            let take = bytes.len().min(end - start - out.len());
+            out.extend_from_slice(&bytes[..take]);
+            inner = 0;
+        }
+        out
+    }
+
+    /// The text of row `line`, without its line break
+    pub fn line(&self, line: usize) -> String {
+        decode(&self.slice(self.line_start(line), self.line_end(line)))
+    }
+
+    /// Byte offset of the char at column `cx` of row `line`, or of the end of the row if it's
+    /// shorter
+    pub fn offset(&self, line: usize, cx: usize) -> usize {
+        let start = self.line_start(line);
+        start + char_offset(&self.slice(start, self.line_end(line)), cx)
+    }
+
+    /// The text in a range, with line breaks as '\n'
+    pub fn text(&self, start: usize, end: usize) -> String {
+        let text = decode(&self.slice(start, end));
+        if self.crlf {
+            text.replace("\r\n", "\n")
+        } else {
+            text
+        }
+    }
+
+    /// Bytes with each '\n' that has no '\r' before it written the way the file's line breaks are
+    pub fn with_line_breaks(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(bytes.len());
+        for (i, &b) in bytes.iter().enumerate() {
+            if self.crlf && b == b'\n' && (i == 0 || bytes[i - 1] != b'\r') {
+                out.push(b'\r');
+            }
+            out.push(b);
+        }
+        out
+    }
+
+    /// Insert text at a byte offset, writing its line breaks the way the file does
+    pub fn insert(&mut self, offset: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.crlf && text.contains('\n') {
+            self.insert_bytes(offset, text.replace('\n', "\r\n").as_bytes());
+        } else {
+            self.insert_bytes(offset, text.as_bytes());
+        }
+    }
+
+    /// Insert bytes at a byte offset exactly as they are
+    pub fn insert_bytes(&mut self, offset: usize, text: &[u8]) {
+        let start = self.add.len();
+        self.add.extend_from_slice(text);
+        let added_newlines = newline_offsets(text, start).collect::<Vec<_>>();
+        self.newlines += added_newlines.len();
+        self.add_newlines.extend(added_newlines);
+        self.len += text.len();
+
+        let new_piece = Piece {
+            source: Source::Add,
+            start,
+            len: text.len(),
+        };
+
+        let (i, inner) = self.find_piece(offset);
+        if inner == 0 {
+            // typing appends to the add buffer right after the previous insert, so the piece
+            // before can usually be extended rather than adding another
+            if i > 0 {
+                let prev = &mut self.pieces[i - 1];
+                if prev.source == Source::Add && prev.start + prev.len == start {
+                    prev.len += text.len();
+                    return;
+                }
+            }
+            self.pieces.insert(i, new_piece);
+        } else {
+            let piece = self.pieces[i];
+            let left = Piece {
+                len: inner,
+                ..piece
+            };
+            let right = Piece {
+                start: piece.start + inner,
+                len: piece.len - inner,
+                ..piece
+            };
+            self.pieces.splice(i..=i, [left, new_piece, right]);
+        }
+    }
+
+    /// Delete `len` bytes starting at a byte offset
+    pub fn delete(&mut self, offset: usize, len: usize) {
+        let end = (offset + len).min(self.len);
+        if offset >= end {
+            return;
+        }
+
+        let mut pieces = Vec::with_capacity(self.pieces.len() + 1);
+        let mut piece_start = 0;
+        for piece in &self.pieces {
+            let piece_end = piece_start + piece.len;
+            if piece_end <= offset || piece_start >= end {
+                pieces.push(*piece);
+            } else {
+                // keep whatever lies outside the deleted range
+                if piece_start < offset {
+                    pieces.push(Piece {
+                        len: offset - piece_start,
+                        ..*piece
+                    });
+                }
+                if piece_end > end {
+                    let cut = end - piece_start;
+                    pieces.push(Piece {
+                        start: piece.start + cut,
+                        len: piece.len - cut,
+                        ..*piece
+                    });
+                }
+
+                let from = offset.max(piece_start) - piece_start;
+                let to = end.min(piece_end) - piece_start;
+                self.newlines -= self.bytes(piece)[from..to]
+                    .iter()
+                    .filter(|b| **b == b'\n')
+                    .count();
+            }
+            piece_start = piece_end;
+        }
+
+        self.pieces = pieces;
+        self.len -= end - offset;
+    }
+
+    /// Insert a whole row before row `line`, or after the last row
+    pub fn insert_row(&mut self, line: usize, text: &str) {
+        let offset = self.line_start(line);
+        if offset == self.len && self.len > 0 && self.byte_at(self.len - 1) != b'\n' {
+            // the last row has no line break to put this one after
+            self.insert(offset, &format!("\n{}", text));
+        } else {
+            self.insert(offset, &format!("{}\n", text));
+        }
+    }
+
+    /// Remove row `line` along with its line break
+    pub fn delete_row(&mut self, line: usize) {
+        let start = self.line_start(line);
+        let end = if line < self.newlines {
+            self.line_start(line + 1)
+        } else {
+            self.len
+        };
+        self.delete(start, end - start);
+    }
+
+    /// Stream the document to a writer
+    pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+        for piece in &self.pieces {
+            w.write_all(self.bytes(piece))?;
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.slice(0, self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(buffer: &TextBuffer) -> String {
+        String::from_utf8(buffer.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn lines_of_original() {
+        let buffer = TextBuffer::from_bytes(b"one\ntwo\n\nfour".to_vec());
+        assert_eq!(buffer.line_count(), 4);
+        assert_eq!(buffer.line(0), "one");
+        assert_eq!(buffer.line(1), "two");
+        assert_eq!(buffer.line(2), "");
+        assert_eq!(buffer.line(3), "four");
+
+        let buffer = TextBuffer::from_bytes(b"one\ntwo\n".to_vec());
+        assert_eq!(buffer.line_count(), 2);
+        assert_eq!(buffer.line_start(2), 8);
+        assert_eq!(TextBuffer::new().line_count(), 0);
+    }
+
+    #[test]
+    fn insert_and_delete() {
+        let mut buffer = TextBuffer::from_bytes(b"hello\nworld\n".to_vec());
+
+        buffer.insert(5, ",");
+        buffer.insert(6, " there");
+        assert_eq!(text(&buffer), "hello, there\nworld\n");
+
+        buffer.insert(0, "> ");
+        buffer.insert(buffer.line_start(1) + 5, "\nand more");
+        assert_eq!(text(&buffer), "> hello, there\nworld\nand more\n");
+        assert_eq!(buffer.line_count(), 3);
+        assert_eq!(buffer.line(2), "and more");
+
+        // delete across piece and line boundaries
+        buffer.delete(7, 14);
+        assert_eq!(text(&buffer), "> helloand more\n");
+        assert_eq!(buffer.line_count(), 1);
+        assert_eq!(buffer.line(0), "> helloand more");
+
+        buffer.delete(0, buffer.len());
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.line_count(), 0);
+    }
+
+    #[test]
+    fn consecutive_inserts_share_a_piece() {
+        let mut buffer = TextBuffer::from_bytes(b"ab".to_vec());
+        for (i, c) in "xyz".chars().enumerate() {
+            buffer.insert(1 + i, &c.to_string());
+        }
+        assert_eq!(text(&buffer), "axyzb");
+        assert_eq!(buffer.pieces.len(), 3);
+    }
+
+    #[test]
+    fn whole_rows() {
+        let mut buffer = TextBuffer::from_bytes(b"a\nb".to_vec());
+        buffer.insert_row(1, "x");
+        assert_eq!(text(&buffer), "a\nx\nb");
+        buffer.insert_row(3, "y");
+        assert_eq!(text(&buffer), "a\nx\nb\ny");
+        assert_eq!(buffer.line_count(), 4);
+
+        buffer.delete_row(1);
+        buffer.delete_row(2);
+        assert_eq!(text(&buffer), "a\nb\n");
+
+        let mut buffer = TextBuffer::new();
+        buffer.insert_row(0, "");
+        assert_eq!(buffer.line_count(), 1);
+        assert_eq!(text(&buffer), "\n");
+    }
+
+    #[test]
+    fn invalid_utf8_keeps_its_bytes() {
+        let mut buffer = TextBuffer::from_bytes(b"a\xff\xfeb\xc3\xa9c\n".to_vec());
+        assert_eq!(buffer.line(0), "a\u{fffd}\u{fffd}b\u{e9}c");
+        assert_eq!(buffer.offset(0, 3), 3);
+        assert_eq!(buffer.offset(0, 5), 6);
+        assert_eq!(buffer.offset(0, 9), 7);
+
+        buffer.insert(buffer.offset(0, 5), "x");
+        buffer.delete(buffer.offset(0, 0), 1);
+        assert_eq!(buffer.to_bytes(), b"\xff\xfeb\xc3\xa9xc\n");
+    }
+
+    #[test]
+    fn crlf_line_breaks() {
+        let mut buffer = TextBuffer::from_bytes(b"one\r\ntwo\r\n".to_vec());
+        assert_eq!(buffer.line(0), "one");
+        assert_eq!(buffer.line_end(0), 3);
+        assert_eq!(buffer.text(0, buffer.len()), "one\ntwo\n");
+
+        buffer.insert(buffer.offset(0, 2), "\n");
+        buffer.insert_row(3, "three");
+        assert_eq!(buffer.to_bytes(), b"on\r\ne\r\ntwo\r\nthree\r\n");
+        assert_eq!(buffer.line_count(), 4);
+        assert_eq!(buffer.line(1), "e");
+
+        assert_eq!(buffer.with_line_breaks(b"a\nb\r\n"), b"a\r\nb\r\n");
+        let buffer = TextBuffer::from_bytes(b"one\n".to_vec());
+        assert_eq!(buffer.with_line_breaks(b"a\nb\r\n"), b"a\nb\r\n");
+    }
+
+    #[test]
+    fn write_streams_pieces() {
+        let mut buffer = TextBuffer::from_bytes(b"a\nb\n".to_vec());
+        buffer.insert(2, "c\n");
+        let mut out = vec![];
+        buffer.write_to(&mut out).unwrap();
+        assert_eq!(out, b"a\nc\nb\n");
+    }
+}
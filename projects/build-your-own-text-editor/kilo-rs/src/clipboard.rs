@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// Recently cut or copied text, newest first, as in Emacs. Yanking inserts the newest entry and
+/// each yank-pop after it swaps in the next older one, cycling back round to the newest.
+#[derive(Debug)]
+pub struct KillRing {
+    entries: VecDeque<String>,
+    capacity: usize,
+    // index of the entry last yanked
+    yank: usize,
+}
+
+impl KillRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            yank: 0,
+        }
+    }
+
+    /// Add a new entry, dropping the oldest if the ring is full
+    pub fn push(&mut self, text: String) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(text);
+        self.yank = 0;
+    }
+
+    /// Add to the newest entry, as consecutive kills build up a single entry
+    pub fn append(&mut self, text: &str) {
+        match self.entries.front_mut() {
+            Some(entry) => entry.push_str(text),
+            None => self.push(text.to_string()),
+        }
+        self.yank = 0;
+    }
+
+    /// The newest entry
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank = 0;
+        self.entries.front().map(String::as_str)
+    }
+
+    /// The entry before the one last yanked
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank = (self.yank + 1) % self.entries.len();
+        self.entries.get(self.yank).map(String::as_str)
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The OSC 52 escape sequence asking the terminal to put text on the system clipboard
+pub fn osc52_copy(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yank_and_pop() {
+        let mut ring = KillRing::new(3);
+        assert_eq!(ring.yank(), None);
+        assert_eq!(ring.yank_pop(), None);
+
+        for text in ["a", "b", "c", "d"] {
+            ring.push(text.to_string());
+        }
+        assert_eq!(ring.yank(), Some("d"));
+        assert_eq!(ring.yank_pop(), Some("c"));
+        assert_eq!(ring.yank_pop(), Some("b"));
+        // "a" fell off the end, so popping cycles back to the newest
+        assert_eq!(ring.yank_pop(), Some("d"));
+
+        ring.append("e");
+        assert_eq!(ring.yank(), Some("de"));
+    }
+
+    #[test]
+    fn osc52() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(osc52_copy("hi\n"), "\x1b]52;c;aGkK\x07");
+    }
+}
@@ -0,0 +1,185 @@
+//! Commands typed at the `:` prompt:
+//!
+//! - `goto <line>`, or just the line number
+//! - `set number`, `set wrap`, `set expandtab`, `set autoindent` and their `no` forms, and
+//!   `set tabstop <n>` or `set tabstop=<n>`
+//! - `w [file]` to save, to another file if one is given
+//! - `e <file>` to open a file
+//! - `!<command>` to pipe the selected rows, or the whole buffer, through a shell command and
+//!   replace them with its output
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Move to a line, counting from 1
+    Goto(usize),
+    Set(Setting),
+    Write(Option<String>),
+    Edit(String),
+    /// Replace text with the output of a shell command given it as input
+    Filter(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    /// Show line numbers in a gutter
+    Number(bool),
+    /// Wrap long rows onto further screen lines rather than scrolling sideways
+    Wrap(bool),
+    TabStop(usize),
+    /// Insert spaces up to the next tab stop for the tab key
+    ExpandTab(bool),
+    /// Start new lines with the indentation of the line they're broken from
+    AutoIndent(bool),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+fn error<T>(message: String) -> Result<T, Error> {
+    Err(Error { message })
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, Error> {
+        let line = line.trim();
+        if let Some(command) = line.strip_prefix('!') {
+            return match command.trim() {
+                "" => error("Usage: !<command>".to_string()),
+                command => Ok(Command::Filter(command.to_string())),
+            };
+        }
+        let (name, arg) = match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (line, None),
+        };
+
+        match (name, arg) {
+            (_, None) if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) => {
+                parse_line_number(name)
+            }
+            ("goto" | "g", Some(line)) => parse_line_number(line),
+            ("goto" | "g", None) => error("Usage: goto <line>".to_string()),
+            ("set", Some(setting)) => Setting::parse(setting).map(Command::Set),
+            ("set", None) => error("Usage: set <option>".to_string()),
+            ("w" | "write", file) => Ok(Command::Write(file.map(str::to_string))),
+            ("e" | "edit", Some(file)) => Ok(Command::Edit(file.to_string())),
+            ("e" | "edit", None) => error("Usage: e <file>".to_string()),
+            _ => error(format!("Unknown command: {}", name)),
+        }
+    }
+}
+
+fn parse_line_number(line: &str) -> Result<Command, Error> {
+    match line.parse::<usize>() {
+        Ok(line) if line > 0 => Ok(Command::Goto(line)),
+        _ => error(format!("Not a line number: {}", line)),
+    }
+}
+
+pub fn parse_tab_stop(value: &str) -> Result<usize, Error> {
+    match value.parse::<usize>() {
+        Ok(n) if (1..=32).contains(&n) => Ok(n),
+        _ => error(format!("tabstop must be from 1 to 32, not {}", value)),
+    }
+}
+
+impl Setting {
+    /// Parse `name`, `noname`, `name=value` or `name value`
+    pub fn parse(setting: &str) -> Result<Setting, Error> {
+        let (name, value) = match setting.split_once(|c: char| c == '=' || c.is_whitespace()) {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (setting.trim(), None),
+        };
+        // only options that are on or off have a `no` form
+        let (name, on) =
+            match name.strip_prefix("no") {
+                Some(
+                    flag @ ("number" | "nu" | "wrap" | "expandtab" | "et" | "autoindent" | "ai"),
+                ) if value.is_none() => (flag, false),
+                _ => (name, true),
+            };
+
+        match (name, value) {
+            ("number" | "nu", None) => Ok(Setting::Number(on)),
+            ("wrap", None) => Ok(Setting::Wrap(on)),
+            ("expandtab" | "et", None) => Ok(Setting::ExpandTab(on)),
+            ("autoindent" | "ai", None) => Ok(Setting::AutoIndent(on)),
+            ("tabstop" | "ts", Some(value)) => parse_tab_stop(value).map(Setting::TabStop),
+            ("tabstop" | "ts", None) => error("Usage: set tabstop <n>".to_string()),
+            ("number" | "nu" | "wrap" | "expandtab" | "et" | "autoindent" | "ai", Some(_)) => {
+                error(format!("{} is set on or off, without a value", name))
+            }
+            _ => error(format!("Unknown option: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(Command::parse("goto 12"), Ok(Command::Goto(12)));
+        assert_eq!(Command::parse(" 7 "), Ok(Command::Goto(7)));
+        assert_eq!(
+            Command::parse("set nowrap"),
+            Ok(Command::Set(Setting::Wrap(false)))
+        );
+        assert_eq!(Command::parse("w"), Ok(Command::Write(None)));
+        assert_eq!(
+            Command::parse("w  my file.txt "),
+            Ok(Command::Write(Some("my file.txt".to_string())))
+        );
+        assert_eq!(
+            Command::parse("e src/main.rs"),
+            Ok(Command::Edit("src/main.rs".to_string()))
+        );
+        assert_eq!(
+            Command::parse("! sort -u | head"),
+            Ok(Command::Filter("sort -u | head".to_string()))
+        );
+
+        let message = |line| Command::parse(line).unwrap_err().message;
+        assert_eq!(message("goto 0"), "Not a line number: 0");
+        assert_eq!(message("goto"), "Usage: goto <line>");
+        assert_eq!(message("e"), "Usage: e <file>");
+        assert_eq!(message("!  "), "Usage: !<command>");
+        assert_eq!(message("frobnicate now"), "Unknown command: frobnicate");
+        assert_eq!(message(""), "Unknown command: ");
+    }
+
+    #[test]
+    fn settings() {
+        assert_eq!(Setting::parse("number"), Ok(Setting::Number(true)));
+        assert_eq!(Setting::parse("nonu"), Ok(Setting::Number(false)));
+        assert_eq!(Setting::parse("wrap"), Ok(Setting::Wrap(true)));
+        assert_eq!(Setting::parse("et"), Ok(Setting::ExpandTab(true)));
+        assert_eq!(
+            Setting::parse("noautoindent"),
+            Ok(Setting::AutoIndent(false))
+        );
+        assert_eq!(Setting::parse("tabstop 4"), Ok(Setting::TabStop(4)));
+        assert_eq!(Setting::parse("ts=2"), Ok(Setting::TabStop(2)));
+
+        let message = |setting| Setting::parse(setting).unwrap_err().message;
+        assert_eq!(message("tabstop=0"), "tabstop must be from 1 to 32, not 0");
+        assert_eq!(message("tabstop"), "Usage: set tabstop <n>");
+        assert_eq!(
+            message("wrap=yes"),
+            "wrap is set on or off, without a value"
+        );
+        assert_eq!(message("notabstop"), "Unknown option: notabstop");
+        assert_eq!(message("colors"), "Unknown option: colors");
+    }
+}
@@ -0,0 +1,308 @@
+//! User configuration, read from `~/.kilorc` at startup. Each line sets an option or binds a key
+//! to an action, and `#` starts a comment:
+//!
+//! ```text
+//! tabstop = 4
+//! number = true
+//! bind ctrl-g = command
+//! bind alt-x = cut
+//! ```
+//!
+//! The options are `tabstop`, `expandtab`, `autoindent`, `number`, `wrap`, `quit_times`, `osc52`
+//! and `backup`. Keys are
+//! named as for [`EditorKey::parse`], and bindings are added to the defaults, replacing any for
+//! the same key. Keys without a binding type themselves.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::command::parse_tab_stop;
+use crate::key::{ArrowDirection, EditorKey, PageDirection};
+
+/// Something a key can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Save,
+    /// Open the `:` command prompt
+    Command,
+    Find,
+    Replace,
+    Undo,
+    Redo,
+    SetMark,
+    ClearMark,
+    Copy,
+    Cut,
+    Paste,
+    YankPop,
+    SwitchBuffer,
+    NextBuffer,
+    /// Wait for a window command
+    Window,
+    Redraw,
+    NewLine,
+    DeleteBack,
+    DeleteForward,
+    Move(ArrowDirection),
+    /// Move, extending the selection
+    Select(ArrowDirection),
+    Page(PageDirection),
+    LineStart,
+    LineEnd,
+    /// Insert a tab, or spaces to the next tab stop
+    InsertTab,
+    /// Move to the bracket matching the one at the cursor
+    MatchBracket,
+}
+
+const ACTIONS: &[(&str, Action)] = &[
+    ("quit", Action::Quit),
+    ("save", Action::Save),
+    ("command", Action::Command),
+    ("find", Action::Find),
+    ("replace", Action::Replace),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("set-mark", Action::SetMark),
+    ("clear-mark", Action::ClearMark),
+    ("copy", Action::Copy),
+    ("cut", Action::Cut),
+    ("paste", Action::Paste),
+    ("yank-pop", Action::YankPop),
+    ("switch-buffer", Action::SwitchBuffer),
+    ("next-buffer", Action::NextBuffer),
+    ("window", Action::Window),
+    ("redraw", Action::Redraw),
+    ("newline", Action::NewLine),
+    ("delete-back", Action::DeleteBack),
+    ("delete-forward", Action::DeleteForward),
+    ("left", Action::Move(ArrowDirection::Left)),
+    ("right", Action::Move(ArrowDirection::Right)),
+    ("up", Action::Move(ArrowDirection::Up)),
+    ("down", Action::Move(ArrowDirection::Down)),
+    ("select-left", Action::Select(ArrowDirection::Left)),
+    ("select-right", Action::Select(ArrowDirection::Right)),
+    ("select-up", Action::Select(ArrowDirection::Up)),
+    ("select-down", Action::Select(ArrowDirection::Down)),
+    ("page-up", Action::Page(PageDirection::Up)),
+    ("page-down", Action::Page(PageDirection::Down)),
+    ("line-start", Action::LineStart),
+    ("line-end", Action::LineEnd),
+    ("insert-tab", Action::InsertTab),
+    ("match-bracket", Action::MatchBracket),
+];
+
+/// The bindings the editor starts with
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("ctrl-q", "quit"),
+    ("ctrl-s", "save"),
+    ("ctrl-e", "command"),
+    ("ctrl-f", "find"),
+    ("ctrl-r", "replace"),
+    ("ctrl-z", "undo"),
+    ("ctrl-y", "redo"),
+    ("ctrl-space", "set-mark"),
+    ("escape", "clear-mark"),
+    ("ctrl-c", "copy"),
+    ("ctrl-x", "cut"),
+    ("ctrl-v", "paste"),
+    ("alt-y", "yank-pop"),
+    ("ctrl-b", "switch-buffer"),
+    ("ctrl-n", "next-buffer"),
+    ("ctrl-w", "window"),
+    ("ctrl-l", "redraw"),
+    ("enter", "newline"),
+    ("backspace", "delete-back"),
+    ("delete", "delete-forward"),
+    ("left", "left"),
+    ("right", "right"),
+    ("up", "up"),
+    ("down", "down"),
+    ("shift-left", "select-left"),
+    ("shift-right", "select-right"),
+    ("shift-up", "select-up"),
+    ("shift-down", "select-down"),
+    ("pageup", "page-up"),
+    ("pagedown", "page-down"),
+    ("home", "line-start"),
+    ("end", "line-end"),
+    ("tab", "insert-tab"),
+    ("ctrl-]", "match-bracket"),
+];
+
+impl Action {
+    pub fn parse(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .find(|(action, _)| *action == name)
+            .map(|&(_, action)| action)
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// Tab stop of each buffer opened
+    pub tab_stop: usize,
+    /// Whether the tab key inserts spaces
+    pub expand_tab: bool,
+    /// Whether new lines start with the indentation of the line before
+    pub auto_indent: bool,
+    /// Whether windows show line numbers
+    pub number: bool,
+    /// Whether windows wrap long rows
+    pub wrap: bool,
+    /// How many more times quit must be pressed to lose unsaved changes
+    pub quit_times: usize,
+    /// Copy kills to the terminal's clipboard with OSC 52
+    pub osc52: bool,
+    /// Keep the previous contents of a file as `name~` when saving
+    pub backup: bool,
+    pub keymap: HashMap<EditorKey, Action>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let keymap = DEFAULT_BINDINGS
+            .iter()
+            .map(|&(key, action)| {
+                let key = EditorKey::parse(key).expect("default keys have valid names");
+                let action = Action::parse(action).expect("default actions exist");
+                (key, action)
+            })
+            .collect();
+
+        Self {
+            tab_stop: 8,
+            expand_tab: false,
+            auto_indent: true,
+            number: false,
+            wrap: false,
+            quit_times: 3,
+            osc52: false,
+            backup: false,
+            keymap,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    /// The line of the file with the problem, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("{} must be true or false, not {}", name, value)),
+    }
+}
+
+impl Config {
+    /// Apply the lines of a configuration file, stopping at the first that isn't valid
+    pub fn apply(&mut self, text: &str) -> Result<(), Error> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.apply_line(line).map_err(|message| Error {
+                line: i + 1,
+                message,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_line(&mut self, line: &str) -> Result<(), String> {
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!("expected `option = value`, found {}", line));
+        };
+        let (name, value) = (name.trim(), value.trim());
+
+        if let Some(key) = name.strip_prefix("bind ") {
+            let key = key.trim();
+            let key = EditorKey::parse(key).ok_or(format!("unknown key: {}", key))?;
+            let action = Action::parse(value).ok_or(format!("unknown action: {}", value))?;
+            self.keymap.insert(key, action);
+            return Ok(());
+        }
+
+        match name {
+            "tabstop" => self.tab_stop = parse_tab_stop(value).map_err(|e| e.message)?,
+            "expandtab" => self.expand_tab = parse_bool(name, value)?,
+            "autoindent" => self.auto_indent = parse_bool(name, value)?,
+            "number" => self.number = parse_bool(name, value)?,
+            "wrap" => self.wrap = parse_bool(name, value)?,
+            "quit_times" => {
+                self.quit_times = value
+                    .parse()
+                    .map_err(|_| format!("quit_times must be a number, not {}", value))?;
+            }
+            "osc52" => self.osc52 = parse_bool(name, value)?,
+            "backup" => self.backup = parse_bool(name, value)?,
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::Modifiers;
+
+    #[test]
+    fn defaults() {
+        let config = Config::default();
+        assert_eq!(config.keymap.len(), DEFAULT_BINDINGS.len());
+        assert_eq!(config.keymap[&EditorKey::Char('\x11')], Action::Quit);
+        assert_eq!(
+            config.keymap[&EditorKey::Arrow(ArrowDirection::Left, Modifiers::SHIFT)],
+            Action::Select(ArrowDirection::Left)
+        );
+        // every action can be bound by default
+        for (name, action) in ACTIONS {
+            assert!(config.keymap.values().any(|a| a == action), "{}", name);
+        }
+    }
+
+    #[test]
+    fn apply() {
+        let mut config = Config::default();
+        let text = "# settings\n\ntabstop = 4\nnumber=true  # with a gutter\nbind ctrl-g = command\nbind ctrl-q = save\n";
+        config.apply(text).unwrap();
+        assert_eq!(config.tab_stop, 4);
+        assert!(config.number);
+        assert_eq!(config.keymap[&EditorKey::Char('\x07')], Action::Command);
+        assert_eq!(config.keymap[&EditorKey::Char('\x11')], Action::Save);
+
+        let error = |text| Config::default().apply(text).unwrap_err().to_string();
+        assert_eq!(
+            error("wrap = true\nwrap"),
+            "line 2: expected `option = value`, found wrap"
+        );
+        assert_eq!(error("colour = red"), "line 1: unknown option: colour");
+        assert_eq!(
+            error("number = 1"),
+            "line 1: number must be true or false, not 1"
+        );
+        assert_eq!(
+            error("tabstop = 99"),
+            "line 1: tabstop must be from 1 to 32, not 99"
+        );
+        assert_eq!(error("bind ctrl-1 = quit"), "line 1: unknown key: ctrl-1");
+        assert_eq!(error("bind ctrl-g = fnd"), "line 1: unknown action: fnd");
+    }
+}
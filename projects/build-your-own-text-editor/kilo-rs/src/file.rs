@@ -1,18 +1,137 @@
-use std::{
-    fs::File,
-    os::{fd::AsRawFd, raw::c_int},
-};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
-extern "C" {
-    pub fn ftruncate(fd: c_int, length: i64) -> c_int;
+/// A file beside `path`, named from its file name
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(prefix);
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
 }
 
-pub fn truncate_file(cmd: &mut File, length: usize) -> Result<(), std::io::Error> {
-    let res = unsafe { ftruncate(cmd.as_raw_fd(), length as i64) };
+/// Where unsaved changes to `path` are kept: `.name.swp` in the same directory
+pub fn swap_path(path: &Path) -> PathBuf {
+    sibling(path, ".", ".swp")
+}
+
+/// Where the previous contents of `path` are kept when saving with a backup: `name~`
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, "", "~")
+}
+
+/// Replace the contents of `path` with what `write` writes, so that a crash or full disk at any
+/// point leaves either the old file or the new one. The contents go to a temporary file in the
+/// same directory, which is synced and renamed over the target. The file gets the permissions in
+/// `mode` if given, or else keeps the target's, or else a new file's default. With `backup`, the
+/// old file is kept at `backup_path`.
+pub fn write_atomic<F>(path: &Path, backup: bool, mode: Option<u32>, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    // replace what a symlink points at rather than the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let existing = fs::metadata(&path).ok();
+    let mode = mode.or(existing
+        .as_ref()
+        .map(|metadata| metadata.permissions().mode()));
+
+    // created with the permissions it ends up with, so the contents are never readable by anyone
+    // the file wouldn't let read them
+    let temp_path = sibling(&path, ".", &format!(".{}.tmp", std::process::id()));
+    let mut temp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode.unwrap_or(0o666))
+        .open(&temp_path)?;
+    let result = (|| {
+        write(&mut temp)?;
+        // the umask may have taken bits away
+        if let Some(mode) = mode {
+            temp.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        temp.sync_all()?;
+
+        if backup && existing.is_some() {
+            let backup_path = backup_path(&path);
+            _ = fs::remove_file(&backup_path);
+            // the rename leaves the old contents to the link
+            fs::hard_link(&path, &backup_path)
+                .or_else(|_| fs::copy(&path, &backup_path).map(|_| ()))?;
+        }
+
+        fs::rename(&temp_path, &path)
+    })();
+    if result.is_err() {
+        _ = fs::remove_file(&temp_path);
+        return result;
+    }
+
+    // make the rename itself durable
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kilo-{}-{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn names() {
+        let path = Path::new("src/main.rs");
+        assert_eq!(swap_path(path), Path::new("src/.main.rs.swp"));
+        assert_eq!(backup_path(path), Path::new("src/main.rs~"));
+    }
+
+    #[test]
+    fn atomic_write() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("file.txt");
+
+        write_atomic(&path, true, None, |f| f.write_all(b"one")).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"one");
+        // nothing to back up the first time
+        assert!(!backup_path(&path).exists());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+        write_atomic(&path, true, None, |f| f.write_all(b"two")).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"two");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"one");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+
+        // a failed write leaves the file alone and cleans up after itself
+        let result = write_atomic(&path, false, None, |f| {
+            f.write_all(b"partial")?;
+            Err(io::Error::other("disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"two");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // a mode overrides the target's permissions
+        write_atomic(&path, false, Some(0o600), |f| {
+            let mode = f.metadata()?.permissions().mode();
+            assert_eq!(mode & 0o077, 0);
+            f.write_all(b"three")
+        })
+        .unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
 
-    if res != 0 {
-        Err(std::io::Error::last_os_error())
-    } else {
-        Ok(())
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,282 @@
+/// A cursor position in file coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub cx: usize,
+    pub cy: usize,
+}
+
+impl Position {
+    pub fn new(cx: usize, cy: usize) -> Self {
+        Self { cx, cy }
+    }
+
+    /// Where text inserted at this position ends
+    pub fn after(self, text: &str) -> Position {
+        match text.rfind('\n') {
+            Some(i) => Position::new(
+                text[i + 1..].chars().count(),
+                self.cy + text.matches('\n').count(),
+            ),
+            None => Position::new(self.cx + text.chars().count(), self.cy),
+        }
+    }
+}
+
+/// A reversible change to the rows of a file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Insert text, which may contain newlines, at a position within an existing row
+    Insert { at: Position, text: String },
+    /// Delete text, which may span rows, starting at a position
+    Delete { at: Position, text: String },
+    /// Insert a whole row before row `at`
+    InsertRow { at: usize, text: String },
+    /// Remove row `at`, whose contents were `text`
+    DeleteRow { at: usize, text: String },
+    /// Insert bytes exactly as they are, such as rows a command wrote, at the start of row `at`
+    InsertBytes { at: usize, bytes: Vec<u8> },
+    /// Delete bytes starting at the start of row `at`, which were `bytes`
+    DeleteBytes { at: usize, bytes: Vec<u8> },
+}
+
+impl Edit {
+    /// The edit that reverses this one
+    pub fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::Insert { at, text } => Edit::Delete { at, text },
+            Edit::Delete { at, text } => Edit::Insert { at, text },
+            Edit::InsertRow { at, text } => Edit::DeleteRow { at, text },
+            Edit::DeleteRow { at, text } => Edit::InsertRow { at, text },
+            Edit::InsertBytes { at, bytes } => Edit::DeleteBytes { at, bytes },
+            Edit::DeleteBytes { at, bytes } => Edit::InsertBytes { at, bytes },
+        }
+    }
+
+    /// Where the cursor belongs once this edit has been applied
+    pub fn end(&self) -> Position {
+        match self {
+            Edit::Insert { at, text } => at.after(text),
+            Edit::Delete { at, .. } => *at,
+            Edit::InsertRow { at, .. }
+            | Edit::DeleteRow { at, .. }
+            | Edit::InsertBytes { at, .. }
+            | Edit::DeleteBytes { at, .. } => Position::new(0, *at),
+        }
+    }
+}
+
+/// Edits that are undone and redone together
+#[derive(Debug)]
+struct UndoGroup {
+    edits: Vec<Edit>,
+    cursor_before: Position,
+}
+
+/// Undo and redo stacks of edit groups, tracking which point in the history was last saved
+#[derive(Debug)]
+pub struct History {
+    undo: Vec<UndoGroup>,
+    redo: Vec<UndoGroup>,
+    // whether the next edit joins the latest group, such as when typing consecutive characters
+    group_open: bool,
+    // the undo stack depth that matches the file on disk, if it's still reachable
+    saved: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            group_open: false,
+            saved: Some(0),
+        }
+    }
+
+    /// Record an edit that has just been applied, with the cursor position from before it
+    pub fn record(&mut self, edit: Edit, cursor_before: Position) {
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            // the saved state is only reachable by redo, which a new edit discards
+            self.saved = None;
+        }
+        self.redo.clear();
+
+        // an edit that reverses the latest unsaved one in the open group, like deleting a character
+        // just typed, cancels it rather than adding a step
+        let depth = self.undo.len();
+        let unsaved = self.saved.is_none_or(|saved| saved < depth);
+        if let Some(group) = self.undo.last_mut().filter(|_| self.group_open && unsaved) {
+            if group.edits.last() == Some(&edit.inverse()) {
+                group.edits.pop();
+                if group.edits.is_empty() {
+                    self.undo.pop();
+                    self.group_open = false;
+                }
+                return;
+            }
+        }
+
+        match self.undo.last_mut() {
+            Some(group) if self.group_open => group.edits.push(edit),
+            _ => {
+                self.undo.push(UndoGroup {
+                    edits: vec![edit],
+                    cursor_before,
+                });
+                self.group_open = true;
+            }
+        }
+    }
+
+    /// Make the next edit start a new undo step
+    pub fn break_group(&mut self) {
+        self.group_open = false;
+    }
+
+    /// Pop the latest undo step, returning the edits that reverse it, in order, and the cursor
+    /// position to restore
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        self.group_open = false;
+        let group = self.undo.pop()?;
+        let edits = group.edits.iter().rev().map(Edit::inverse).collect();
+        let cursor = group.cursor_before;
+        self.redo.push(group);
+        Some((edits, cursor))
+    }
+
+    /// Pop the latest redo step, returning its edits and the cursor position after them
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        self.group_open = false;
+        let group = self.redo.pop()?;
+        let edits = group.edits.clone();
+        let cursor = edits.last().map_or(group.cursor_before, Edit::end);
+        self.undo.push(group);
+        Some((edits, cursor))
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+        self.group_open = false;
+    }
+
+    /// Forget the saved state, for a buffer that never matched the file on disk
+    pub fn mark_unsaved(&mut self) {
+        self.saved = None;
+    }
+
+    /// Whether the file differs from what was last saved
+    pub fn is_modified(&self) -> bool {
+        self.saved != Some(self.undo.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(cx: usize, cy: usize, text: &str) -> Edit {
+        Edit::Insert {
+            at: Position::new(cx, cy),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn grouped_edits_undo_together() {
+        let mut history = History::new();
+        history.record(insert(0, 0, "a"), Position::new(0, 0));
+        history.record(insert(1, 0, "b"), Position::new(1, 0));
+        history.break_group();
+        history.record(insert(2, 0, "\n"), Position::new(2, 0));
+
+        let (edits, cursor) = history.undo().unwrap();
+        assert_eq!(edits, vec![insert(2, 0, "\n").inverse()]);
+        assert_eq!(cursor, Position::new(2, 0));
+
+        let (edits, cursor) = history.undo().unwrap();
+        assert_eq!(
+            edits,
+            vec![insert(1, 0, "b").inverse(), insert(0, 0, "a").inverse()]
+        );
+        assert_eq!(cursor, Position::new(0, 0));
+        assert!(history.undo().is_none());
+
+        let (edits, cursor) = history.redo().unwrap();
+        assert_eq!(edits, vec![insert(0, 0, "a"), insert(1, 0, "b")]);
+        assert_eq!(cursor, Position::new(2, 0));
+    }
+
+    #[test]
+    fn modified_relative_to_save() {
+        let mut history = History::new();
+        assert!(!history.is_modified());
+
+        history.record(insert(0, 0, "a"), Position::new(0, 0));
+        assert!(history.is_modified());
+        history.mark_saved();
+        assert!(!history.is_modified());
+
+        // typing after a save starts a new step
+        history.record(insert(1, 0, "b"), Position::new(1, 0));
+        assert!(history.is_modified());
+        history.undo();
+        assert!(!history.is_modified());
+        history.undo();
+        assert!(history.is_modified());
+        history.redo();
+        assert!(!history.is_modified());
+
+        // a new edit discards the redo stack, and with it the saved state
+        history.undo();
+        history.record(insert(0, 0, "c"), Position::new(0, 0));
+        assert!(history.is_modified());
+        history.undo();
+        assert!(history.is_modified());
+    }
+
+    #[test]
+    fn reversed_edit_cancels() {
+        let mut history = History::new();
+        history.record(insert(0, 0, "a"), Position::new(0, 0));
+        history.record(insert(1, 0, "b"), Position::new(1, 0));
+        history.record(insert(1, 0, "b").inverse(), Position::new(2, 0));
+        assert!(history.is_modified());
+        history.record(insert(0, 0, "a").inverse(), Position::new(1, 0));
+        assert!(!history.is_modified());
+        assert!(history.undo().is_none());
+
+        // once the group is closed, such as by moving the cursor, the edit is a step of its own
+        history.record(insert(0, 0, "a"), Position::new(0, 0));
+        history.record(insert(1, 0, "b"), Position::new(1, 0));
+        history.break_group();
+        history.record(insert(1, 0, "b").inverse(), Position::new(2, 0));
+        let (edits, _) = history.undo().unwrap();
+        assert_eq!(edits, vec![insert(1, 0, "b")]);
+        let (edits, _) = history.undo().unwrap();
+        assert_eq!(
+            edits,
+            vec![insert(1, 0, "b").inverse(), insert(0, 0, "a").inverse()]
+        );
+        assert!(history.undo().is_none());
+
+        // an edit at or below the saved point is a real change
+        history.record(insert(0, 0, "a"), Position::new(0, 0));
+        history.mark_saved();
+        history.record(insert(0, 0, "a").inverse(), Position::new(1, 0));
+        assert!(history.is_modified());
+        history.undo();
+        assert!(!history.is_modified());
+    }
+
+    #[test]
+    fn end_of_edit() {
+        assert_eq!(insert(3, 1, "ab").end(), Position::new(5, 1));
+        assert_eq!(insert(3, 1, "ab\ncd\ne").end(), Position::new(1, 3));
+    }
+}
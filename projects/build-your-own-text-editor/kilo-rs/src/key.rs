@@ -0,0 +1,492 @@
+//! Keys as the editor sees them, how they're decoded from what the terminal sends, and their
+//! names in `~/.kilorc`.
+//!
+//! Terminals send special keys as escape sequences, `ESC [ <code> ~` or `ESC [ <letter>`, with
+//! `;<modifiers>` after the code (or a code of 1) when shift, alt or ctrl is held. Mouse events
+//! come as `ESC [ < <buttons> ; <column> ; <row>` and `M` for a press or `m` for a release, once
+//! SGR mouse reporting has been turned on.
+
+use crate::terminal::Terminal;
+use crate::unicode::utf8_sequence_len;
+
+const ESCAPE: u8 = 0x1b;
+const BACKSPACE: u8 = 0x7f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EditorKey {
+    Backspace,
+    Arrow(ArrowDirection, Modifiers),
+    Page(PageDirection, Modifiers),
+    Home(Modifiers),
+    End(Modifiers),
+    Del(Modifiers),
+    /// A function key, F1 to F12
+    Function(u8, Modifiers),
+    Char(char),
+    /// A key pressed with alt held
+    Alt(char),
+    Mouse(Mouse),
+    /// An escape sequence the editor doesn't know
+    Unknown,
+    /// The terminal window changed size
+    Resize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArrowDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PageDirection {
+    Up,
+    Down,
+}
+
+/// The modifier keys held with a special key or mouse event. With a character, ctrl and alt
+/// are sent as a control character or an `EditorKey::Alt` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: false,
+    };
+    pub const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        alt: false,
+        ctrl: false,
+    };
+
+    /// The modifiers of a key's escape sequence, which are sent as 1 more than their bits
+    fn from_param(param: usize) -> Self {
+        let bits = param.saturating_sub(1);
+        Self {
+            shift: bits & 1 != 0,
+            alt: bits & 2 != 0,
+            ctrl: bits & 4 != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseAction {
+    Press(MouseButton),
+    /// The mouse moved with a button held
+    Drag(MouseButton),
+    /// A button was let go. Terminals don't say which.
+    Release,
+    ScrollUp,
+    ScrollDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mouse {
+    pub action: MouseAction,
+    /// The screen row, from 0
+    pub row: usize,
+    /// The screen column, from 0
+    pub col: usize,
+    pub modifiers: Modifiers,
+}
+
+pub const fn ctrl_key(c: char) -> char {
+    (c as u8 & 0x1f) as char
+}
+
+/// Wait for a key, a mouse event or the terminal changing size
+pub fn read_key(terminal: &mut dyn Terminal) -> EditorKey {
+    let byte = loop {
+        // a resize interrupts the read, so this is seen without waiting for a key
+        if terminal.take_resized() {
+            return EditorKey::Resize;
+        }
+        if let Some(byte) = terminal.read_byte() {
+            break byte;
+        }
+    };
+
+    match byte {
+        ESCAPE => read_escape(terminal),
+        BACKSPACE => EditorKey::Backspace,
+        _ if byte as char == ctrl_key('h') => EditorKey::Backspace,
+        _ if byte.is_ascii() => EditorKey::Char(byte as char),
+        _ => EditorKey::Char(read_utf8_char(terminal, byte)),
+    }
+}
+
+/// Decode what follows an escape. An escape with nothing after it is the escape key.
+fn read_escape(terminal: &mut dyn Terminal) -> EditorKey {
+    match terminal.read_byte() {
+        Some(b'[') => read_control_sequence(terminal),
+        // some terminals send these keys this way
+        Some(b'O') => match terminal.read_byte() {
+            Some(b'H') => EditorKey::Home(Modifiers::NONE),
+            Some(b'F') => EditorKey::End(Modifiers::NONE),
+            Some(b @ b'P'..=b'S') => EditorKey::Function(b - b'P' + 1, Modifiers::NONE),
+            Some(b) => arrow_direction(b).map_or(EditorKey::Unknown, |dir| {
+                EditorKey::Arrow(dir, Modifiers::NONE)
+            }),
+            None => EditorKey::Char(ESCAPE as char),
+        },
+        // holding alt sends escape before the key
+        Some(b) if b.is_ascii_graphic() => EditorKey::Alt(b as char),
+        _ => EditorKey::Char(ESCAPE as char),
+    }
+}
+
+/// Decode the rest of an `ESC [` sequence. All of it is read even if it isn't known, so none of
+/// it is taken for typing.
+fn read_control_sequence(terminal: &mut dyn Terminal) -> EditorKey {
+    let mut params = String::new();
+    let command = loop {
+        match terminal.read_byte() {
+            Some(b @ 0x30..=0x3f) => params.push(b as char),
+            Some(b @ 0x40..=0x7e) => break b,
+            _ => return EditorKey::Char(ESCAPE as char),
+        }
+    };
+    if let Some(params) = params.strip_prefix('<') {
+        return decode_mouse(params, command).map_or(EditorKey::Unknown, EditorKey::Mouse);
+    }
+
+    let params = params
+        .split(';')
+        .map(|param| param.parse::<usize>().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let modifiers = Modifiers::from_param(params.get(1).copied().unwrap_or(1));
+    match command {
+        b'~' => match params[0] {
+            1 | 7 => EditorKey::Home(modifiers),
+            4 | 8 => EditorKey::End(modifiers),
+            3 => EditorKey::Del(modifiers),
+            5 => EditorKey::Page(PageDirection::Up, modifiers),
+            6 => EditorKey::Page(PageDirection::Down, modifiers),
+            // the codes of F1 to F12 skip 16 and 22
+            n @ 11..=15 => EditorKey::Function(n as u8 - 10, modifiers),
+            n @ 17..=21 => EditorKey::Function(n as u8 - 11, modifiers),
+            n @ 23..=24 => EditorKey::Function(n as u8 - 12, modifiers),
+            _ => EditorKey::Unknown,
+        },
+        b'H' => EditorKey::Home(modifiers),
+        b'F' => EditorKey::End(modifiers),
+        b'P'..=b'S' => EditorKey::Function(command - b'P' + 1, modifiers),
+        _ => arrow_direction(command)
+            .map_or(EditorKey::Unknown, |dir| EditorKey::Arrow(dir, modifiers)),
+    }
+}
+
+/// Decode the parameters of an SGR mouse event, which ends in `M` for a press or `m` for a
+/// release
+fn decode_mouse(params: &str, command: u8) -> Option<Mouse> {
+    let mut params = params.split(';').map(|param| param.parse::<usize>().ok());
+    let (buttons, col, row) = (params.next()??, params.next()??, params.next()??);
+
+    let button = match buttons & 3 {
+        0 => Some(MouseButton::Left),
+        1 => Some(MouseButton::Middle),
+        2 => Some(MouseButton::Right),
+        _ => None,
+    };
+    let action = match command {
+        b'm' => MouseAction::Release,
+        // the wheel is sent as presses of buttons 4 and 5, and scrolling sideways as 6 and 7
+        b'M' if buttons & 64 != 0 => match buttons & 3 {
+            0 => MouseAction::ScrollUp,
+            1 => MouseAction::ScrollDown,
+            _ => return None,
+        },
+        b'M' if buttons & 32 != 0 => MouseAction::Drag(button?),
+        b'M' => MouseAction::Press(button?),
+        _ => return None,
+    };
+
+    Some(Mouse {
+        action,
+        row: row.checked_sub(1)?,
+        col: col.checked_sub(1)?,
+        modifiers: Modifiers {
+            shift: buttons & 4 != 0,
+            alt: buttons & 8 != 0,
+            ctrl: buttons & 16 != 0,
+        },
+    })
+}
+
+fn arrow_direction(b: u8) -> Option<ArrowDirection> {
+    match b {
+        b'A' => Some(ArrowDirection::Up),
+        b'B' => Some(ArrowDirection::Down),
+        b'C' => Some(ArrowDirection::Right),
+        b'D' => Some(ArrowDirection::Left),
+        _ => None,
+    }
+}
+
+/// Read the rest of a multi-byte UTF-8 sequence, decoding it to a char. Malformed input is
+/// replaced with U+FFFD.
+fn read_utf8_char(terminal: &mut dyn Terminal, first: u8) -> char {
+    let Some(len) = utf8_sequence_len(first) else {
+        return char::REPLACEMENT_CHARACTER;
+    };
+
+    let mut bytes = [first, 0, 0, 0];
+    for byte in &mut bytes[1..len] {
+        let Some(next) = terminal.read_byte() else {
+            return char::REPLACEMENT_CHARACTER;
+        };
+        *byte = next;
+    }
+
+    std::str::from_utf8(&bytes[..len])
+        .ok()
+        .and_then(|s| s.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// Parse the name of a key that can be held with any modifiers
+fn parse_special(name: &str, modifiers: Modifiers) -> Option<EditorKey> {
+    match name {
+        "left" => Some(EditorKey::Arrow(ArrowDirection::Left, modifiers)),
+        "right" => Some(EditorKey::Arrow(ArrowDirection::Right, modifiers)),
+        "up" => Some(EditorKey::Arrow(ArrowDirection::Up, modifiers)),
+        "down" => Some(EditorKey::Arrow(ArrowDirection::Down, modifiers)),
+        "pageup" => Some(EditorKey::Page(PageDirection::Up, modifiers)),
+        "pagedown" => Some(EditorKey::Page(PageDirection::Down, modifiers)),
+        "home" => Some(EditorKey::Home(modifiers)),
+        "end" => Some(EditorKey::End(modifiers)),
+        "delete" => Some(EditorKey::Del(modifiers)),
+        _ => name
+            .strip_prefix('f')
+            .and_then(|n| n.parse::<u8>().ok())
+            .filter(|n| (1..=12).contains(n))
+            .map(|n| EditorKey::Function(n, modifiers)),
+    }
+}
+
+impl EditorKey {
+    /// Parse the name of a key. The arrows `left`, `right`, `up` and `down`, and `pageup`,
+    /// `pagedown`, `home`, `end`, `delete` and `f1` to `f12` can be held with any of `shift-`,
+    /// `alt-` and `ctrl-`. Otherwise a key is a single character, `space`, `enter`, `tab`,
+    /// `escape` or `backspace`, with `alt-` and a printable character, or `ctrl-` and a letter,
+    /// one of `\ ] ^ _` or `space`.
+    pub fn parse(name: &str) -> Option<EditorKey> {
+        let mut name = name;
+        let mut modifiers = Modifiers::NONE;
+        loop {
+            let (held, rest) = if let Some(rest) = name.strip_prefix("shift-") {
+                (&mut modifiers.shift, rest)
+            } else if let Some(rest) = name.strip_prefix("alt-") {
+                (&mut modifiers.alt, rest)
+            } else if let Some(rest) = name.strip_prefix("ctrl-") {
+                (&mut modifiers.ctrl, rest)
+            } else {
+                break;
+            };
+            // `alt--` is alt and `-`, but `alt-` on its own isn't a key
+            if rest.is_empty() {
+                return None;
+            }
+            *held = true;
+            name = rest;
+        }
+
+        if let Some(key) = parse_special(name, modifiers) {
+            return Some(key);
+        }
+        let c = match name {
+            "space" => ' ',
+            "enter" => '\r',
+            "tab" => '\t',
+            "escape" => ESCAPE as char,
+            "backspace" => {
+                return (modifiers == Modifiers::NONE).then_some(EditorKey::Backspace);
+            }
+            _ => {
+                let mut chars = name.chars();
+                chars.next().filter(|_| chars.next().is_none())?
+            }
+        };
+
+        match modifiers {
+            Modifiers::NONE => Some(EditorKey::Char(c)),
+            Modifiers {
+                ctrl: true,
+                alt: false,
+                shift: false,
+            } => match c {
+                // the terminal sends the same as for backspace
+                'h' => Some(EditorKey::Backspace),
+                'a'..='z' | ' ' | '\\' | ']' | '^' | '_' => Some(EditorKey::Char(ctrl_key(c))),
+                _ => None,
+            },
+            Modifiers {
+                alt: true,
+                ctrl: false,
+                shift: false,
+            } => c.is_ascii_graphic().then_some(EditorKey::Alt(c)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::VirtualTerminal;
+
+    fn decode(input: &[u8]) -> Vec<EditorKey> {
+        let mut terminal = VirtualTerminal::new(1, 1);
+        terminal.send(input);
+        let mut keys = vec![];
+        while terminal.has_input() {
+            keys.push(read_key(&mut terminal));
+        }
+        keys
+    }
+
+    const CTRL: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: true,
+    };
+
+    #[test]
+    fn decodes_keys() {
+        assert_eq!(
+            decode(
+                b"a\x7f\x08\x1b[A\x1b[1;5C\x1b[3~\x1b[5;2~\x1bOQ\x1b[15~\x1b[24;5~\x1b[1;2H\x1bx"
+            ),
+            [
+                EditorKey::Char('a'),
+                EditorKey::Backspace,
+                EditorKey::Backspace,
+                EditorKey::Arrow(ArrowDirection::Up, Modifiers::NONE),
+                EditorKey::Arrow(ArrowDirection::Right, CTRL),
+                EditorKey::Del(Modifiers::NONE),
+                EditorKey::Page(PageDirection::Up, Modifiers::SHIFT),
+                EditorKey::Function(2, Modifiers::NONE),
+                EditorKey::Function(5, Modifiers::NONE),
+                EditorKey::Function(12, CTRL),
+                EditorKey::Home(Modifiers::SHIFT),
+                EditorKey::Alt('x'),
+            ]
+        );
+        assert_eq!(decode("é".as_bytes()), [EditorKey::Char('é')]);
+        // unknown sequences are read to the end, leaving nothing of them to be typed
+        assert_eq!(
+            decode(b"\x1b[?1;2c\x1b[99~z"),
+            [EditorKey::Unknown, EditorKey::Unknown, EditorKey::Char('z')]
+        );
+    }
+
+    #[test]
+    fn escape_on_its_own() {
+        let mut terminal = VirtualTerminal::new(1, 1);
+        terminal.send(b"\x1b");
+        assert_eq!(read_key(&mut terminal), EditorKey::Char('\x1b'));
+        terminal.send(b"\x1b[");
+        assert_eq!(read_key(&mut terminal), EditorKey::Char('\x1b'));
+    }
+
+    #[test]
+    fn decodes_mouse() {
+        let mouse = |action, row, col| {
+            EditorKey::Mouse(Mouse {
+                action,
+                row,
+                col,
+                modifiers: Modifiers::NONE,
+            })
+        };
+        assert_eq!(
+            decode(
+                b"\x1b[<0;5;2M\x1b[<32;6;2M\x1b[<0;6;2m\x1b[<64;1;1M\x1b[<65;1;1M\x1b[<2;10;20M"
+            ),
+            [
+                mouse(MouseAction::Press(MouseButton::Left), 1, 4),
+                mouse(MouseAction::Drag(MouseButton::Left), 1, 5),
+                mouse(MouseAction::Release, 1, 5),
+                mouse(MouseAction::ScrollUp, 0, 0),
+                mouse(MouseAction::ScrollDown, 0, 0),
+                mouse(MouseAction::Press(MouseButton::Right), 19, 9),
+            ]
+        );
+        assert_eq!(
+            decode(b"\x1b[<16;1;1M"),
+            [EditorKey::Mouse(Mouse {
+                action: MouseAction::Press(MouseButton::Left),
+                row: 0,
+                col: 0,
+                modifiers: CTRL,
+            })]
+        );
+        // scrolling sideways, and a position off the screen
+        assert_eq!(
+            decode(b"\x1b[<66;1;1M\x1b[<0;0;1M"),
+            [EditorKey::Unknown, EditorKey::Unknown]
+        );
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(EditorKey::parse("ctrl-q"), Some(EditorKey::Char('\x11')));
+        assert_eq!(EditorKey::parse("ctrl-space"), Some(EditorKey::Char('\0')));
+        assert_eq!(EditorKey::parse("ctrl-h"), Some(EditorKey::Backspace));
+        assert_eq!(EditorKey::parse("ctrl-]"), Some(EditorKey::Char('\x1d')));
+        assert_eq!(EditorKey::parse("alt-Y"), Some(EditorKey::Alt('Y')));
+        assert_eq!(EditorKey::parse("alt--"), Some(EditorKey::Alt('-')));
+        assert_eq!(
+            EditorKey::parse("shift-up"),
+            Some(EditorKey::Arrow(ArrowDirection::Up, Modifiers::SHIFT))
+        );
+        assert_eq!(
+            EditorKey::parse("ctrl-shift-home"),
+            Some(EditorKey::Home(Modifiers {
+                shift: true,
+                ..CTRL
+            }))
+        );
+        assert_eq!(
+            EditorKey::parse("pagedown"),
+            Some(EditorKey::Page(PageDirection::Down, Modifiers::NONE))
+        );
+        assert_eq!(
+            EditorKey::parse("f10"),
+            Some(EditorKey::Function(10, Modifiers::NONE))
+        );
+        assert_eq!(EditorKey::parse("enter"), Some(EditorKey::Char('\r')));
+        assert_eq!(EditorKey::parse("é"), Some(EditorKey::Char('é')));
+
+        for name in [
+            "ctrl-1",
+            "ctrl-[",
+            "ctrl-",
+            "alt-",
+            "alt- ",
+            "shift-a",
+            "ctrl-alt-x",
+            "shift-backspace",
+            "f13",
+            "",
+        ] {
+            assert_eq!(EditorKey::parse(name), None, "{}", name);
+        }
+    }
+}
@@ -1,4 +1,18 @@
+pub mod buffer;
+pub mod clipboard;
+pub mod command;
+pub mod config;
 pub mod file;
+pub mod history;
+pub mod key;
+#[cfg(test)]
+mod pty;
+pub mod regex;
+pub mod shell;
+pub mod signal;
 pub mod stdio;
+pub mod syntax;
+pub mod terminal;
 pub mod termios;
+pub mod unicode;
 pub mod window;
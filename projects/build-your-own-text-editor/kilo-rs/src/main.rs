@@ -1,52 +1,90 @@
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Read, Write};
-
-use kilo_rs::file::truncate_file;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Instant;
+
+use kilo_rs::buffer::TextBuffer;
+use kilo_rs::clipboard::{osc52_copy, KillRing};
+use kilo_rs::command::{Command, Setting};
+use kilo_rs::config::{Action, Config};
+use kilo_rs::file::{swap_path, write_atomic};
+use kilo_rs::history::{Edit, History, Position};
+use kilo_rs::key::{
+    ctrl_key, read_key, ArrowDirection, EditorKey, Mouse, MouseAction, MouseButton, PageDirection,
+};
+use kilo_rs::regex::{Match, Regex, CASE_INSENSITIVE, WHOLE_WORD};
+use kilo_rs::shell::pipe_through;
+use kilo_rs::signal::register_resize_handler;
 use kilo_rs::stdio::BufferedCommands;
+use kilo_rs::syntax::{highlight_row, select_syntax, Highlight, Syntax};
+use kilo_rs::terminal::{Terminal, Tty};
 use kilo_rs::termios::enable_raw_mode;
-use kilo_rs::window::get_window_size;
+use kilo_rs::unicode::{
+    grapheme_floor, grapheme_width, graphemes, next_grapheme_boundary, prev_grapheme_boundary,
+    str_width, truncate_to_width,
+};
 
 fn main() {
+    let settings = load_config().unwrap_or_else(|e| {
+        eprintln!("kilo: {}", e);
+        std::process::exit(1);
+    });
     enable_raw_mode().expect("failed to enable raw mode");
-    let mut config = EditorConfig::new().expect("failed to initialize editor config");
-
-    let args = std::env::args();
-    if args.len() > 1 {
-        let file_name = args.skip(1).next().expect("failed to get file name");
-        editor_open(file_name.as_str(), &mut config).expect("failed to open file");
-    }
+    register_resize_handler().expect("failed to handle window resizes");
+    let mut config =
+        EditorConfig::new(Box::new(Tty), settings).expect("failed to initialize editor config");
 
     set_status_message(
         &mut config,
-        "HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = find",
+        "HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-E = command | Ctrl-F = find | Ctrl-R = replace | Ctrl-Z = undo | Ctrl-Y = redo",
     );
 
+    // each file goes in its own buffer, with the first shown
+    for (i, file_name) in std::env::args().skip(1).enumerate() {
+        if i > 0 {
+            editor_new_buffer(&mut config);
+        }
+        editor_open(file_name.as_str(), &mut config).expect("failed to open file");
+    }
+    editor_show_buffer(&mut config, 0);
+
     loop {
         refresh_screen(&mut config).expect("failed to refresh screen");
         if process_keypress(&mut config) {
             break;
         }
+        editor_write_swaps(&mut config);
+    }
+    for buffer in &config.buffers {
+        editor_remove_swap(buffer);
     }
 }
 
 // region: defines
 
-const TAB_STOP: usize = 8;
-const KILO_QUIT_TIMES: usize = 3;
+/// Seconds between writes of unsaved changes to the swap file
+const KILO_SWAP_SECS: u64 = 4;
+const KILL_RING_SIZE: usize = 32;
+/// Rows searched either way for the bracket matching the one at the cursor
+const BRACKET_SCAN_ROWS: usize = 1000;
+/// Rows above the first one drawn that are highlighted to find out whether it starts inside a
+/// multi-line comment
+const COMMENT_SYNC_ROWS: usize = 1000;
+/// Rows scrolled by a turn of the mouse wheel
+const WHEEL_SCROLL_ROWS: usize = 3;
 
 const CR: char = '\r';
 const LF: char = '\n';
-const CTRL_F: char = ctrl_key('f');
-const CTRL_Q: char = ctrl_key('q');
-const CTRL_S: char = ctrl_key('s');
-const CTRL_L: char = ctrl_key('l');
+const CTRL_T: char = ctrl_key('t');
+const CTRL_W: char = ctrl_key('w');
 const ESCAPE: char = '\x1b';
-const CTRL_H: char = ctrl_key('h');
-const BACKSPACE: char = '\x7f';
 
+/// A row of the buffer decoded for display, built only for rows that are drawn or edited
 struct EditorRow {
     chars: Vec<char>,
     render: Vec<char>,
+    hl: Vec<Highlight>,
 }
 
 impl EditorRow {
@@ -54,78 +92,313 @@ impl EditorRow {
         Self {
             chars,
             render: vec![],
+            hl: vec![],
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum EditorKey {
-    Backspace,
-    Arrow(ArrowDirection),
-    Page(PageDirection),
-    Home,
-    End,
-    Del,
-    Char(char),
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum ArrowDirection {
-    Left,
-    Right,
-    Up,
-    Down,
+/// How a rendered char is drawn
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    hl: Highlight,
+    /// In reverse video, as for the selection and the bracket matching the one at the cursor
+    reverse: bool,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum PageDirection {
-    Up,
-    Down,
+/// State of the search prompt, kept between searches for the flags
+struct SearchState {
+    /// `CASE_INSENSITIVE` and `WHOLE_WORD`, toggled from the prompt
+    flags: u32,
+    /// The pattern being searched for, whose matches on screen are highlighted
+    regex: Option<Regex>,
+    /// Why the query isn't a valid pattern
+    error: Option<String>,
+    last_match: Option<Position>,
+    forward: bool,
+    /// Cursor position when the search started
+    origin: Position,
 }
 
 // endregion: defines
 
 // region: data
 
-struct EditorConfig {
+/// A file being edited, shown in any number of windows
+struct EditorBuffer {
+    text: TextBuffer,
+    // rendered rows by index, dropped when an edit above or at them changes the buffer
+    rows: HashMap<usize, EditorRow>,
+    // whether rows end inside an unclosed multi-line comment, for those highlighted so far
+    hl_open_comment: BTreeMap<usize, bool>,
+    // bumped by every change to the text, so that what's worked out from it can tell it's stale
+    version: u64,
+    dirty: bool,
+    history: History,
+    file_name: Option<String>,
+    syntax: Option<&'static Syntax>,
+    swap_time: Instant,
+    tab_stop: usize,
+    expand_tab: bool,
+    auto_indent: bool,
+}
+
+impl EditorBuffer {
+    fn new(settings: &Config) -> Self {
+        Self {
+            text: TextBuffer::new(),
+            rows: HashMap::new(),
+            hl_open_comment: BTreeMap::new(),
+            version: 0,
+            dirty: false,
+            history: History::new(),
+            file_name: None,
+            syntax: None,
+            swap_time: Instant::now(),
+            tab_stop: settings.tab_stop,
+            expand_tab: settings.expand_tab,
+            auto_indent: settings.auto_indent,
+        }
+    }
+}
+
+/// A view of a buffer in part of the screen, with its own cursor and scroll offsets
+#[derive(Clone)]
+struct EditorWindow {
+    /// Index of the buffer shown
+    buffer: usize,
     cx: usize,
     cy: usize,
     rx: usize,
     row_offset: usize,
+    /// Columns scrolled off the left, or when wrapping, the column the cursor's line starts at
     col_offset: usize,
-    rows: Vec<EditorRow>,
-    dirty: bool,
-    quit_times: usize,
-    file_name: Option<String>,
-    status_msg: Option<String>,
-    status_msg_time: std::time::Instant,
+    /// When wrapping, the screen lines of the top row that are scrolled off the top
+    line_offset: usize,
+    // the screen line of the window the cursor is on
+    cursor_line: usize,
+    /// Show line numbers
+    number: bool,
+    /// Wrap long rows onto more screen lines instead of scrolling sideways
+    wrap: bool,
+    // the bracket matching the one at the cursor, which is highlighted
+    matching_bracket: Option<Position>,
+    // the buffer, cursor position and buffer version the matching bracket was found for
+    matching_bracket_for: Option<(usize, Position, u64)>,
+    /// The other end of the selection from the cursor, while there is one
+    mark: Option<Position>,
+    // whether the mark was set with shift+arrow, so that moving without shift drops it
+    shift_selection: bool,
+    // where the window's text goes on screen, with its status bar on the row below
+    top: usize,
+    left: usize,
     screen_rows: usize,
     screen_cols: usize,
-    last_match: Option<usize>,
-    match_direction: bool,
 }
 
-impl EditorConfig {
-    fn new() -> Result<Self, std::io::Error> {
-        let (screen_rows, screen_cols) = get_window_size()?;
-        Ok(Self {
+impl EditorWindow {
+    fn new(buffer: usize) -> Self {
+        Self {
+            buffer,
             cx: 0,
             cy: 0,
             rx: 0,
             row_offset: 0,
             col_offset: 0,
-            rows: vec![],
-            dirty: false,
-            quit_times: KILO_QUIT_TIMES,
-            file_name: None,
+            line_offset: 0,
+            cursor_line: 0,
+            number: false,
+            wrap: false,
+            matching_bracket: None,
+            matching_bracket_for: None,
+            mark: None,
+            shift_selection: false,
+            top: 0,
+            left: 0,
+            screen_rows: 1,
+            screen_cols: 1,
+        }
+    }
+
+    fn cursor(&self) -> Position {
+        Position::new(self.cx, self.cy)
+    }
+}
+
+/// How the screen is divided between windows
+enum Layout {
+    Window(usize),
+    /// Two layouts side by side if `vertical`, or one above the other
+    Split {
+        vertical: bool,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Share the area of `window` between it and `new`
+    fn split(&mut self, window: usize, new: usize, vertical: bool) {
+        match self {
+            Layout::Window(w) if *w == window => {
+                *self = Layout::Split {
+                    vertical,
+                    first: Box::new(Layout::Window(window)),
+                    second: Box::new(Layout::Window(new)),
+                };
+            }
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.split(window, new, vertical);
+                second.split(window, new, vertical);
+            }
+        }
+    }
+
+    /// The layout without `window`, giving its area to the layout it was split from. Later
+    /// windows are renumbered to match removing it from the list of windows.
+    fn remove(self, window: usize) -> Option<Layout> {
+        match self {
+            Layout::Window(w) if w == window => None,
+            Layout::Window(w) => Some(Layout::Window(if w > window { w - 1 } else { w })),
+            Layout::Split {
+                vertical,
+                first,
+                second,
+            } => match (first.remove(window), second.remove(window)) {
+                (Some(first), Some(second)) => Some(Layout::Split {
+                    vertical,
+                    first: Box::new(first),
+                    second: Box::new(second),
+                }),
+                (layout, None) | (None, layout) => layout,
+            },
+        }
+    }
+
+    /// Give each window its part of an area of the screen, which includes a row for each
+    /// window's status bar
+    fn arrange(
+        &self,
+        windows: &mut [EditorWindow],
+        top: usize,
+        left: usize,
+        rows: usize,
+        cols: usize,
+    ) {
+        match self {
+            Layout::Window(w) => {
+                let window = &mut windows[*w];
+                window.top = top;
+                window.left = left;
+                window.screen_rows = rows.saturating_sub(1).max(1);
+                window.screen_cols = cols.max(1);
+            }
+            Layout::Split {
+                vertical: true,
+                first,
+                second,
+            } => {
+                // leaving a column between them for the separator
+                let first_cols = cols.saturating_sub(1) / 2;
+                let second_cols = cols.saturating_sub(first_cols + 1);
+                first.arrange(windows, top, left, rows, first_cols);
+                second.arrange(windows, top, left + first_cols + 1, rows, second_cols);
+            }
+            Layout::Split {
+                vertical: false,
+                first,
+                second,
+            } => {
+                let first_rows = rows / 2;
+                first.arrange(windows, top, left, first_rows, cols);
+                second.arrange(windows, top + first_rows, left, rows - first_rows, cols);
+            }
+        }
+    }
+}
+
+struct EditorConfig {
+    terminal: Box<dyn Terminal>,
+    buffers: Vec<EditorBuffer>,
+    windows: Vec<EditorWindow>,
+    layout: Layout,
+    /// Index of the window with the cursor
+    current: usize,
+    quit_times: usize,
+    status_msg: Option<String>,
+    status_msg_time: Instant,
+    // the screen above the message bar, shared out between the windows
+    screen_rows: usize,
+    screen_cols: usize,
+    search: SearchState,
+    kill_ring: KillRing,
+    // where the last command yanked text to, so that yank-pop can swap it for an older kill
+    yanked: Option<(Position, Position)>,
+    // whether the last command cut a whole row, so that cutting the next adds to the same kill
+    cutting_rows: bool,
+    /// Options and key bindings from `~/.kilorc`
+    settings: Config,
+}
+
+impl EditorConfig {
+    fn new(mut terminal: Box<dyn Terminal>, settings: Config) -> Result<Self, std::io::Error> {
+        let (screen_rows, screen_cols) = terminal.size()?;
+        let window = EditorWindow {
+            number: settings.number,
+            wrap: settings.wrap,
+            ..EditorWindow::new(0)
+        };
+        Ok(Self {
+            terminal,
+            buffers: vec![EditorBuffer::new(&settings)],
+            windows: vec![window],
+            layout: Layout::Window(0),
+            current: 0,
+            quit_times: settings.quit_times,
             status_msg: None,
-            status_msg_time: std::time::Instant::now(),
-            screen_rows: screen_rows - 2,
+            status_msg_time: Instant::now(),
+            // leave room for the message bar
+            screen_rows: screen_rows.saturating_sub(1).max(2),
             screen_cols,
-            last_match: None,
-            match_direction: true,
+            search: SearchState {
+                flags: 0,
+                regex: None,
+                error: None,
+                last_match: None,
+                forward: true,
+                origin: Position::new(0, 0),
+            },
+            kill_ring: KillRing::new(KILL_RING_SIZE),
+            yanked: None,
+            cutting_rows: false,
+            settings,
         })
     }
+
+    fn window(&self) -> &EditorWindow {
+        &self.windows[self.current]
+    }
+
+    fn window_mut(&mut self) -> &mut EditorWindow {
+        &mut self.windows[self.current]
+    }
+
+    /// The buffer shown in the current window
+    fn buffer(&self) -> &EditorBuffer {
+        &self.buffers[self.window().buffer]
+    }
+
+    fn buffer_mut(&mut self) -> &mut EditorBuffer {
+        let buffer = self.window().buffer;
+        &mut self.buffers[buffer]
+    }
+
+    /// The current window and its buffer, borrowed together
+    fn view_mut(&mut self) -> (&mut EditorWindow, &mut EditorBuffer) {
+        let window = &mut self.windows[self.current];
+        let buffer = &mut self.buffers[window.buffer];
+        (window, buffer)
+    }
 }
 
 // endregion: data
@@ -133,129 +406,244 @@ impl EditorConfig {
 // region: input
 
 fn move_cursor(config: &mut EditorConfig, dir: ArrowDirection) {
-    let col_limit = config
-        .rows
-        .get(config.cy)
-        .map(|r| r.chars.len())
-        .unwrap_or(0);
+    let (window, buffer) = config.view_mut();
+    let col_limit = row_len(buffer, window.cy);
+    let num_rows = buffer.text.line_count();
     match dir {
         ArrowDirection::Left => {
-            if config.cx > 0 {
-                config.cx -= 1;
-            } else if config.cy > 0 {
-                config.cy -= 1;
-                config.cx = config.rows[config.cy].chars.len();
+            if window.cx > 0 {
+                window.cx = prev_grapheme_boundary(&editor_row(buffer, window.cy).chars, window.cx);
+            } else if window.cy > 0 {
+                window.cy -= 1;
+                window.cx = row_len(buffer, window.cy);
             }
         }
         ArrowDirection::Right => {
-            if config.cx < col_limit {
-                config.cx += 1;
-            } else if config.cy < config.rows.len() && config.cx == col_limit {
-                config.cy += 1;
-                config.cx = 0;
+            if window.cx < col_limit {
+                window.cx = next_grapheme_boundary(&editor_row(buffer, window.cy).chars, window.cx);
+            } else if window.cy < num_rows && window.cx == col_limit {
+                window.cy += 1;
+                window.cx = 0;
             }
         }
-        ArrowDirection::Down if config.cy < config.rows.len() => {
-            config.cy += 1;
+        ArrowDirection::Up | ArrowDirection::Down if window.wrap => {
+            move_cursor_wrapped(window, buffer, dir == ArrowDirection::Down);
         }
-        ArrowDirection::Up if config.cy > 0 => {
-            config.cy -= 1;
+        ArrowDirection::Down if window.cy < num_rows => {
+            window.cy += 1;
+        }
+        ArrowDirection::Up if window.cy > 0 => {
+            window.cy -= 1;
         }
         _ => {}
     }
 
-    config.cx = if let Some(row) = config.rows.get(config.cy) {
-        config.cx.min(row.chars.len())
+    // keep the cursor off the middle of a cluster when moving between rows
+    window.cx = if window.cy < num_rows {
+        grapheme_floor(&editor_row(buffer, window.cy).chars, window.cx)
+    } else {
+        0
+    };
+}
+
+/// Move the cursor to the screen line above or below in a window that wraps rows, keeping to the
+/// same column on screen where the line is long enough
+fn move_cursor_wrapped(window: &mut EditorWindow, buffer: &mut EditorBuffer, down: bool) {
+    let width = text_cols(window, buffer);
+    let tab_stop = buffer.tab_stop;
+    let num_rows = buffer.text.line_count();
+    let rx = if window.cy < num_rows {
+        map_row_cx_to_rx(editor_row(buffer, window.cy), window.cx, tab_stop)
     } else {
         0
+    };
+    let starts = editor_wrap(buffer, window.cy, width);
+    let line = starts.iter().rposition(|&start| start <= rx).unwrap_or(0);
+    let col = rx - starts[line];
+
+    let (cy, line) = if down {
+        if line + 1 < starts.len() {
+            (window.cy, line + 1)
+        } else if window.cy < num_rows {
+            (window.cy + 1, 0)
+        } else {
+            return;
+        }
+    } else if line > 0 {
+        (window.cy, line - 1)
+    } else if window.cy > 0 {
+        let above = window.cy - 1;
+        (above, editor_wrap(buffer, above, width).len() - 1)
+    } else {
+        return;
+    };
+
+    window.cy = cy;
+    if cy < num_rows {
+        let rx = line_column(&editor_wrap(buffer, cy, width), line, col);
+        window.cx = map_row_rx_to_cx(editor_row(buffer, cy), rx, tab_stop);
+    }
+}
+
+/// The column of a wrapped row `col` columns along its screen line `line`, given where its lines
+/// start, or the last column of the line if it isn't that long
+fn line_column(starts: &[usize], line: usize, col: usize) -> usize {
+    // a line that isn't the last of its row ends where the next one starts
+    match starts.get(line + 1) {
+        Some(&next) => (starts[line] + col).min(next - 1),
+        None => starts[line] + col,
     }
 }
 
 fn process_keypress(config: &mut EditorConfig) -> bool {
-    let c = read_key();
+    let c = read_key(config.terminal.as_mut());
     // dbg!(c.clone());
-    match c {
-        EditorKey::Char(c) => match c {
-            CTRL_Q => {
-                if config.dirty && config.quit_times > 0 {
-                    set_status_message(
-                        config,
-                        format!("WARNING!!! File has unsaved changes. Press Ctrl-Q {} more times to quit.", config.quit_times)
-                            .as_str(),
-                    );
-                    config.quit_times -= 1;
-                    return false;
-                }
-                return refresh_screen(config).is_ok();
-            }
-            CTRL_S => {
-                _ = editor_save(config).inspect_err(|e| {
-                    set_status_message(config, format!("Can't save! I/O error: {}", e).as_str());
-                });
-            }
-            CTRL_F => {
-                editor_find(config);
-            }
-            CR => {
-                editor_insert_new_line(config);
-            }
-            ESCAPE | CTRL_L => {
-                // Ignore escape key
-            }
-            _ => editor_insert_char(config, c),
-        },
-        EditorKey::Backspace | EditorKey::Del => {
-            if c == EditorKey::Del {
-                move_cursor(config, ArrowDirection::Right);
+    if c == EditorKey::Resize {
+        editor_resize(config);
+        return false;
+    }
+
+    // only the command right after these can follow on from them
+    let yanked = config.yanked.take();
+    let cutting_rows = std::mem::take(&mut config.cutting_rows);
+
+    let action = config.settings.keymap.get(&c).copied();
+    // moving without shift ends a selection made with shift
+    if config.window().shift_selection
+        && matches!(
+            action,
+            Some(Action::Move(_) | Action::Page(_) | Action::LineStart | Action::LineEnd)
+        )
+    {
+        config.window_mut().mark = None;
+    }
+
+    // consecutive typed characters are undone as one step, anything else starts a new one, but
+    // deleting back right after typing takes back the characters typed
+    let typing = action.is_none() && matches!(c, EditorKey::Char(c) if !c.is_control());
+    if !typing && action != Some(Action::DeleteBack) {
+        config.buffer_mut().history.break_group();
+    }
+
+    match action {
+        Some(Action::Quit) => {
+            let dirty = config.buffers.iter().any(|buffer| buffer.dirty);
+            if dirty && config.quit_times > 0 {
+                set_status_message(
+                    config,
+                    format!(
+                        "WARNING!!! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                        config.quit_times
+                    )
+                    .as_str(),
+                );
+                config.quit_times -= 1;
+                return false;
             }
+            return refresh_screen(config).is_ok();
+        }
+        Some(Action::Save) => editor_save_or_report(config, None),
+        Some(Action::Command) => editor_command(config),
+        Some(Action::Find) => {
+            editor_find(config);
+        }
+        Some(Action::Replace) => editor_replace(config),
+        Some(Action::Undo) => editor_undo(config),
+        Some(Action::Redo) => editor_redo(config),
+        Some(Action::SetMark) => editor_toggle_mark(config),
+        Some(Action::ClearMark) => config.window_mut().mark = None,
+        Some(Action::Copy) => editor_copy(config),
+        Some(Action::Cut) => editor_cut(config, cutting_rows),
+        Some(Action::Paste) => editor_yank(config),
+        Some(Action::YankPop) => editor_yank_pop(config, yanked),
+        Some(Action::SwitchBuffer) => editor_switch_buffer(config),
+        Some(Action::NextBuffer) => {
+            let next = (config.window().buffer + 1) % config.buffers.len();
+            editor_show_buffer(config, next);
+        }
+        Some(Action::Window) => editor_window_command(config),
+        Some(Action::Redraw) => {}
+        Some(Action::NewLine) => {
+            editor_insert_new_line(config);
+        }
+        Some(Action::DeleteBack) => editor_del_char(config),
+        Some(Action::DeleteForward) => {
+            move_cursor(config, ArrowDirection::Right);
             editor_del_char(config);
         }
-        EditorKey::Arrow(dir) => move_cursor(config, dir),
-        EditorKey::Page(dir) => {
+        Some(Action::Move(dir)) => move_cursor(config, dir),
+        Some(Action::Select(dir)) => {
+            if config.window().mark.is_none() {
+                config.window_mut().mark = Some(config.window().cursor());
+                config.window_mut().shift_selection = true;
+            }
+            move_cursor(config, dir);
+        }
+        Some(Action::Page(dir)) => {
+            let screen_rows = config.window().screen_rows;
             let (key, adjusted_cy) = match dir {
-                PageDirection::Up => (ArrowDirection::Up, config.row_offset),
+                PageDirection::Up => (ArrowDirection::Up, config.window().row_offset),
                 PageDirection::Down => (
                     ArrowDirection::Down,
                     config
-                        .rows
-                        .len()
-                        .min(config.row_offset + config.screen_rows - 1),
+                        .buffer()
+                        .text
+                        .line_count()
+                        .min(config.window().row_offset + screen_rows - 1),
                 ),
             };
-            config.cy = adjusted_cy;
-            for _ in 0..config.screen_rows {
+            config.window_mut().cy = adjusted_cy;
+            for _ in 0..screen_rows {
                 move_cursor(config, key);
             }
         }
-        EditorKey::Home => config.cx = 0,
-        EditorKey::End => {
-            config.cx = if let Some(row) = config.rows.get(config.cy) {
-                row.chars.len()
-            } else {
-                0
+        Some(Action::LineStart) => config.window_mut().cx = 0,
+        Some(Action::LineEnd) => {
+            let (window, buffer) = config.view_mut();
+            window.cx = row_len(buffer, window.cy);
+        }
+        Some(Action::InsertTab) => editor_insert_tab(config),
+        Some(Action::MatchBracket) => {
+            let (window, buffer) = config.view_mut();
+            match editor_matching_bracket(buffer, window.cursor()) {
+                Some(at) => (window.cx, window.cy) = (at.cx, at.cy),
+                None => set_status_message(config, "No matching bracket"),
             }
         }
+        // keys without a binding type themselves
+        None => match c {
+            EditorKey::Char(c) => editor_insert_char(config, c),
+            EditorKey::Mouse(mouse) => editor_mouse(config, mouse),
+            _ => {}
+        },
     }
 
-    config.quit_times = KILO_QUIT_TIMES;
+    if !typing {
+        config.buffer_mut().history.break_group();
+    }
+    config.quit_times = config.settings.quit_times;
 
     false
 }
 
+/// Read a line of input in the message bar, calling `callback` after every key. Returns `None` if
+/// the prompt was cancelled with escape.
 fn editor_prompt<F, C>(formatter: F, callback: C, config: &mut EditorConfig) -> Option<String>
 where
-    F: Fn(&str) -> String,
+    F: Fn(&str, &EditorConfig) -> String,
     C: Fn(&str, EditorKey, &mut EditorConfig),
 {
     let mut buf = vec![];
     loop {
-        set_status_message(
-            config,
-            formatter(buf.iter().collect::<String>().as_str()).as_str(),
-        );
+        let msg = formatter(buf.iter().collect::<String>().as_str(), config);
+        set_status_message(config, msg.as_str());
         refresh_screen(config).ok()?;
-        let key = read_key();
+        let key = read_key(config.terminal.as_mut());
         match key {
+            EditorKey::Resize => {
+                editor_resize(config);
+                continue;
+            }
             EditorKey::Backspace => {
                 buf.pop();
             }
@@ -265,12 +653,12 @@ where
                     callback(buf.iter().collect::<String>().as_str(), key, config);
                     return None;
                 }
-                CR if !buf.is_empty() => {
+                CR => {
                     set_status_message(config, "");
                     callback(buf.iter().collect::<String>().as_str(), key, config);
                     break;
                 }
-                c if !c.is_ascii_control() && (c as u8) < 128 => {
+                c if !c.is_control() => {
                     buf.push(c);
                 }
                 _ => {}
@@ -283,8 +671,21 @@ where
     Some(buf.iter().collect())
 }
 
-const fn ctrl_key(c: char) -> char {
-    (c as u8 & 0x1f) as char
+/// Ask a question in the message bar and wait for a single key in answer
+fn editor_confirm(config: &mut EditorConfig, question: &str) -> EditorKey {
+    loop {
+        set_status_message(config, question);
+        if refresh_screen(config).is_err() {
+            return EditorKey::Char(ESCAPE);
+        }
+        match read_key(config.terminal.as_mut()) {
+            EditorKey::Resize => editor_resize(config),
+            key => {
+                set_status_message(config, "");
+                return key;
+            }
+        }
+    }
 }
 
 // endregion: input
@@ -292,128 +693,488 @@ const fn ctrl_key(c: char) -> char {
 // region: output
 
 fn refresh_screen(config: &mut EditorConfig) -> Result<(), std::io::Error> {
-    editor_scroll(config);
+    let (rows, cols) = (config.screen_rows, config.screen_cols);
+    config.layout.arrange(&mut config.windows, 0, 0, rows, cols);
+    for window in &mut config.windows {
+        editor_scroll(window, &mut config.buffers[window.buffer]);
+    }
+    editor_render_visible_rows(config);
+
     let make_cursor_invisible_cmd = b"\x1b[?25l";
-    let reposition_cursor_cmd = b"\x1b[H";
-    let mut commmands = BufferedCommands::new(
-        [
-            make_cursor_invisible_cmd.as_slice(),
-            reposition_cursor_cmd.as_slice(),
-        ]
-        .concat(),
-    );
+    let mut commmands = BufferedCommands::new(make_cursor_invisible_cmd.to_vec());
 
-    draw_rows(config, &mut commmands);
-    draw_status_bar(config, &mut commmands);
+    for (i, window) in config.windows.iter().enumerate() {
+        draw_rows(config, window, &mut commmands);
+        draw_status_bar(config, window, i == config.current, &mut commmands);
+    }
     draw_message_bar(config, &mut commmands);
 
+    let window = config.window();
+    let gutter = gutter_width(window, config.buffer());
     let place_cursor_cmd = format!(
         "\x1b[{};{}H",
-        config.cy - config.row_offset + 1,
-        config.rx - config.col_offset + 1
+        window.top + window.cursor_line + 1,
+        window.left + gutter + window.rx - window.col_offset + 1
     );
     commmands.append(place_cursor_cmd.as_bytes());
     let make_cursor_visible_cmd = b"\x1b[?25h";
     commmands.append(make_cursor_visible_cmd);
-    commmands.execute()?;
+    config.terminal.write_all(commmands.as_bytes())?;
 
     Ok(())
 }
 
-fn editor_scroll(config: &mut EditorConfig) {
-    config.rx = config.cx;
-    if config.cy < config.rows.len() {
-        config.rx = map_row_cx_to_rx(&config.rows[config.cy], config.cx);
+/// Pick up a new terminal size. The cursor is kept in file coordinates, so it stays put, and
+/// `editor_scroll` brings the offsets back around it when the screen is next drawn.
+fn editor_resize(config: &mut EditorConfig) {
+    let Ok((screen_rows, screen_cols)) = config.terminal.size() else {
+        return;
+    };
+    config.screen_rows = screen_rows.saturating_sub(1).max(2);
+    config.screen_cols = screen_cols;
+}
+
+fn editor_scroll(window: &mut EditorWindow, buffer: &mut EditorBuffer) {
+    // edits in another window on the buffer can leave the cursor past the end of it
+    window.cy = window.cy.min(buffer.text.line_count());
+    window.rx = 0;
+    if window.cy < buffer.text.line_count() {
+        let tab_stop = buffer.tab_stop;
+        let row = editor_row(buffer, window.cy);
+        window.cx = grapheme_floor(&row.chars, window.cx.min(row.chars.len()));
+        window.rx = map_row_cx_to_rx(row, window.cx, tab_stop);
+    } else {
+        window.cx = 0;
+    }
+    let bracket_key = (window.buffer, window.cursor(), buffer.version);
+    if window.matching_bracket_for != Some(bracket_key) {
+        window.matching_bracket = editor_matching_bracket(buffer, window.cursor());
+        window.matching_bracket_for = Some(bracket_key);
+    }
+
+    if window.wrap {
+        editor_scroll_wrapped(window, buffer);
+        return;
+    }
+    window.row_offset = window.row_offset.min(window.cy);
+    if window.cy >= window.row_offset + window.screen_rows {
+        window.row_offset = window.cy - window.screen_rows + 1;
+    }
+    let width = text_cols(window, buffer);
+    window.col_offset = window.col_offset.min(window.rx);
+    if window.rx >= window.col_offset + width {
+        window.col_offset = window.rx - width + 1;
+    }
+    window.line_offset = 0;
+    window.cursor_line = window.cy - window.row_offset;
+}
+
+/// Scroll a window that wraps rows by screen lines, so that the top of it can be partway through
+/// a row that's longer than the window is high
+fn editor_scroll_wrapped(window: &mut EditorWindow, buffer: &mut EditorBuffer) {
+    let width = text_cols(window, buffer);
+    let starts = editor_wrap(buffer, window.cy, width);
+    let line = starts
+        .iter()
+        .rposition(|&start| start <= window.rx)
+        .unwrap_or(0);
+    window.col_offset = starts[line];
+    let cursor = (window.cy, line);
+
+    // the lines that can be on screen above the cursor's, going up
+    let mut above = vec![cursor];
+    while above.len() < window.screen_rows {
+        let (row, line) = above[above.len() - 1];
+        let prev = if line > 0 {
+            (row, line - 1)
+        } else if row > 0 {
+            (row - 1, editor_wrap(buffer, row - 1, width).len() - 1)
+        } else {
+            break;
+        };
+        above.push(prev);
+    }
+
+    let mut top = (window.row_offset, window.line_offset);
+    if top.0 <= buffer.text.line_count() {
+        // the row may have been shortened since
+        top.1 = top.1.min(editor_wrap(buffer, top.0, width).len() - 1);
+    }
+    let top = top.max(above[above.len() - 1]).min(cursor);
+    (window.row_offset, window.line_offset) = top;
+    window.cursor_line = above
+        .iter()
+        .position(|&line| line == top)
+        .expect("the top is between the cursor and the highest line above it");
+}
+
+/// Columns at the left of a window taken by line numbers and the space after them
+fn gutter_width(window: &EditorWindow, buffer: &EditorBuffer) -> usize {
+    if !window.number {
+        return 0;
+    }
+    let digits = buffer.text.line_count().max(1).ilog10() as usize + 1;
+    // leaving at least a column for the text
+    (digits.max(3) + 1).min(window.screen_cols - 1)
+}
+
+/// Columns of a window that show text, right of the gutter
+fn text_cols(window: &EditorWindow, buffer: &EditorBuffer) -> usize {
+    window.screen_cols - gutter_width(window, buffer)
+}
+
+/// Where each screen line of a rendered row starts, as a column of the row, when it's wrapped to
+/// `width` columns. Rows break before a cluster that would cross the edge. A row that exactly
+/// fills its last line gets an empty one after it, for the cursor at its end.
+fn wrap_columns(render: &[char], width: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut col = 0;
+    for g in graphemes(render) {
+        let w = grapheme_width(&render[g]);
+        if col > starts[starts.len() - 1] && col + w > starts[starts.len() - 1] + width {
+            starts.push(col);
+        }
+        col += w;
+    }
+    if col > 0 && col == starts[starts.len() - 1] + width {
+        starts.push(col);
     }
 
-    config.row_offset = config.row_offset.min(config.cy);
-    if config.cy >= config.row_offset + config.screen_rows {
-        config.row_offset = config.cy - config.screen_rows + 1;
+    starts
+}
+
+/// Where the screen lines of the row at `at` start when wrapped to `width`. Past the last row
+/// there's a single empty line.
+fn editor_wrap(buffer: &mut EditorBuffer, at: usize, width: usize) -> Vec<usize> {
+    if at < buffer.text.line_count() {
+        wrap_columns(&editor_row(buffer, at).render, width)
+    } else {
+        vec![0]
     }
-    config.col_offset = config.col_offset.min(config.rx);
-    if config.rx >= config.col_offset + config.screen_cols {
-        config.col_offset = config.rx - config.screen_cols + 1;
+}
+
+/// Render the rows on screen, dropping every other rendered row but the cursors', so that only
+/// a screenful of each file is ever decoded
+fn editor_render_visible_rows(config: &mut EditorConfig) {
+    for (i, buffer) in config.buffers.iter_mut().enumerate() {
+        let line_count = buffer.text.line_count();
+        let views = config
+            .windows
+            .iter()
+            .filter(|window| window.buffer == i)
+            .map(|window| {
+                let visible =
+                    window.row_offset..(window.row_offset + window.screen_rows).min(line_count);
+                (visible, window.cy)
+            })
+            .collect::<Vec<_>>();
+
+        buffer.rows.retain(|row, _| {
+            views
+                .iter()
+                .any(|(visible, cy)| visible.contains(row) || row == cy)
+        });
+        for (visible, _) in views {
+            for row in visible {
+                editor_row(buffer, row);
+            }
+        }
     }
 }
 
-fn draw_rows(config: &EditorConfig, commands: &mut BufferedCommands) {
-    dbg!(config.cx, config.col_offset, config.screen_cols);
-    dbg!(config.cy, config.row_offset, config.screen_rows);
-    for y in 0..config.screen_rows {
-        let file_row = y + config.row_offset;
-        if file_row >= config.rows.len() {
-            if config.rows.len() == 0 && y == config.screen_rows / 3 {
-                draw_welcome_greeting(config, commands);
+fn draw_rows(config: &EditorConfig, window: &EditorWindow, commands: &mut BufferedCommands) {
+    let buffer = &config.buffers[window.buffer];
+    let separator = window.left + window.screen_cols < config.screen_cols;
+    let gutter = gutter_width(window, buffer);
+    let width = text_cols(window, buffer);
+    let mut file_row = window.row_offset;
+    let mut skip = window.line_offset;
+    let mut y = 0;
+    while y < window.screen_rows {
+        if file_row >= buffer.text.line_count() {
+            draw_window_line(window, y, separator, commands);
+            if buffer.text.is_empty() && y == window.screen_rows / 3 {
+                draw_welcome_greeting(window, commands);
             } else {
                 let placeholder_tilde_line = b"~";
                 commands.append(placeholder_tilde_line);
             }
+            y += 1;
+            continue;
+        }
+
+        let row = &buffer.rows[&file_row];
+        let hl = match &config.search.regex {
+            Some(regex) => search_highlight(row, regex, buffer.tab_stop),
+            None => row.hl.clone(),
+        };
+        let selected = selected_columns(window, row, file_row, buffer.tab_stop);
+        let bracket = window
+            .matching_bracket
+            .filter(|at| at.cy == file_row)
+            .map(|at| map_row_cx_to_render(row, at.cx, buffer.tab_stop));
+        let styles = hl
+            .into_iter()
+            .enumerate()
+            .map(|(i, hl)| Style {
+                hl,
+                reverse: selected.contains(&i) || bracket == Some(i),
+            })
+            .collect::<Vec<_>>();
+
+        // each screen line of the row as its first column and width
+        let lines = if window.wrap {
+            let starts = wrap_columns(&row.render, width);
+            let ends = starts
+                .iter()
+                .skip(1)
+                .copied()
+                .chain([starts[starts.len() - 1] + width]);
+            starts
+                .iter()
+                .zip(ends)
+                .map(|(&start, end)| (start, end - start))
+                .collect()
         } else {
-            let len = config.rows[file_row]
-                .render
-                .len()
-                .checked_sub(config.col_offset)
-                .unwrap_or(0);
-            let len = len.min(config.screen_cols);
-            if config.col_offset < config.rows[file_row].render.len() {
-                let line =
-                    &config.rows[file_row].render[config.col_offset..config.col_offset + len];
-                commands.append(line.iter().collect::<String>().as_bytes());
+            vec![(window.col_offset, width)]
+        };
+        for (i, (start, len)) in lines.into_iter().enumerate().skip(skip) {
+            if y == window.screen_rows {
+                break;
+            }
+            draw_window_line(window, y, separator, commands);
+            if gutter > 0 {
+                let number = if i == 0 {
+                    (file_row + 1).to_string()
+                } else {
+                    String::new()
+                };
+                let number = format!("{:>1$} ", number, gutter - 1);
+                commands.append(truncate_to_width(&number, gutter).as_bytes());
+            }
+            let (chars, styles) = visible_columns(&row.render, &styles, start, len);
+            draw_highlighted(&chars, &styles, commands);
+            y += 1;
+        }
+        file_row += 1;
+        skip = 0;
+    }
+}
+
+/// Move to the start of row `y` of a window and clear it, drawing the separator from the window
+/// to its right if there is one
+fn draw_window_line(
+    window: &EditorWindow,
+    y: usize,
+    separator: bool,
+    commands: &mut BufferedCommands,
+) {
+    let row = window.top + y + 1;
+    let erase_cmd = format!(
+        "\x1b[{};{}H\x1b[{}X",
+        row,
+        window.left + 1,
+        window.screen_cols
+    );
+    commands.append(erase_cmd.as_bytes());
+
+    if separator {
+        let right = window.left + window.screen_cols;
+        let separator_cmd = format!("\x1b[{};{}H\x1b[7m|\x1b[m", row, right + 1);
+        commands.append(separator_cmd.as_bytes());
+    }
+    let reposition_cursor_cmd = format!("\x1b[{};{}H", row, window.left + 1);
+    commands.append(reposition_cursor_cmd.as_bytes());
+}
+
+/// A row's highlight with every match of the search pattern marked
+fn search_highlight(row: &EditorRow, regex: &Regex, tab_stop: usize) -> Vec<Highlight> {
+    let mut hl = row.hl.clone();
+    for m in regex.find_iter(&row.chars) {
+        let start = map_row_cx_to_render(row, m.start(), tab_stop);
+        let end = map_row_cx_to_render(row, m.end(), tab_stop);
+        hl[start..end].fill(Highlight::Match);
+    }
+
+    hl
+}
+
+/// Indexes in `render` of the selected chars of the row at `at`
+fn selected_columns(
+    window: &EditorWindow,
+    row: &EditorRow,
+    at: usize,
+    tab_stop: usize,
+) -> Range<usize> {
+    let Some((start, end)) = editor_selection(window) else {
+        return 0..0;
+    };
+    if at < start.cy || at > end.cy {
+        return 0..0;
+    }
+
+    let from = if at == start.cy { start.cx } else { 0 };
+    let to = if at == end.cy {
+        end.cx
+    } else {
+        row.chars.len()
+    };
+    map_row_cx_to_render(row, from, tab_stop)..map_row_cx_to_render(row, to, tab_stop)
+}
+
+/// The rendered chars of a row that fall within `width` columns from `col_offset`, with their
+/// styles. A wide character cut by either edge is drawn as spaces.
+fn visible_columns(
+    render: &[char],
+    row_styles: &[Style],
+    col_offset: usize,
+    width: usize,
+) -> (Vec<char>, Vec<Style>) {
+    let mut chars = vec![];
+    let mut styles = vec![];
+    let end = col_offset + width;
+    let mut col = 0;
+    for g in graphemes(render) {
+        if col >= end {
+            break;
+        }
+        let w = grapheme_width(&render[g.clone()]);
+        if col + w > col_offset {
+            if col < col_offset || col + w > end {
+                let shown = (col + w).min(end) - col.max(col_offset);
+                chars.extend(std::iter::repeat_n(' ', shown));
+                styles.extend(std::iter::repeat_n(
+                    Style {
+                        hl: Highlight::Normal,
+                        ..row_styles[g.start]
+                    },
+                    shown,
+                ));
             } else {
-                commands.append(b"");
-            };
+                // the whole cluster takes the style of its first char
+                chars.extend_from_slice(&render[g.clone()]);
+                styles.extend(std::iter::repeat_n(row_styles[g.start], g.len()));
+            }
+        }
+        col += w;
+    }
+
+    (chars, styles)
+}
+
+fn draw_highlighted(chars: &[char], styles: &[Style], commands: &mut BufferedCommands) {
+    let mut current_color = None;
+    let mut inverted = false;
+    for (&c, &style) in chars.iter().zip(styles) {
+        // reverse video is drawn over the highlight colors
+        if style.reverse && !inverted {
+            commands.append(b"\x1b[7m");
+        } else if !style.reverse && inverted {
+            commands.append(b"\x1b[27m");
         }
+        inverted = style.reverse;
 
-        let clear_line_cmd = b"\x1b[K";
-        commands.append(clear_line_cmd);
-        commands.append(b"\r\n");
+        let h = style.hl;
+        if c.is_control() {
+            // show control characters as inverted '@', 'A', 'B', ... or '?'
+            let symbol = if (c as u32) <= 26 {
+                (b'@' + c as u8) as char
+            } else {
+                '?'
+            };
+            commands.append(format!("\x1b[7m{}\x1b[m", symbol).as_bytes());
+            if let Some(color) = current_color {
+                commands.append(format!("\x1b[{}m", color).as_bytes());
+            }
+            if inverted {
+                commands.append(b"\x1b[7m");
+            }
+        } else if h == Highlight::Normal {
+            if current_color.is_some() {
+                let default_color_cmd = b"\x1b[39m";
+                commands.append(default_color_cmd);
+                current_color = None;
+            }
+            commands.append(c.to_string().as_bytes());
+        } else {
+            let color = h.color();
+            if current_color != Some(color) {
+                commands.append(format!("\x1b[{}m", color).as_bytes());
+                current_color = Some(color);
+            }
+            commands.append(c.to_string().as_bytes());
+        }
+    }
+    if inverted {
+        commands.append(b"\x1b[27m");
     }
+    let default_color_cmd = b"\x1b[39m";
+    commands.append(default_color_cmd);
 }
 
-fn draw_welcome_greeting(config: &EditorConfig, commands: &mut BufferedCommands) {
+fn draw_welcome_greeting(window: &EditorWindow, commands: &mut BufferedCommands) {
     let greeting = "Kilo editor -- version ".to_string() + env!("CARGO_PKG_VERSION");
-    let mut padding = (config.screen_cols - greeting.len()) / 2;
+    let greeting = truncate_to_width(&greeting, window.screen_cols);
+    let mut padding = window.screen_cols.saturating_sub(greeting.len()) / 2;
     if padding > 0 {
         let placeholder_tilde_line = b"~";
         commands.append(placeholder_tilde_line);
         padding -= 1;
     }
     for _ in 0..padding {
-        commands.append(&[b' ']);
+        commands.append(b" ");
     }
     commands.append(greeting.bytes().collect::<Vec<_>>().as_slice());
 }
 
-fn draw_status_bar(config: &EditorConfig, commands: &mut BufferedCommands) {
-    let file_name = config.file_name.as_deref().unwrap_or("[No Name]");
-    let lines = config.rows.len();
-    let status_left = format!(
+/// Draw the status bar below a window, in bold for the current one
+fn draw_status_bar(
+    config: &EditorConfig,
+    window: &EditorWindow,
+    current: bool,
+    commands: &mut BufferedCommands,
+) {
+    let separator = window.left + window.screen_cols < config.screen_cols;
+    draw_window_line(window, window.screen_rows, separator, commands);
+
+    let buffer = &config.buffers[window.buffer];
+    let file_name = buffer.file_name.as_deref().unwrap_or("[No Name]");
+    let lines = buffer.text.line_count();
+    let mut status_left = format!(
         "{:.20} - {} lines {}",
         file_name,
         lines,
-        if config.dirty { "(modified)" } else { "" }
+        if buffer.dirty { "(modified)" } else { "" }
     );
-    let status_right = format!("{}/{}", config.cy + 1, config.rows.len());
-    draw_text_in_status_bar(config, &status_left, &status_right, commands);
-    commands.append(b"\r\n");
+    if config.buffers.len() > 1 {
+        status_left = format!("{}: {}", window.buffer + 1, status_left);
+    }
+    let file_type = buffer.syntax.map_or("no ft", |syntax| syntax.file_type);
+    let status_right = format!("{} | {}/{}", file_type, window.cy + 1, lines);
+    if current {
+        let bold_cmd = b"\x1b[1m";
+        commands.append(bold_cmd);
+    }
+    draw_text_in_status_bar(window.screen_cols, &status_left, &status_right, commands);
 }
 
 fn draw_text_in_status_bar(
-    config: &EditorConfig,
+    width: usize,
     text_left: &str,
     text_right: &str,
     commands: &mut BufferedCommands,
 ) {
     let inverted_color_cmd = b"\x1b[7m";
     commands.append(inverted_color_cmd);
-    let mut len = text_left.as_bytes().len().min(config.screen_cols);
-    commands.append(&text_left.as_bytes()[..len]);
-    while len < config.screen_cols {
-        if len + text_right.as_bytes().len() == config.screen_cols {
+    let text_left = truncate_to_width(text_left, width);
+    commands.append(text_left.as_bytes());
+    let mut len = str_width(text_left);
+    while len < width {
+        if len + str_width(text_right) == width {
             commands.append(text_right.as_bytes());
             break;
         }
-        commands.append(&[b' ']);
+        commands.append(b" ");
         len += 1;
     }
 
@@ -422,135 +1183,471 @@ fn draw_text_in_status_bar(
 }
 
 fn draw_message_bar(config: &EditorConfig, commands: &mut BufferedCommands) {
+    let place_cursor_cmd = format!("\x1b[{};1H", config.screen_rows + 1);
+    commands.append(place_cursor_cmd.as_bytes());
     commands.append(b"\x1b[K");
     let msg = config.status_msg.as_deref().unwrap_or("");
-    let msg_len = msg.len().min(config.screen_cols);
-    let msg = &msg[..msg_len];
+    let msg = truncate_to_width(msg, config.screen_cols);
 
-    if msg.len() > 0 && config.status_msg_time.elapsed().as_secs() < 5 {
+    if !msg.is_empty() && config.status_msg_time.elapsed().as_secs() < 5 {
         commands.append(msg.as_bytes());
     }
 }
 
 fn set_status_message(config: &mut EditorConfig, msg: &str) {
     config.status_msg = Some(msg.to_string());
-    config.status_msg_time = std::time::Instant::now();
+    config.status_msg_time = Instant::now();
 }
 
 // endregion: output
 
 // region: file i/o
 
+/// Open a file, offering to recover the unsaved changes in its swap file if there are any
 fn editor_open(file_name: &str, config: &mut EditorConfig) -> std::io::Result<()> {
-    let file = File::open(file_name).expect("failed to open file");
-    config.file_name = Some(file_name.to_string());
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line?;
-        editor_insert_row(config.rows.len(), line, config);
+    let mut contents = std::fs::read(file_name)?;
+    let swap = swap_path(Path::new(file_name));
+    let mut recovered = false;
+    match std::fs::read(&swap) {
+        Ok(swapped) if swapped != contents => {
+            let question = format!(
+                "{} has unsaved changes from a session that didn't exit. Recover them? (y/n)",
+                file_name
+            );
+            if editor_confirm(config, &question) == EditorKey::Char('y') {
+                contents = swapped;
+                recovered = true;
+                set_status_message(config, "Recovered unsaved changes; save to keep them");
+            } else {
+                _ = std::fs::remove_file(&swap);
+            }
+        }
+        Ok(_) => _ = std::fs::remove_file(&swap),
+        Err(_) => {}
+    }
+
+    let buffer = config.buffer_mut();
+    buffer.text = TextBuffer::from_bytes(contents);
+    buffer.file_name = Some(file_name.to_string());
+    editor_select_syntax(buffer);
+    buffer.history = History::new();
+    if recovered {
+        buffer.history.mark_unsaved();
     }
-    config.dirty = false;
+    buffer.dirty = recovered;
 
     Ok(())
 }
 
 fn editor_save(config: &mut EditorConfig) -> std::io::Result<()> {
-    let Some(file_name) = config.file_name.clone().or_else(|| {
+    let Some(file_name) = config.buffer().file_name.clone().or_else(|| {
         editor_prompt(
-            |file_name| format!("Save as: {}", file_name),
+            |file_name, _| format!("Save as: {}", file_name),
             |_, _, _| (),
             config,
         )
+        .filter(|file_name| !file_name.is_empty())
     }) else {
         set_status_message(config, "Save aborted");
         return Ok(());
     };
+    editor_write(config, &file_name)
+}
 
-    let content = editor_rows_to_string(config);
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(file_name)?;
+/// Write the current buffer to a file, which becomes the buffer's file if it doesn't have one.
+/// Writing to any other file leaves the buffer as modified as it was.
+fn editor_write(config: &mut EditorConfig, file_name: &str) -> std::io::Result<()> {
+    if config.buffer().file_name.is_none() {
+        config.buffer_mut().file_name = Some(file_name.to_string());
+        editor_select_syntax(config.buffer_mut());
+    }
 
-    truncate_file(&mut file, content.len())?;
-    let dat = content.as_bytes();
-    file.write_all(dat)?;
-    config.dirty = false;
-    set_status_message(
-        config,
-        format!("{} bytes written to disk", dat.len()).as_str(),
-    );
+    let backup = config.settings.backup;
+    let buffer = config.buffer_mut();
+    let len = buffer.text.len();
+    write_atomic(Path::new(file_name), backup, None, |file| {
+        buffer.text.write_to(file)
+    })?;
+    if buffer.file_name.as_deref() == Some(file_name) {
+        editor_remove_swap(buffer);
+        buffer.history.mark_saved();
+        buffer.dirty = false;
+        set_status_message(config, format!("{} bytes written to disk", len).as_str());
+    } else {
+        set_status_message(
+            config,
+            format!("{} bytes written to {}", len, file_name).as_str(),
+        );
+    }
 
     Ok(())
 }
 
-fn editor_rows_to_string(config: &EditorConfig) -> String {
-    let mut content = String::new();
-    for row in &config.rows {
-        content.push_str(&row.chars.iter().collect::<String>());
-        content.push(LF);
+/// Write each buffer with changes to its swap file, if it's been long enough since the last time
+fn editor_write_swaps(config: &mut EditorConfig) {
+    let mut error = None;
+    for buffer in &mut config.buffers {
+        if !buffer.dirty || buffer.swap_time.elapsed().as_secs() < KILO_SWAP_SECS {
+            continue;
+        }
+        let Some(file_name) = &buffer.file_name else {
+            continue;
+        };
+
+        // the swap holds what the file does, so it lets no one read it the file doesn't
+        let mode = std::fs::metadata(file_name)
+            .map_or(0o600, |metadata| metadata.permissions().mode() & 0o777);
+        let text = &buffer.text;
+        let result = write_atomic(
+            &swap_path(Path::new(file_name)),
+            false,
+            Some(mode),
+            |file| text.write_to(file),
+        );
+        buffer.swap_time = Instant::now();
+        error = result.err().or(error);
     }
 
-    content
+    if let Some(e) = error {
+        set_status_message(config, format!("Can't write swap file: {}", e).as_str());
+    }
 }
 
-// endregion: file i/o
-
-// region: find
+fn editor_remove_swap(buffer: &EditorBuffer) {
+    if let Some(file_name) = &buffer.file_name {
+        _ = std::fs::remove_file(swap_path(Path::new(file_name)));
+    }
+}
 
-fn editor_find_callback(query: &str, key: EditorKey, config: &mut EditorConfig) {
-    if key == EditorKey::Char(ESCAPE) || key == EditorKey::Char(CR) {
-        config.last_match = None;
-        config.match_direction = true;
-        return;
+/// Save the current buffer, or write it to `file_name`, saying in the message bar if it failed
+fn editor_save_or_report(config: &mut EditorConfig, file_name: Option<&str>) {
+    let result = match file_name {
+        Some(file_name) => editor_write(config, file_name),
+        None => editor_save(config),
+    };
+    if let Err(e) = result {
+        set_status_message(config, format!("Can't save! I/O error: {}", e).as_str());
     }
+}
 
-    match key {
-        EditorKey::Arrow(ArrowDirection::Down) | EditorKey::Arrow(ArrowDirection::Right) => {
-            config.match_direction = true;
+/// Read the options and key bindings in `~/.kilorc`, or the defaults if there isn't one.
+/// `KILO_OSC52` and `KILO_BACKUP` set in the environment turn those options on.
+fn load_config() -> Result<Config, String> {
+    let mut config = Config {
+        osc52: std::env::var_os("KILO_OSC52").is_some(),
+        backup: std::env::var_os("KILO_BACKUP").is_some(),
+        ..Config::default()
+    };
+    let Some(home) = std::env::var_os("HOME") else {
+        return Ok(config);
+    };
+
+    let path = Path::new(&home).join(".kilorc");
+    match std::fs::read_to_string(&path) {
+        Ok(text) => config
+            .apply(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    }
+
+    Ok(config)
+}
+
+// endregion: file i/o
+
+// region: commands
+
+/// Read a command at the `:` prompt and run it
+fn editor_command(config: &mut EditorConfig) {
+    let Some(line) = editor_prompt(|line, _| format!(":{}", line), |_, _, _| (), config) else {
+        return;
+    };
+    if line.trim().is_empty() {
+        return;
+    }
+
+    match Command::parse(&line) {
+        Ok(Command::Goto(line)) => {
+            let (window, buffer) = config.view_mut();
+            window.cy = (line - 1).min(buffer.text.line_count().saturating_sub(1));
+            window.cx = 0;
         }
-        EditorKey::Arrow(ArrowDirection::Up) | EditorKey::Arrow(ArrowDirection::Left) => {
-            config.match_direction = false;
+        Ok(Command::Set(setting)) => editor_set(config, setting),
+        Ok(Command::Write(file_name)) => editor_save_or_report(config, file_name.as_deref()),
+        Ok(Command::Edit(file_name)) => editor_open_buffer(config, &file_name),
+        Ok(Command::Filter(command)) => editor_filter(config, &command),
+        Err(e) => set_status_message(config, e.to_string().as_str()),
+    }
+}
+
+/// Change an option of the current window, or of its buffer for the options to do with
+/// indentation
+fn editor_set(config: &mut EditorConfig, setting: Setting) {
+    let (window, buffer) = config.view_mut();
+    match setting {
+        Setting::Number(on) => window.number = on,
+        Setting::Wrap(on) => {
+            window.wrap = on;
+            window.col_offset = 0;
+            window.line_offset = 0;
         }
-        _ => {
-            config.last_match = None;
-            config.match_direction = true;
+        Setting::TabStop(tab_stop) => {
+            buffer.tab_stop = tab_stop;
+            editor_invalidate_rows(buffer, 0);
         }
+        Setting::ExpandTab(on) => buffer.expand_tab = on,
+        Setting::AutoIndent(on) => buffer.auto_indent = on,
     }
+}
 
-    if config.last_match.is_none() {
-        config.match_direction = true;
+/// Show a file in the current window, switching to its buffer if it's open already. A file that
+/// doesn't exist yet is created when the buffer is saved.
+fn editor_open_buffer(config: &mut EditorConfig, file_name: &str) {
+    let open = config
+        .buffers
+        .iter()
+        .position(|buffer| buffer.file_name.as_deref() == Some(file_name));
+    if let Some(open) = open {
+        editor_show_buffer(config, open);
+        return;
     }
 
-    let delta = if config.match_direction { 1isize } else { -1 };
-    let cur = mod_add(
-        config.last_match.unwrap_or(config.rows.len() - 1),
-        delta,
-        config.rows.len(),
-    );
-    let query = query.chars().collect::<Vec<_>>();
-    let Some((i, j)) = (0..config.rows.len()).find_map(|offset| {
-        let i = mod_add(cur, offset as isize * delta, config.rows.len());
-        let row = &config.rows[i];
-        if let Some(j) = row
-            .chars
-            .windows(query.len())
-            .position(|w| w == query.as_slice())
-        {
-            Some((i, j))
+    // the empty buffer the editor starts with is used rather than kept
+    let buffer = config.buffer();
+    let unused = buffer.file_name.is_none() && buffer.text.is_empty() && !buffer.dirty;
+    let previous = config.window().clone();
+    if !unused {
+        editor_new_buffer(config);
+    }
+    match editor_open(file_name, config) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let buffer = config.buffer_mut();
+            buffer.file_name = Some(file_name.to_string());
+            editor_select_syntax(buffer);
+            set_status_message(config, format!("{} [New File]", file_name).as_str());
+        }
+        Err(e) => {
+            if !unused {
+                config.buffers.pop();
+                *config.window_mut() = previous;
+            }
+            set_status_message(config, format!("Can't open {}: {}", file_name, e).as_str());
+        }
+    }
+}
+
+/// Pipe the rows the selection touches, or the whole buffer without one, through a shell
+/// command, and replace them with what it prints as a single edit. A command that fails leaves
+/// the text alone.
+fn editor_filter(config: &mut EditorConfig, command: &str) {
+    let num_rows = config.buffer().text.line_count();
+    let rows = match editor_selection(config.window()) {
+        // a selection that ends at the start of a row doesn't take it in
+        Some((start, end)) if end.cx == 0 && end.cy > start.cy => start.cy..end.cy,
+        Some((start, end)) => start.cy..(end.cy + 1).min(num_rows),
+        None => 0..num_rows,
+    };
+    let text = &config.buffer().text;
+    let (start, end) = (text.line_start(rows.start), text.line_start(rows.end));
+    let old = text.slice(start, end);
+    let mut input = old.clone();
+    let line_break = text.with_line_breaks(b"\n");
+    // commands expect lines to end in a line break, which the last row may not have
+    let unterminated = !input.is_empty() && !input.ends_with(b"\n");
+    if unterminated {
+        input.extend_from_slice(&line_break);
+    }
+
+    set_status_message(config, &format!("Running {}... (Esc to cancel)", command));
+    // a screen that can't be drawn now will be on the next refresh
+    let _ = refresh_screen(config);
+    let terminal = config.terminal.as_mut();
+    let output = match pipe_through(command, &input, || {
+        terminal.poll_byte() == Some(ESCAPE as u8)
+    }) {
+        Ok(Some(output)) => output,
+        Ok(None) => {
+            set_status_message(config, &format!("{} cancelled", command));
+            return;
+        }
+        Err(e) => {
+            set_status_message(config, format!("Can't run {}: {}", command, e).as_str());
+            return;
+        }
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.lines().find(|line| !line.trim().is_empty());
+    if !output.status.success() {
+        let status = match output.status.code() {
+            Some(code) => format!("{} exited with status {}", command, code),
+            None => format!("{} was killed", command),
+        };
+        let message = match stderr {
+            Some(stderr) => format!("{}: {}", status, stderr),
+            None => status,
+        };
+        set_status_message(config, &message);
+        return;
+    }
+
+    // the output replaces the rows byte for byte, so bytes that aren't UTF-8 and line breaks
+    // the command passed through come back as they were
+    let mut replacement = config.buffer().text.with_line_breaks(&output.stdout);
+    if unterminated {
+        if replacement.ends_with(&line_break) {
+            replacement.truncate(replacement.len() - line_break.len());
+        }
+    } else if !replacement.is_empty() && !replacement.ends_with(b"\n") {
+        replacement.extend_from_slice(&line_break);
+    }
+    let lines = replacement.iter().filter(|&&b| b == b'\n').count()
+        + usize::from(!replacement.is_empty() && !replacement.ends_with(b"\n"));
+    let at = rows.start;
+    if old != replacement {
+        // deleted and inserted in one command, so undone together
+        if !old.is_empty() {
+            editor_edit(config, Edit::DeleteBytes { at, bytes: old });
+        }
+        if !replacement.is_empty() {
+            editor_edit(
+                config,
+                Edit::InsertBytes {
+                    at,
+                    bytes: replacement,
+                },
+            );
+        }
+    }
+    (config.window_mut().cx, config.window_mut().cy) = (0, at);
+
+    let message = match stderr {
+        Some(stderr) => stderr.to_string(),
+        None => format!("{} lines filtered through {}", lines, command),
+    };
+    set_status_message(config, &message);
+}
+
+// endregion: commands
+
+// region: find
+
+/// Describe the search flags for a prompt
+fn search_flags_label(flags: u32) -> &'static str {
+    match (flags & CASE_INSENSITIVE != 0, flags & WHOLE_WORD != 0) {
+        (false, false) => "",
+        (true, false) => " (ignore case)",
+        (false, true) => " (whole word)",
+        (true, true) => " (ignore case, whole word)",
+    }
+}
+
+/// Flip a search flag if the key is one of the toggles, returning whether it was
+fn editor_toggle_search_flag(key: EditorKey, config: &mut EditorConfig) -> bool {
+    match key {
+        EditorKey::Char(CTRL_T) => config.search.flags ^= CASE_INSENSITIVE,
+        EditorKey::Char(CTRL_W) => config.search.flags ^= WHOLE_WORD,
+        _ => return false,
+    }
+    true
+}
+
+/// Find the nearest match starting from a position, in either direction, optionally wrapping
+/// around the ends of the file. Going forward a match may start at `from`, going backward it must
+/// start before it.
+fn editor_search(
+    config: &EditorConfig,
+    regex: &Regex,
+    from: Position,
+    forward: bool,
+    wrap: bool,
+) -> Option<(Position, Match)> {
+    let num_rows = config.buffer().text.line_count();
+    if num_rows == 0 {
+        return None;
+    }
+
+    for offset in 0..=num_rows {
+        let delta = if forward {
+            offset as isize
         } else {
-            None
+            -(offset as isize)
+        };
+        if !wrap && !(0..num_rows as isize).contains(&(from.cy as isize + delta)) {
+            return None;
         }
-    }) else {
+        let cy = mod_add(from.cy, delta, num_rows);
+
+        // the last row searched is the one we started on, for what's left of it
+        let first = offset == 0;
+        let wrapped = offset == num_rows;
+        let chars = config.buffer().text.line(cy).chars().collect::<Vec<_>>();
+        let found = if forward {
+            regex
+                .find_at(&chars, if first { from.cx } else { 0 })
+                .filter(|m| !wrapped || m.start() < from.cx)
+        } else {
+            regex
+                .find_iter(&chars)
+                .filter(|m| (!first || m.start() < from.cx) && (!wrapped || m.start() >= from.cx))
+                .last()
+        };
+
+        if let Some(m) = found {
+            return Some((Position::new(m.start(), cy), m));
+        }
+    }
+
+    None
+}
+
+fn editor_find_callback(query: &str, key: EditorKey, config: &mut EditorConfig) {
+    match key {
+        EditorKey::Char(ESCAPE) | EditorKey::Char(CR) => {
+            config.search.regex = None;
+            config.search.last_match = None;
+            return;
+        }
+        EditorKey::Arrow(ArrowDirection::Down | ArrowDirection::Right, _) => {
+            config.search.forward = true;
+        }
+        EditorKey::Arrow(ArrowDirection::Up | ArrowDirection::Left, _) => {
+            config.search.forward = false;
+        }
+        _ => {
+            editor_toggle_search_flag(key, config);
+            config.search.last_match = None;
+            config.search.forward = true;
+        }
+    }
+
+    config.search.regex = None;
+    config.search.error = None;
+    if query.is_empty() {
         return;
+    }
+    let regex = match Regex::new(query, config.search.flags) {
+        Ok(regex) => regex,
+        Err(e) => {
+            config.search.error = Some(e.to_string());
+            return;
+        }
     };
 
-    // matched
-    config.last_match = Some(i);
-    config.cy = i;
-    config.cx = j;
-    config.row_offset = config.rows.len();
+    // step on from the last match, or start at the cursor as it was before searching
+    let from = match config.search.last_match {
+        Some(at) if config.search.forward => Position::new(at.cx + 1, at.cy),
+        Some(at) => at,
+        None => config.search.origin,
+    };
+    if let Some((at, _)) = editor_search(config, &regex, from, config.search.forward, true) {
+        config.search.last_match = Some(at);
+        config.window_mut().cy = at.cy;
+        config.window_mut().cx = at.cx;
+        config.window_mut().row_offset = config.buffer().text.line_count();
+    }
+    config.search.regex = Some(regex);
 }
 
 fn mod_add(a: usize, b: isize, modulus: usize) -> usize {
@@ -558,99 +1655,367 @@ fn mod_add(a: usize, b: isize, modulus: usize) -> usize {
 }
 
 fn editor_find(config: &mut EditorConfig) {
-    let original_cx = config.cx;
-    let original_cy = config.cy;
-    let original_col_offset = config.col_offset;
-    let original_row_offset = config.row_offset;
+    let original_cx = config.window().cx;
+    let original_cy = config.window().cy;
+    let original_col_offset = config.window().col_offset;
+    let original_row_offset = config.window().row_offset;
+    config.search.origin = config.window().cursor();
 
     if editor_prompt(
-        |query| format!("Search: {} (Use ESC/Arrows/Enter)", query),
-        |query, key, config| editor_find_callback(query, key, config),
+        |query, config| {
+            format!(
+                "Search{}: {} (ESC/Arrows/Enter, ^T case, ^W word){}",
+                search_flags_label(config.search.flags),
+                query,
+                config
+                    .search
+                    .error
+                    .as_ref()
+                    .map_or(String::new(), |e| format!(" [{}]", e))
+            )
+        },
+        editor_find_callback,
         config,
     )
     .is_none()
     {
-        config.cx = original_cx;
-        config.cy = original_cy;
-        config.col_offset = original_col_offset;
-        config.row_offset = original_row_offset;
+        config.window_mut().cx = original_cx;
+        config.window_mut().cy = original_cy;
+        config.window_mut().col_offset = original_col_offset;
+        config.window_mut().row_offset = original_row_offset;
+    }
+}
+
+/// Replace matches of a pattern from the cursor on, wrapping around to where it started, asking
+/// before each one. Captures can be used in the replacement as `$1` or `${1}`.
+fn editor_replace(config: &mut EditorConfig) {
+    let Some(pattern) = editor_prompt(
+        |pattern, config| {
+            format!(
+                "Replace{}: {} (ESC to cancel, ^T case, ^W word)",
+                search_flags_label(config.search.flags),
+                pattern
+            )
+        },
+        |_, key, config| {
+            editor_toggle_search_flag(key, config);
+        },
+        config,
+    )
+    .filter(|pattern| !pattern.is_empty()) else {
+        set_status_message(config, "Replace aborted");
+        return;
+    };
+    let regex = match Regex::new(&pattern, config.search.flags) {
+        Ok(regex) => regex,
+        Err(e) => {
+            set_status_message(config, format!("Invalid pattern: {}", e).as_str());
+            return;
+        }
+    };
+    let Some(replacement) = editor_prompt(
+        |replacement, _| {
+            format!(
+                "Replace with: {} ($1 for a group, ESC to cancel)",
+                replacement
+            )
+        },
+        |_, _, _| (),
+        config,
+    ) else {
+        set_status_message(config, "Replace aborted");
+        return;
+    };
+
+    config.search.regex = Some(regex.clone());
+    let mut origin = config.window().cursor();
+    let mut from = origin;
+    let mut wrapped = false;
+    let mut replace_all = false;
+    let mut count = 0;
+
+    loop {
+        let found = editor_search(config, &regex, from, true, false)
+            .filter(|(at, _)| !wrapped || (at.cy, at.cx) < (origin.cy, origin.cx));
+        let Some((at, m)) = found else {
+            if wrapped || (origin.cy, origin.cx) == (0, 0) {
+                break;
+            }
+            wrapped = true;
+            from = Position::new(0, 0);
+            continue;
+        };
+
+        config.window_mut().cx = at.cx;
+        config.window_mut().cy = at.cy;
+        let chars = config.buffer().text.line(at.cy).chars().collect::<Vec<_>>();
+        // move past an empty match so that the next search doesn't find it again
+        let skip = Position::new(m.end() + usize::from(m.range().is_empty()), at.cy);
+
+        if !replace_all {
+            let key = editor_confirm(config, "Replace this match? (y)es (n)o (a)ll (q)uit");
+            match key {
+                EditorKey::Char('y') => {}
+                EditorKey::Char('a') => replace_all = true,
+                EditorKey::Char('n') => {
+                    from = skip;
+                    continue;
+                }
+                _ => break,
+            }
+        }
+
+        let old = chars[m.range()].iter().collect::<String>();
+        let new = m.expand(&chars, &replacement);
+        if !old.is_empty() {
+            editor_edit(config, Edit::Delete { at, text: old });
+        }
+        if !new.is_empty() {
+            editor_edit(
+                config,
+                Edit::Insert {
+                    at,
+                    text: new.clone(),
+                },
+            );
+        }
+        count += 1;
+
+        let new_len = new.chars().count();
+        if wrapped && at.cy == origin.cy {
+            // the replacement shifted where we started from
+            origin.cx = (origin.cx + new_len).saturating_sub(m.range().len());
+        }
+        config.window_mut().cx = at.cx + new_len;
+        from = Position::new(
+            config.window().cx + usize::from(m.range().is_empty()),
+            at.cy,
+        );
     }
+
+    config.search.regex = None;
+    set_status_message(
+        config,
+        format!(
+            "Replaced {} occurrence{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        )
+        .as_str(),
+    );
 }
 
 // endregion: find
 
 // region: row operations
 
-fn map_row_cx_to_rx(row: &EditorRow, cx: usize) -> usize {
+/// Screen columns taken by the cluster `g` of `chars`, starting at column `col`
+fn cluster_width(chars: &[char], g: Range<usize>, col: usize, tab_stop: usize) -> usize {
+    if chars[g.start] == '\t' {
+        tab_stop - (col % tab_stop)
+    } else {
+        grapheme_width(&chars[g])
+    }
+}
+
+/// Screen column of the char at `cx`
+fn map_row_cx_to_rx(row: &EditorRow, cx: usize, tab_stop: usize) -> usize {
+    let chars = &row.chars[..cx];
     let mut rx = 0;
-    for i in 0..cx {
-        if row.chars[i] == '\t' {
-            rx = rx + TAB_STOP - (rx % TAB_STOP);
-        } else {
-            rx += 1;
-        }
+    for g in graphemes(chars) {
+        rx += cluster_width(chars, g, rx, tab_stop);
     }
 
     rx
 }
 
-fn update_row(row: &mut EditorRow) {
-    row.render.clear();
-    for i in 0..row.chars.len() {
-        if row.chars[i] == '\t' {
-            while row.render.len() % TAB_STOP != 0 {
-                row.render.push(' ');
-            }
+/// Index of the char drawn at screen column `rx`, or the length of the row past its end
+fn map_row_rx_to_cx(row: &EditorRow, rx: usize, tab_stop: usize) -> usize {
+    let mut col = 0;
+    for g in graphemes(&row.chars) {
+        let start = g.start;
+        col += cluster_width(&row.chars, g, col, tab_stop);
+        if col > rx {
+            return start;
+        }
+    }
+
+    row.chars.len()
+}
+
+/// Index in `render` of the char at `cx`
+fn map_row_cx_to_render(row: &EditorRow, cx: usize, tab_stop: usize) -> usize {
+    render_chars(&row.chars[..cx], tab_stop).len()
+}
+
+/// Chars as drawn, with tabs expanded to spaces up to the next tab stop
+fn render_chars(chars: &[char], tab_stop: usize) -> Vec<char> {
+    let mut render = vec![];
+    let mut col = 0;
+    for g in graphemes(chars) {
+        let width = cluster_width(chars, g.clone(), col, tab_stop);
+        if chars[g.start] == '\t' {
+            render.extend(std::iter::repeat_n(' ', width));
         } else {
-            row.render.push(row.chars[i]);
+            render.extend_from_slice(&chars[g]);
         }
+        col += width;
     }
+
+    render
 }
 
-fn editor_insert_row(at: usize, line: String, config: &mut EditorConfig) {
-    if at > config.rows.len() {
-        return;
+fn update_row(row: &mut EditorRow, tab_stop: usize) {
+    row.render = render_chars(&row.chars, tab_stop);
+}
+
+/// The rendered row at `at`, building it from the buffer if it isn't cached
+fn editor_row(buffer: &mut EditorBuffer, at: usize) -> &EditorRow {
+    if !buffer.rows.contains_key(&at) {
+        let in_comment = editor_in_comment(buffer, at);
+        let mut row = EditorRow::new(buffer.text.line(at).chars().collect());
+        update_row(&mut row, buffer.tab_stop);
+        let (hl, open_comment) = highlight_row(&row.render, buffer.syntax, in_comment);
+        row.hl = hl;
+        buffer.hl_open_comment.insert(at, open_comment);
+        buffer.rows.insert(at, row);
     }
 
-    let mut row = EditorRow::new(line.trim_end().chars().collect());
-    update_row(&mut row);
-    config.rows.insert(at, row);
-    config.dirty = true;
+    &buffer.rows[&at]
 }
 
-fn editor_del_row(config: &mut EditorConfig, at: usize) -> Option<EditorRow> {
-    if at >= config.rows.len() {
-        return None;
+/// Number of chars in the row at `at`, or 0 past the last row
+fn row_len(buffer: &mut EditorBuffer, at: usize) -> usize {
+    if at < buffer.text.line_count() {
+        editor_row(buffer, at).chars.len()
+    } else {
+        0
     }
+}
 
-    let row = config.rows.remove(at);
+/// Whether the row at `at` starts inside an unclosed multi-line comment. Rows above it that
+/// haven't been highlighted yet are highlighted to find out, but not kept, going back no further
+/// than `COMMENT_SYNC_ROWS` so that jumping into a large file doesn't highlight all of it.
+fn editor_in_comment(buffer: &mut EditorBuffer, at: usize) -> bool {
+    if at == 0
+        || buffer
+            .syntax
+            .is_none_or(|syntax| syntax.multi_line_comment.is_none())
+    {
+        return false;
+    }
 
-    config.dirty = true;
+    // carry on from the nearest row known to end in or out of a comment, or else assume the
+    // earliest row looked at starts outside one
+    let sync = at.saturating_sub(COMMENT_SYNC_ROWS);
+    let (start, mut in_comment) = match buffer.hl_open_comment.range(sync..at).next_back() {
+        Some((&i, &open_comment)) => (i + 1, open_comment),
+        None => (sync, false),
+    };
 
-    Some(row)
+    for i in start..at {
+        let mut row = EditorRow::new(buffer.text.line(i).chars().collect());
+        update_row(&mut row, buffer.tab_stop);
+        let (_, open_comment) = highlight_row(&row.render, buffer.syntax, in_comment);
+        buffer.hl_open_comment.insert(i, open_comment);
+        in_comment = open_comment;
+    }
+
+    in_comment
+}
+
+/// Forget the rendering of every row from `at` on, after the buffer changed there
+fn editor_invalidate_rows(buffer: &mut EditorBuffer, at: usize) {
+    buffer.version += 1;
+    buffer.rows.retain(|i, _| *i < at);
+    buffer.hl_open_comment.split_off(&at);
 }
 
-fn row_insert_char(row: &mut EditorRow, at: usize, c: char, dirty: &mut bool) {
-    let at = at.min(row.chars.len());
-    row.chars.insert(at, c);
-    update_row(row);
-    *dirty = true;
+fn editor_select_syntax(buffer: &mut EditorBuffer) {
+    buffer.syntax = buffer.file_name.as_deref().and_then(select_syntax);
+    editor_invalidate_rows(buffer, 0);
 }
 
-fn editor_row_append_string(row: &mut EditorRow, s: &mut Vec<char>, dirty: &mut bool) {
-    row.chars.append(s);
-    update_row(row);
-    *dirty = true;
+/// Byte offset in the buffer of a position
+fn editor_offset(buffer: &EditorBuffer, at: Position) -> usize {
+    buffer.text.offset(at.cy, at.cx)
 }
 
-fn row_del_char(row: &mut EditorRow, at: usize, dirty: &mut bool) {
-    if at >= row.chars.len() {
+fn editor_insert_row(at: usize, line: String, buffer: &mut EditorBuffer) {
+    if at > buffer.text.line_count() {
         return;
     }
 
-    row.chars.remove(at);
-    update_row(row);
-    *dirty = true;
+    buffer.text.insert_row(at, &line);
+    editor_invalidate_rows(buffer, at);
+}
+
+fn editor_del_row(buffer: &mut EditorBuffer, at: usize) {
+    if at >= buffer.text.line_count() {
+        return;
+    }
+
+    buffer.text.delete_row(at);
+    editor_invalidate_rows(buffer, at);
+}
+
+/// Insert text, which may contain newlines, at a position within an existing row
+fn editor_insert_text(buffer: &mut EditorBuffer, at: Position, text: &str) {
+    let offset = editor_offset(buffer, at);
+    buffer.text.insert(offset, text);
+    editor_invalidate_rows(buffer, at.cy);
+}
+
+/// Delete text, which may span rows, starting at a position
+fn editor_delete_text(buffer: &mut EditorBuffer, at: Position, text: &str) {
+    // the text was decoded from the buffer, so its length in bytes may not be what's there
+    let offset = editor_offset(buffer, at);
+    let end = editor_offset(buffer, at.after(text));
+    buffer.text.delete(offset, end - offset);
+    editor_invalidate_rows(buffer, at.cy);
+}
+
+/// Insert bytes exactly as they are at the start of row `at`
+fn editor_insert_bytes(buffer: &mut EditorBuffer, at: usize, bytes: &[u8]) {
+    let offset = buffer.text.line_start(at);
+    buffer.text.insert_bytes(offset, bytes);
+    editor_invalidate_rows(buffer, at);
+}
+
+/// Delete `len` bytes from the start of row `at`
+fn editor_delete_bytes(buffer: &mut EditorBuffer, at: usize, len: usize) {
+    let offset = buffer.text.line_start(at);
+    buffer.text.delete(offset, len);
+    editor_invalidate_rows(buffer, at);
+}
+
+fn apply_edit(config: &mut EditorConfig, edit: &Edit) {
+    // the text moves out from under the mark in every window on the buffer
+    let current = config.window().buffer;
+    for window in config.windows.iter_mut().filter(|w| w.buffer == current) {
+        window.mark = None;
+    }
+
+    let buffer = config.buffer_mut();
+    match edit {
+        Edit::Insert { at, text } => editor_insert_text(buffer, *at, text),
+        Edit::Delete { at, text } => editor_delete_text(buffer, *at, text),
+        Edit::InsertRow { at, text } => editor_insert_row(*at, text.clone(), buffer),
+        Edit::DeleteRow { at, .. } => {
+            editor_del_row(buffer, *at);
+        }
+        Edit::InsertBytes { at, bytes } => editor_insert_bytes(buffer, *at, bytes),
+        Edit::DeleteBytes { at, bytes } => editor_delete_bytes(buffer, *at, bytes.len()),
+    }
+}
+
+/// Apply an edit and record it in the undo history
+fn editor_edit(config: &mut EditorConfig, edit: Edit) {
+    apply_edit(config, &edit);
+    let cursor = config.window().cursor();
+    let buffer = config.buffer_mut();
+    buffer.history.record(edit, cursor);
+    buffer.dirty = buffer.history.is_modified();
 }
 
 // endregion: row operations
@@ -658,111 +2023,1010 @@ fn row_del_char(row: &mut EditorRow, at: usize, dirty: &mut bool) {
 // region: editor operations
 
 fn editor_insert_char(config: &mut EditorConfig, c: char) {
-    if config.cy == config.rows.len() {
-        editor_insert_row(config.rows.len(), "".to_string(), config);
+    editor_insert(config, &c.to_string());
+}
+
+/// Insert text without line breaks at the cursor, moving the cursor past it
+fn editor_insert(config: &mut EditorConfig, text: &str) {
+    if config.window().cy == config.buffer().text.line_count() {
+        let at = config.window().cy;
+        editor_edit(
+            config,
+            Edit::InsertRow {
+                at,
+                text: String::new(),
+            },
+        );
     }
-    row_insert_char(&mut config.rows[config.cy], config.cx, c, &mut config.dirty);
-    config.cx += 1;
+    let at = config.window().cursor();
+    editor_edit(
+        config,
+        Edit::Insert {
+            at,
+            text: text.to_string(),
+        },
+    );
+    config.window_mut().cx += text.chars().count();
+}
+
+/// Insert a tab, or with `expandtab` the spaces up to the next tab stop
+fn editor_insert_tab(config: &mut EditorConfig) {
+    let (window, buffer) = config.view_mut();
+    if !buffer.expand_tab {
+        editor_insert_char(config, '\t');
+        return;
+    }
+
+    let tab_stop = buffer.tab_stop;
+    let rx = if window.cy < buffer.text.line_count() {
+        map_row_cx_to_rx(editor_row(buffer, window.cy), window.cx, tab_stop)
+    } else {
+        0
+    };
+    editor_insert(config, &" ".repeat(tab_stop - rx % tab_stop));
+}
+
+/// The indentation for a line broken off at `at`: that of the row it's broken from, as far as
+/// `at`, and a level more after an opening brace or a colon
+fn editor_indent(buffer: &mut EditorBuffer, at: Position) -> String {
+    if !buffer.auto_indent || at.cy >= buffer.text.line_count() {
+        return String::new();
+    }
+
+    let level = if buffer.expand_tab {
+        " ".repeat(buffer.tab_stop)
+    } else {
+        "\t".to_string()
+    };
+    let chars = &editor_row(buffer, at.cy).chars[..at.cx];
+    let mut indent = chars
+        .iter()
+        .take_while(|&&c| c == ' ' || c == '\t')
+        .collect::<String>();
+    if matches!(
+        chars.iter().rev().find(|c| !c.is_whitespace()),
+        Some('{' | ':')
+    ) {
+        indent.push_str(&level);
+    }
+
+    indent
 }
 
 fn editor_insert_new_line(config: &mut EditorConfig) {
-    if config.cx == 0 {
-        editor_insert_row(config.cy, "".to_string(), config);
+    let at = config.window().cursor();
+    let indent = editor_indent(config.buffer_mut(), at);
+    let edit = if at.cy == config.buffer().text.line_count() {
+        // past the last row, which is as good as empty, it becomes a row of its own
+        Edit::InsertRow {
+            at: at.cy,
+            text: String::new(),
+        }
+    } else {
+        Edit::Insert {
+            at,
+            text: format!("{}{}", LF, indent),
+        }
+    };
+    editor_edit(config, edit);
+    config.window_mut().cy += 1;
+    config.window_mut().cx = indent.chars().count();
+}
+
+/// The bracket that pairs with `c`, and whether `c` opens the pair
+fn bracket_pair(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
+/// Where the bracket matching the one at `at` is, skipping over nested pairs of the same kind.
+/// Brackets in strings and comments count as much as any others.
+fn editor_matching_bracket(buffer: &mut EditorBuffer, at: Position) -> Option<Position> {
+    if at.cy >= buffer.text.line_count() {
+        return None;
+    }
+    let c = *editor_row(buffer, at.cy).chars.get(at.cx)?;
+    let (pair, forward) = bracket_pair(c)?;
+
+    // whether the char at `cx` closes the pair, counting those nested in it
+    let mut depth = 0;
+    let mut closes = |chars: &[char], cx: usize| {
+        if chars[cx] == c {
+            depth += 1;
+        } else if chars[cx] == pair {
+            depth -= 1;
+        }
+        chars[cx] == pair && depth == 0
+    };
+
+    if forward {
+        let end = buffer.text.line_count().min(at.cy + BRACKET_SCAN_ROWS);
+        for cy in at.cy..end {
+            let chars = buffer.text.line(cy).chars().collect::<Vec<_>>();
+            let start = if cy == at.cy { at.cx } else { 0 };
+            if let Some(cx) = (start..chars.len()).find(|&cx| closes(&chars, cx)) {
+                return Some(Position::new(cx, cy));
+            }
+        }
     } else {
-        let row = &mut config.rows[config.cy];
-        let new_row = row.chars.split_off(config.cx);
-        update_row(row);
-        editor_insert_row(config.cy + 1, new_row.iter().collect(), config);
+        for cy in (at.cy.saturating_sub(BRACKET_SCAN_ROWS)..=at.cy).rev() {
+            let chars = buffer.text.line(cy).chars().collect::<Vec<_>>();
+            let end = if cy == at.cy { at.cx + 1 } else { chars.len() };
+            if let Some(cx) = (0..end).rev().find(|&cx| closes(&chars, cx)) {
+                return Some(Position::new(cx, cy));
+            }
+        }
     }
-    config.cy += 1;
-    config.cx = 0;
+
+    None
 }
 
 fn editor_del_char(config: &mut EditorConfig) {
-    if config.cy == config.rows.len() {
+    if config.window().cy == config.buffer().text.line_count() {
         return;
     }
-    if config.cx == 0 && config.cy == 0 {
+    if config.window().cx == 0 && config.window().cy == 0 {
         return;
     }
 
-    if config.cx > 0 {
-        let row = &mut config.rows[config.cy];
-        row_del_char(row, config.cx - 1, &mut config.dirty);
-        config.cx -= 1;
+    if config.window().cx > 0 {
+        // delete the whole cluster before the cursor
+        let (window, buffer) = config.view_mut();
+        let cx = window.cx;
+        let chars = &editor_row(buffer, window.cy).chars;
+        let start = prev_grapheme_boundary(chars, cx);
+        let text = chars[start..cx].iter().collect();
+        let at = Position::new(start, config.window().cy);
+        editor_edit(config, Edit::Delete { at, text });
+        config.window_mut().cx = start;
     } else {
-        config.cx = config.rows[config.cy - 1].chars.len();
-        let Some(mut row) = editor_del_row(config, config.cy) else {
-            return;
-        };
-
-        editor_row_append_string(
-            &mut config.rows[config.cy - 1],
-            &mut row.chars,
-            &mut config.dirty,
+        // join this row onto the end of the previous one
+        let cy = config.window().cy - 1;
+        let at = Position::new(row_len(config.buffer_mut(), cy), cy);
+        editor_edit(
+            config,
+            Edit::Delete {
+                at,
+                text: LF.to_string(),
+            },
         );
-        config.cy -= 1;
+        config.window_mut().cx = at.cx;
+        config.window_mut().cy = at.cy;
     }
 }
 
+fn editor_undo(config: &mut EditorConfig) {
+    let Some((edits, cursor)) = config.buffer_mut().history.undo() else {
+        set_status_message(config, "Already at oldest change");
+        return;
+    };
+    for edit in &edits {
+        apply_edit(config, edit);
+    }
+    config.window_mut().cx = cursor.cx;
+    config.window_mut().cy = cursor.cy;
+    let buffer = config.buffer_mut();
+    buffer.dirty = buffer.history.is_modified();
+}
+
+fn editor_redo(config: &mut EditorConfig) {
+    let Some((edits, cursor)) = config.buffer_mut().history.redo() else {
+        set_status_message(config, "Already at newest change");
+        return;
+    };
+    for edit in &edits {
+        apply_edit(config, edit);
+    }
+    config.window_mut().cx = cursor.cx;
+    config.window_mut().cy = cursor.cy;
+    let buffer = config.buffer_mut();
+    buffer.dirty = buffer.history.is_modified();
+}
+
 // endregion: editor operations
 
-// region: terminal
-
-fn read_key() -> EditorKey {
-    let stdin = std::io::stdin();
-    let mut handle = stdin.lock();
-    let mut buffer = [0; 1];
-    buffer[0] = '\0' as u8;
-    while !handle.read(&mut buffer).is_ok_and(|n| n == 1) {}
-    let c = buffer[0] as char;
-
-    // Escape sequence
-    if c == ESCAPE {
-        let mut seq = [0; 2];
-        if handle.read(&mut seq).is_ok_and(|n| n == 2) {
-            match seq[0] {
-                b'[' => {
-                    let mut seq2 = [0; 1];
-                    match seq[1] {
-                        b'A' => EditorKey::Arrow(ArrowDirection::Up),
-                        b'B' => EditorKey::Arrow(ArrowDirection::Down),
-                        b'C' => EditorKey::Arrow(ArrowDirection::Right),
-                        b'D' => EditorKey::Arrow(ArrowDirection::Left),
-                        b'H' => EditorKey::Home,
-                        b'F' => EditorKey::End,
-                        b'1' | b'3' | b'4' | b'5' | b'6' | b'7' | b'8'
-                            if handle.read(&mut seq2).is_ok_and(|n| n == 1) && seq2[0] == b'~' =>
-                        {
-                            match seq[1] {
-                                b'1' | b'7' => EditorKey::Home,
-                                b'4' | b'8' => EditorKey::End,
-                                b'3' => EditorKey::Del,
-                                b'5' => EditorKey::Page(PageDirection::Up),
-                                b'6' => EditorKey::Page(PageDirection::Down),
-                                _ => unreachable!(),
-                            }
-                        }
-                        _ => EditorKey::Char(ESCAPE),
-                    }
-                }
-                b'O' => match seq[1] {
-                    b'H' => EditorKey::Home,
-                    b'F' => EditorKey::End,
-                    _ => EditorKey::Char(ESCAPE),
-                },
-                _ => EditorKey::Char(ESCAPE),
-            }
-        } else {
-            EditorKey::Char(ESCAPE)
+// region: clipboard
+
+fn editor_toggle_mark(config: &mut EditorConfig) {
+    if config.window_mut().mark.take().is_some() {
+        set_status_message(config, "Mark cleared");
+    } else {
+        config.window_mut().mark = Some(config.window().cursor());
+        config.window_mut().shift_selection = false;
+        set_status_message(config, "Mark set");
+    }
+}
+
+/// The selected region, from whichever of the mark and the cursor comes first
+fn editor_selection(window: &EditorWindow) -> Option<(Position, Position)> {
+    let mark = window.mark?;
+    let cursor = window.cursor();
+    if (mark.cy, mark.cx) <= (cursor.cy, cursor.cx) {
+        Some((mark, cursor))
+    } else {
+        Some((cursor, mark))
+    }
+}
+
+/// The region cut or copied: the selection, or else the cursor's row and its line break
+fn editor_kill_region(config: &EditorConfig) -> Option<(Position, Position)> {
+    match editor_selection(config.window()) {
+        Some(selection) => Some(selection),
+        None if config.window().cy < config.buffer().text.line_count() => Some((
+            Position::new(0, config.window().cy),
+            Position::new(0, config.window().cy + 1),
+        )),
+        None => None,
+    }
+}
+
+/// The text between two positions
+fn editor_text(config: &mut EditorConfig, start: Position, end: Position) -> String {
+    let buffer = config.buffer();
+    let start = editor_offset(buffer, start);
+    let end = editor_offset(buffer, end);
+    buffer.text.text(start, end)
+}
+
+/// Put text in the kill ring, adding it to the newest kill if `append`
+fn editor_kill(config: &mut EditorConfig, text: &str, append: bool) {
+    if append {
+        config.kill_ring.append(text);
+    } else {
+        config.kill_ring.push(text.to_string());
+    }
+
+    if config.settings.osc52 {
+        if let Some(kill) = config.kill_ring.yank() {
+            _ = config.terminal.write_all(osc52_copy(kill).as_bytes());
         }
+    }
+}
+
+fn editor_copy(config: &mut EditorConfig) {
+    let Some((start, end)) = editor_kill_region(config) else {
+        return;
+    };
+    let text = editor_text(config, start, end);
+    if text.is_empty() {
+        return;
+    }
+
+    editor_kill(config, &text, false);
+    config.window_mut().mark = None;
+    set_status_message(config, format!("Copied {} bytes", text.len()).as_str());
+}
+
+/// Cut the selection, or the cursor's row if nothing is selected. Rows cut one after another
+/// are killed together, so they can be moved as a block.
+fn editor_cut(config: &mut EditorConfig, cutting_rows: bool) {
+    let whole_row = config.window().mark.is_none();
+    let Some((start, end)) = editor_kill_region(config) else {
+        return;
+    };
+    let text = editor_text(config, start, end);
+    if text.is_empty() {
+        return;
+    }
+
+    editor_kill(config, &text, whole_row && cutting_rows);
+    editor_edit(config, Edit::Delete { at: start, text });
+    config.window_mut().cx = start.cx;
+    config.window_mut().cy = start.cy;
+    config.cutting_rows = whole_row;
+}
+
+/// Insert the newest kill at the cursor
+fn editor_yank(config: &mut EditorConfig) {
+    let Some(text) = config.kill_ring.yank().map(str::to_string) else {
+        set_status_message(config, "Kill ring is empty");
+        return;
+    };
+    editor_insert_yank(config, text);
+}
+
+/// Replace the text just yanked with the kill before it
+fn editor_yank_pop(config: &mut EditorConfig, yanked: Option<(Position, Position)>) {
+    let Some((start, end)) = yanked else {
+        set_status_message(config, "Previous command was not a yank");
+        return;
+    };
+    let Some(text) = config.kill_ring.yank_pop().map(str::to_string) else {
+        return;
+    };
+
+    let old = editor_text(config, start, end);
+    editor_edit(
+        config,
+        Edit::Delete {
+            at: start,
+            text: old,
+        },
+    );
+    config.window_mut().cx = start.cx;
+    config.window_mut().cy = start.cy;
+    editor_insert_yank(config, text);
+}
+
+fn editor_insert_yank(config: &mut EditorConfig, text: String) {
+    let last = config.buffer().text.line_count();
+    if config.window().cy == last
+        && last > 0
+        && config.buffer().text.line_end(last - 1) == config.buffer().text.len()
+    {
+        // the last row has no line break to put the text after
+        let at = Position::new(row_len(config.buffer_mut(), last - 1), last - 1);
+        editor_edit(
+            config,
+            Edit::Insert {
+                at,
+                text: LF.to_string(),
+            },
+        );
+    }
+
+    let at = config.window().cursor();
+    let edit = Edit::Insert { at, text };
+    let end = edit.end();
+    editor_edit(config, edit);
+    config.window_mut().cx = end.cx;
+    config.window_mut().cy = end.cy;
+    config.yanked = Some((at, end));
+}
+
+// endregion: clipboard
+
+// region: windows
+
+/// Add an empty buffer and show it in the current window
+fn editor_new_buffer(config: &mut EditorConfig) {
+    config.buffers.push(EditorBuffer::new(&config.settings));
+    editor_show_buffer(config, config.buffers.len() - 1);
+}
+
+/// Show a buffer in the current window, from the top
+fn editor_show_buffer(config: &mut EditorConfig, buffer: usize) {
+    if config.window().buffer != buffer {
+        let window = config.window_mut();
+        // the options belong to the window, whatever it shows
+        *window = EditorWindow {
+            number: window.number,
+            wrap: window.wrap,
+            ..EditorWindow::new(buffer)
+        };
+    }
+}
+
+/// List the open buffers and switch to one by number
+fn editor_switch_buffer(config: &mut EditorConfig) {
+    let list = config
+        .buffers
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| {
+            let file_name = buffer.file_name.as_deref().unwrap_or("[No Name]");
+            let modified = if buffer.dirty { "+" } else { "" };
+            format!("{}:{}{}", i + 1, file_name, modified)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let Some(answer) = editor_prompt(
+        |answer, _| format!("{} | Buffer: {}", list, answer),
+        |_, _, _| (),
+        config,
+    ) else {
+        return;
+    };
+
+    match answer.trim().parse::<usize>() {
+        Ok(n) if (1..=config.buffers.len()).contains(&n) => editor_show_buffer(config, n - 1),
+        _ => set_status_message(config, format!("No buffer {}", answer).as_str()),
+    }
+}
+
+/// The window commands that follow Ctrl-W
+fn editor_window_command(config: &mut EditorConfig) {
+    let question = "Window: s = split, v = split vertically, w = next, c = close";
+    match editor_confirm(config, question) {
+        EditorKey::Char('s') => editor_split(config, false),
+        EditorKey::Char('v') => editor_split(config, true),
+        EditorKey::Char('w' | CTRL_W) => {
+            config.current = (config.current + 1) % config.windows.len();
+        }
+        EditorKey::Char('c') => editor_close_window(config),
+        _ => {}
+    }
+}
+
+/// Split the current window in two, moving to the new half, which starts with the same view
+fn editor_split(config: &mut EditorConfig, vertical: bool) {
+    let window = config.window();
+    // each half needs a column, or a row and a status bar
+    let too_small = if vertical {
+        window.screen_cols < 3
     } else {
-        if c == CTRL_H || c == BACKSPACE {
-            EditorKey::Backspace
+        window.screen_rows < 3
+    };
+    if too_small {
+        set_status_message(config, "Window too small to split");
+        return;
+    }
+
+    let new = EditorWindow {
+        mark: None,
+        shift_selection: false,
+        ..window.clone()
+    };
+    config.windows.push(new);
+    let new = config.windows.len() - 1;
+    config.layout.split(config.current, new, vertical);
+    config.current = new;
+}
+
+fn editor_close_window(config: &mut EditorConfig) {
+    if config.windows.len() == 1 {
+        set_status_message(config, "Can't close the last window");
+        return;
+    }
+
+    let current = config.current;
+    config.windows.remove(current);
+    let layout = std::mem::replace(&mut config.layout, Layout::Window(0));
+    config.layout = layout
+        .remove(current)
+        .expect("other windows are left in the layout");
+    config.current = current.saturating_sub(1);
+}
+
+// endregion: windows
+
+// region: mouse
+
+fn editor_mouse(config: &mut EditorConfig, mouse: Mouse) {
+    let Some(w) = config.windows.iter().position(|window| {
+        (window.top..window.top + window.screen_rows).contains(&mouse.row)
+            && (window.left..window.left + window.screen_cols).contains(&mouse.col)
+    }) else {
+        return;
+    };
+    let (y, x) = (
+        mouse.row - config.windows[w].top,
+        mouse.col - config.windows[w].left,
+    );
+
+    match mouse.action {
+        MouseAction::Press(MouseButton::Left) => {
+            config.current = w;
+            config.window_mut().mark = None;
+            editor_click(config, y, x);
+        }
+        // dragging selects from where the button was pressed
+        MouseAction::Drag(MouseButton::Left) if w == config.current => {
+            if config.window().mark.is_none() {
+                config.window_mut().mark = Some(config.window().cursor());
+                config.window_mut().shift_selection = true;
+            }
+            editor_click(config, y, x);
+        }
+        MouseAction::ScrollUp | MouseAction::ScrollDown => {
+            let window = &mut config.windows[w];
+            let buffer = &mut config.buffers[window.buffer];
+            editor_wheel(window, buffer, mouse.action == MouseAction::ScrollDown);
+        }
+        _ => {}
+    }
+}
+
+/// Move the cursor of the current window to what's drawn at line `y` and column `x` of it
+fn editor_click(config: &mut EditorConfig, y: usize, x: usize) {
+    let (window, buffer) = config.view_mut();
+    let x = x.saturating_sub(gutter_width(window, buffer));
+    let at = editor_position_at(window, buffer, y, x);
+    (window.cx, window.cy) = (at.cx, at.cy);
+}
+
+/// The row, and the screen line of it when wrapping, shown `y` lines down a window, or the last
+/// there is if the text doesn't reach that far
+fn editor_line_at(window: &EditorWindow, buffer: &mut EditorBuffer, y: usize) -> (usize, usize) {
+    let num_rows = buffer.text.line_count();
+    if !window.wrap {
+        return ((window.row_offset + y).min(num_rows), 0);
+    }
+
+    let width = text_cols(window, buffer);
+    let (mut row, mut line) = (window.row_offset, window.line_offset);
+    for _ in 0..y {
+        if line + 1 < editor_wrap(buffer, row, width).len() {
+            line += 1;
+        } else if row < num_rows {
+            (row, line) = (row + 1, 0);
         } else {
-            EditorKey::Char(c)
+            break;
         }
     }
+
+    (row, line)
+}
+
+/// The position of the text shown at line `y` of a window, `x` columns right of the gutter, or
+/// the nearest to it
+fn editor_position_at(
+    window: &EditorWindow,
+    buffer: &mut EditorBuffer,
+    y: usize,
+    x: usize,
+) -> Position {
+    let (row, line) = editor_line_at(window, buffer, y);
+    if row >= buffer.text.line_count() {
+        return Position::new(0, row);
+    }
+
+    let starts = if window.wrap {
+        editor_wrap(buffer, row, text_cols(window, buffer))
+    } else {
+        vec![window.col_offset]
+    };
+    let tab_stop = buffer.tab_stop;
+    let rx = line_column(&starts, line, x);
+    Position::new(map_row_rx_to_cx(editor_row(buffer, row), rx, tab_stop), row)
 }
 
-// endregion: terminal
+/// Scroll a window for a turn of the mouse wheel, taking the cursor along to the top or bottom
+/// line if it would go off screen
+fn editor_wheel(window: &mut EditorWindow, buffer: &mut EditorBuffer, down: bool) {
+    let num_rows = buffer.text.line_count();
+    window.row_offset = if down {
+        // keeping the last row on screen
+        (window.row_offset + WHEEL_SCROLL_ROWS).min(num_rows.saturating_sub(1))
+    } else {
+        window.row_offset.saturating_sub(WHEEL_SCROLL_ROWS)
+    };
+    window.line_offset = 0;
+
+    // the cursor's line and column on screen
+    let tab_stop = buffer.tab_stop;
+    let rx = if window.cy < num_rows {
+        map_row_cx_to_rx(editor_row(buffer, window.cy), window.cx, tab_stop)
+    } else {
+        0
+    };
+    let starts = if window.wrap {
+        editor_wrap(buffer, window.cy, text_cols(window, buffer))
+    } else {
+        vec![window.col_offset]
+    };
+    let line = starts.iter().rposition(|&start| start <= rx).unwrap_or(0);
+    let cursor = (window.cy, line);
+
+    let y = if cursor < (window.row_offset, 0) {
+        0
+    } else if cursor > editor_line_at(window, buffer, window.screen_rows - 1) {
+        window.screen_rows - 1
+    } else {
+        return;
+    };
+    let at = editor_position_at(window, buffer, y, rx.saturating_sub(starts[line]));
+    (window.cx, window.cy) = (at.cx, at.cy);
+}
+
+// endregion: mouse
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kilo_rs::terminal::VirtualTerminal;
+    use std::path::PathBuf;
+
+    fn editor(rows: usize, cols: usize) -> (EditorConfig, VirtualTerminal) {
+        editor_with_config(rows, cols, "")
+    }
+
+    /// An editor configured with the lines of a `~/.kilorc`
+    fn editor_with_config(
+        rows: usize,
+        cols: usize,
+        kilorc: &str,
+    ) -> (EditorConfig, VirtualTerminal) {
+        let mut settings = Config::default();
+        settings.apply(kilorc).unwrap();
+        let terminal = VirtualTerminal::new(rows, cols);
+        let config = EditorConfig::new(Box::new(terminal.clone()), settings).unwrap();
+        (config, terminal)
+    }
+
+    /// Type keys into the editor and draw the screen, as the main loop would. Returns whether the
+    /// editor quit.
+    fn type_keys(config: &mut EditorConfig, terminal: &VirtualTerminal, keys: &str) -> bool {
+        terminal.send(keys.as_bytes());
+        while terminal.has_input() {
+            refresh_screen(config).unwrap();
+            if process_keypress(config) {
+                return true;
+            }
+        }
+        refresh_screen(config).unwrap();
+        false
+    }
+
+    fn temp_file(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kilo-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn edit_and_save() {
+        let path = temp_file("edit.txt", "hello\nthere\n");
+        let (mut config, terminal) = editor(6, 60);
+        editor_open(path.to_str().unwrap(), &mut config).unwrap();
+
+        type_keys(&mut config, &terminal, "\x1b[F, world\r日本");
+        assert_eq!(terminal.row(0), "hello, world");
+        assert_eq!(terminal.row(1), "日本");
+        assert_eq!(terminal.row(2), "there");
+        assert_eq!(terminal.row(3), "~");
+        assert!(terminal.row(4).contains("(modified)"));
+        // after two wide characters
+        assert_eq!(terminal.cursor(), (1, 4));
+
+        type_keys(&mut config, &terminal, "\x13");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "hello, world\n日本\nthere\n"
+        );
+        assert!(!terminal.row(4).contains("(modified)"));
+        assert_eq!(terminal.row(5), "26 bytes written to disk");
+
+        assert!(type_keys(&mut config, &terminal, "\x11"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn edit_keeps_bytes_and_line_breaks() {
+        let path = temp_file("bytes.txt", b"a\xffb\r\nc\xe9\r\n");
+        let (mut config, terminal) = editor(6, 40);
+        editor_open(path.to_str().unwrap(), &mut config).unwrap();
+
+        // type after the invalid byte, delete the char after that, and join the rows
+        type_keys(&mut config, &terminal, "\x1b[C\x1b[Cx");
+        assert_eq!(terminal.row(0), "a\u{fffd}xb");
+        assert_eq!(terminal.row(1), "c\u{fffd}");
+        type_keys(&mut config, &terminal, "\x1b[3~\x1b[F\x1b[3~\x1b[F!");
+        assert_eq!(terminal.row(0), "a\u{fffd}xc\u{fffd}!");
+        type_keys(&mut config, &terminal, "\r\x13");
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\xffxc\xe9!\r\n\r\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn swap_file_is_as_private_as_the_file() {
+        let path = temp_file("private.txt", "secret\n");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let new_path = std::env::temp_dir().join(format!("kilo-{}-new.txt", std::process::id()));
+        let (mut config, terminal) = editor(6, 40);
+        editor_open(path.to_str().unwrap(), &mut config).unwrap();
+        type_keys(&mut config, &terminal, "!");
+        let command = format!("\x05e {}\r!", new_path.display());
+        type_keys(&mut config, &terminal, &command);
+
+        let long_ago = Instant::now() - std::time::Duration::from_secs(KILO_SWAP_SECS);
+        for buffer in &mut config.buffers {
+            buffer.swap_time = long_ago;
+        }
+        editor_write_swaps(&mut config);
+        for path in [&path, &new_path] {
+            let swap = swap_path(path);
+            let mode = std::fs::metadata(&swap).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            std::fs::remove_file(&swap).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn search_moves_to_match() {
+        let path = temp_file("search.txt", "one\ntwo foo\nthree\n");
+        let (mut config, terminal) = editor(6, 40);
+        editor_open(path.to_str().unwrap(), &mut config).unwrap();
+
+        // the match is scrolled to the top of the screen
+        type_keys(&mut config, &terminal, "\x06fo+\r");
+        assert_eq!(config.window().cursor(), Position::new(4, 1));
+        assert_eq!(terminal.row(0), "two foo");
+        assert_eq!(terminal.cursor(), (0, 4));
+
+        // escape goes back to where the search started
+        type_keys(&mut config, &terminal, "\x1b[A\x06three\x1b");
+        assert_eq!(config.window().cursor(), Position::new(3, 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn selection_and_yank() {
+        let (mut config, terminal) = editor(6, 40);
+        type_keys(&mut config, &terminal, "abc def\r");
+        type_keys(&mut config, &terminal, "\x1b[A\x1b[1;2C\x1b[1;2C\x1b[1;2C");
+        let selected = (0..8)
+            .map(|x| terminal.cell(0, x).reverse)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            selected,
+            [true, true, true, false, false, false, false, false]
+        );
+
+        // cut, then paste it twice at the end of the line
+        type_keys(&mut config, &terminal, "\x18\x1b[F\x16\x16");
+        assert_eq!(terminal.row(0), " defabcabc");
+        assert!(!terminal.cell(0, 0).reverse);
+    }
+
+    #[test]
+    fn deleting_back_after_moving_is_its_own_step() {
+        let (mut config, terminal) = editor(6, 40);
+        type_keys(&mut config, &terminal, "hello\x7f");
+        assert_eq!(terminal.row(0), "hell");
+
+        // once the cursor has moved, deleting back no longer takes back the typing
+        type_keys(&mut config, &terminal, "o\x1b[D\x1b[C\x7f");
+        assert_eq!(terminal.row(0), "hell");
+        type_keys(&mut config, &terminal, "\x1a");
+        assert_eq!(terminal.row(0), "hello");
+        type_keys(&mut config, &terminal, "\x1a\x1a");
+        assert_eq!(terminal.row(0), "~");
+    }
+
+    #[test]
+    fn split_windows() {
+        let (mut config, terminal) = editor(8, 21);
+        type_keys(&mut config, &terminal, "left");
+        type_keys(&mut config, &terminal, "\x17v");
+        assert_eq!(terminal.row(0), "left      |left");
+
+        // the windows share the buffer but not the cursor
+        type_keys(&mut config, &terminal, "!\x17w\x1b[H>");
+        assert_eq!(terminal.row(0), ">left!    |>left!");
+        assert_eq!(terminal.cursor(), (0, 1));
+
+        type_keys(&mut config, &terminal, "\x17s");
+        assert_eq!(terminal.row(3), ">left!    |~");
+        assert!(terminal.row(2).starts_with("[No Name]"));
+
+        type_keys(&mut config, &terminal, "\x17c\x17c\x17c");
+        assert_eq!(terminal.row(0), ">left!");
+        assert_eq!(terminal.row(7), "Can't close the last");
+    }
+
+    #[test]
+    fn commands_and_wrap() {
+        let (mut config, terminal) = editor(7, 24);
+        type_keys(&mut config, &terminal, "abcdefghijklmnopqrstuvwxyz\rx");
+        type_keys(&mut config, &terminal, "\x05set wrap\r\x05set number\r");
+        assert_eq!(terminal.row(0), "  1 abcdefghijklmnopqrst");
+        assert_eq!(terminal.row(1), "    uvwxyz");
+        assert_eq!(terminal.row(2), "  2 x");
+
+        // up goes a screen line at a time, keeping the column
+        type_keys(&mut config, &terminal, "\x1b[A");
+        assert_eq!(config.window().cursor(), Position::new(21, 0));
+        assert_eq!(terminal.cursor(), (1, 5));
+        type_keys(&mut config, &terminal, "\x1b[A");
+        assert_eq!(config.window().cursor(), Position::new(1, 0));
+
+        type_keys(&mut config, &terminal, "\x05goto 2\r\x05set ts=4\r\tb");
+        assert_eq!(terminal.row(2), "  2     bx");
+        type_keys(&mut config, &terminal, "\x05bogus\r");
+        assert_eq!(terminal.row(6), "Unknown command: bogus");
+
+        // writing a copy names the buffer, and editing a file opens another buffer
+        let copy = temp_file("copy.txt", "");
+        let other = temp_file("other.txt", "other\n");
+        let command = format!("\x05w {}\r", copy.display());
+        type_keys(&mut config, &terminal, &command);
+        assert!(std::fs::read_to_string(&copy).unwrap().starts_with("abc"));
+        let command = format!("\x05e {}\r", other.display());
+        type_keys(&mut config, &terminal, &command);
+        assert_eq!(config.buffers.len(), 2);
+        assert_eq!(terminal.row(0), "  1 other");
+        std::fs::remove_file(&copy).unwrap();
+        std::fs::remove_file(&other).unwrap();
+    }
+
+    #[test]
+    fn mouse_click_and_scroll() {
+        let (mut config, terminal) = editor(6, 20);
+        let lines = (1..=10).map(|n| format!("line {}", n)).collect::<Vec<_>>();
+        type_keys(&mut config, &terminal, &lines.join("\r"));
+        assert_eq!(terminal.row(0), "line 7");
+
+        // the wheel scrolls, taking the cursor along when it would go off screen
+        type_keys(&mut config, &terminal, "\x1b[<64;1;1M\x1b[<64;1;1M");
+        assert_eq!(terminal.row(0), "line 1");
+        assert_eq!(config.window().cursor(), Position::new(6, 3));
+        type_keys(&mut config, &terminal, "\x1b[<65;1;1M");
+        assert_eq!(terminal.row(0), "line 4");
+        assert_eq!(config.window().cursor(), Position::new(6, 3));
+
+        // clicking places the cursor, and past the end of a row puts it at the end
+        type_keys(&mut config, &terminal, "\x1b[<0;3;2M\x1b[<0;3;2m");
+        assert_eq!(config.window().cursor(), Position::new(2, 4));
+        type_keys(&mut config, &terminal, "\x1b[<0;15;3M\x1b[<0;15;3m");
+        assert_eq!(config.window().cursor(), Position::new(6, 5));
+
+        // dragging selects from where the button went down
+        type_keys(
+            &mut config,
+            &terminal,
+            "\x1b[<0;1;1M\x1b[<32;3;2M\x1b[<0;3;2m",
+        );
+        assert_eq!(config.window().mark, Some(Position::new(0, 3)));
+        type_keys(&mut config, &terminal, "\x03");
+        assert_eq!(config.kill_ring.yank(), Some("line 4\nli"));
+
+        // clicking in another window moves to it, through the gutter
+        type_keys(&mut config, &terminal, "\x17v\x05set number\r\x17w");
+        assert_eq!(config.current, 0);
+        type_keys(&mut config, &terminal, "\x1b[<0;12;1M");
+        assert_eq!(config.current, 1);
+        assert_eq!(config.window().cursor(), Position::new(0, 3));
+        type_keys(&mut config, &terminal, "\x1b[<0;17;1M");
+        assert_eq!(config.window().cursor(), Position::new(2, 3));
+    }
+
+    #[test]
+    fn filter_through_command() {
+        let (mut config, terminal) = editor(8, 40);
+        type_keys(&mut config, &terminal, "pear\rapple\rfig");
+        type_keys(&mut config, &terminal, "\x05!sort\r");
+        assert_eq!(terminal.rows()[..3], ["apple", "fig", "pear"]);
+        assert_eq!(terminal.row(7), "3 lines filtered through sort");
+
+        // the replacement is undone in one step
+        type_keys(&mut config, &terminal, "\x1a");
+        assert_eq!(terminal.rows()[..3], ["pear", "apple", "fig"]);
+
+        // a selection filters the rows it touches, up to a row it ends at the start of
+        type_keys(
+            &mut config,
+            &terminal,
+            "\x05goto 1\r\x1b[C\x00\x1b[B\x1b[B\x1b[H",
+        );
+        type_keys(&mut config, &terminal, "\x05!tr a-z A-Z; echo done >&2\r");
+        assert_eq!(terminal.rows()[..3], ["PEAR", "APPLE", "fig"]);
+        assert_eq!(terminal.row(7), "done");
+        assert_eq!(config.window().mark, None);
+
+        // a failing command leaves the text alone
+        type_keys(
+            &mut config,
+            &terminal,
+            "\x05!echo no such thing >&2; exit 2\r",
+        );
+        assert_eq!(terminal.rows()[..3], ["PEAR", "APPLE", "fig"]);
+        assert_eq!(terminal.row(7), "echo no such thing >&2; exit 2 exited wi");
+        assert!(config
+            .status_msg
+            .as_deref()
+            .is_some_and(|msg| msg.ends_with("with status 2: no such thing")));
+
+        // escape kills a command that's taking too long
+        type_keys(&mut config, &terminal, "\x05!sleep 10\r\x1b");
+        assert_eq!(terminal.rows()[..3], ["PEAR", "APPLE", "fig"]);
+        assert_eq!(terminal.row(7), "sleep 10 cancelled");
+    }
+
+    #[test]
+    fn filter_replaces_invalid_bytes_exactly() {
+        let path = temp_file("filter.txt", b"a\xffb\nc\n");
+        let (mut config, terminal) = editor(6, 40);
+        editor_open(path.to_str().unwrap(), &mut config).unwrap();
+
+        type_keys(&mut config, &terminal, "\x05!tr a-z A-Z\r");
+        assert_eq!(terminal.rows()[..3], ["A\u{fffd}B", "C", "~"]);
+        assert_eq!(config.buffer().text.line_count(), 2);
+        type_keys(&mut config, &terminal, "\x13");
+        assert_eq!(std::fs::read(&path).unwrap(), b"A\xffB\nC\n");
+
+        // undoing puts back the bytes that were there
+        type_keys(&mut config, &terminal, "\x1a");
+        type_keys(&mut config, &terminal, "\x13");
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\xffb\nc\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn filter_keeps_crlf_line_breaks() {
+        let path = temp_file("filter-crlf.txt", b"pear\r\napple\r\nfig");
+        let (mut config, terminal) = editor(6, 40);
+        editor_open(path.to_str().unwrap(), &mut config).unwrap();
+
+        // output that's the same as the input changes nothing
+        type_keys(&mut config, &terminal, "\x05!cat\r");
+        assert!(!config.buffer().dirty);
+
+        // line breaks the command passed through aren't doubled, and ones it wrote are the file's
+        type_keys(&mut config, &terminal, "\x05!sort\r");
+        type_keys(&mut config, &terminal, "\x13");
+        assert_eq!(std::fs::read(&path).unwrap(), b"apple\r\nfig\r\npear");
+        type_keys(&mut config, &terminal, "\x05!echo one; echo two\r");
+        type_keys(&mut config, &terminal, "\x13");
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\r\ntwo");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn configured_options_and_keys() {
+        let kilorc = "number = true\ntabstop = 2\nquit_times = 0\nbind ctrl-a = line-start\nbind ctrl-q = undo\nbind ctrl-g = quit\n";
+        let (mut config, terminal) = editor_with_config(6, 40, kilorc);
+        type_keys(&mut config, &terminal, "abc\x01\tX");
+        assert_eq!(terminal.row(0), "  1   Xabc");
+
+        // the tab is undone separately from the typing after it
+        type_keys(&mut config, &terminal, "\x11");
+        assert_eq!(terminal.row(0), "  1   abc");
+        // with unsaved changes, but no more times to press it
+        assert!(type_keys(&mut config, &terminal, "\x07"));
+    }
+
+    #[test]
+    fn indent_and_brackets() {
+        let (mut config, terminal) = editor_with_config(8, 40, "expandtab = true\ntabstop = 4");
+        type_keys(&mut config, &terminal, "if (a[1] > b) {\rx\r\ty");
+        assert_eq!(terminal.row(1), "    x");
+        assert_eq!(terminal.row(2), "        y");
+
+        // the bracket matching the one under the cursor is shown in reverse
+        type_keys(
+            &mut config,
+            &terminal,
+            "\x1b[A\x1b[A\x1b[H\x1b[C\x1b[C\x1b[C",
+        );
+        assert!(terminal.cell(0, 12).reverse);
+        assert!(!terminal.cell(0, 7).reverse);
+        type_keys(&mut config, &terminal, "\x1d");
+        assert_eq!(config.window().cursor(), Position::new(12, 0));
+        type_keys(&mut config, &terminal, "\x1b[F\x1d");
+        assert_eq!(terminal.row(7), "No matching bracket");
+
+        type_keys(&mut config, &terminal, "\x05set noai\r\x1b[B\x1b[F\r");
+        assert_eq!(config.window().cursor(), Position::new(0, 2));
+
+        // past the last row is an empty one, with no indentation to carry on
+        type_keys(&mut config, &terminal, "\x05set ai\r\x1b[B\x1b[B\r");
+        assert_eq!(config.window().cursor(), Position::new(0, 5));
+        assert_eq!(config.buffer().text.line_count(), 5);
+    }
+
+    #[test]
+    fn bracket_follows_edits_in_another_window() {
+        let (mut config, terminal) = editor(8, 21);
+        type_keys(&mut config, &terminal, "(a) x\x1b[H\x17v");
+        assert!(terminal.cell(0, 2).reverse);
+        assert!(terminal.cell(0, 13).reverse);
+
+        // the left window's cursor stays on the opening bracket as the closing one moves
+        type_keys(&mut config, &terminal, "\x1b[Cb");
+        assert_eq!(terminal.row(0), "(ba) x    |(ba) x");
+        assert!(terminal.cell(0, 3).reverse);
+        assert!(!terminal.cell(0, 2).reverse);
+    }
+
+    #[test]
+    fn redraws_on_resize() {
+        let (mut config, terminal) = editor(6, 40);
+        type_keys(&mut config, &terminal, "text");
+        assert!(terminal.row(4).starts_with("[No Name]"));
+
+        terminal.resize(10, 30);
+        type_keys(&mut config, &terminal, "!");
+        assert_eq!(terminal.row(0), "text!");
+        assert!(terminal.row(8).starts_with("[No Name]"));
+        assert_eq!(terminal.row(8).chars().count(), 30);
+    }
+}
@@ -0,0 +1,59 @@
+//! Pseudo-terminal pairs for exercising terminal code in tests
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::raw::{c_char, c_int};
+
+extern "C" {
+    fn posix_openpt(flags: c_int) -> c_int;
+    fn grantpt(fd: c_int) -> c_int;
+    fn unlockpt(fd: c_int) -> c_int;
+    fn ptsname(fd: c_int) -> *mut c_char;
+}
+
+const O_RDWR: c_int = 2;
+#[cfg(target_os = "linux")]
+const O_NOCTTY: c_int = 0o400;
+#[cfg(target_os = "macos")]
+const O_NOCTTY: c_int = 0x20000;
+
+/// A master/slave pseudo-terminal pair, closed when dropped
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    pub fn open() -> Result<Self, std::io::Error> {
+        unsafe {
+            let fd = posix_openpt(O_RDWR | O_NOCTTY);
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+
+            if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            let name = ptsname(fd);
+            if name.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            let slave = OpenOptions::new().read(true).write(true).open(path)?;
+
+            Ok(Self { master, slave })
+        }
+    }
+
+    pub fn master_fd(&self) -> RawFd {
+        self.master.as_raw_fd()
+    }
+
+    pub fn slave_fd(&self) -> RawFd {
+        self.slave.as_raw_fd()
+    }
+}
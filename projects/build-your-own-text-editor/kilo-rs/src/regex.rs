@@ -0,0 +1,979 @@
+//! Regular expressions for search and replace.
+//!
+//! Supported syntax: literals, `.`, classes like `[a-z_]` and `[^0-9]`, the escapes `\d \w \s`
+//! and their negations, anchors `^ $ \b \B`, capturing `( )` and non-capturing `(?: )` groups,
+//! alternation `|` and the quantifiers `* + ? {m} {m,} {m,n}`, each of which can be made lazy
+//! with a trailing `?`.
+//!
+//! Patterns compile to a small program run by a backtracking matcher that never visits the same
+//! instruction at the same position twice, so matching takes time proportional to the pattern size
+//! times the text length however the pattern is written. Matches prefer the leftmost start and
+//! then the first alternative, as in Perl.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+// bounded repetitions are compiled by copying, so their counts are limited, and so is the size of
+// the program once repetitions are nested
+const MAX_REPEAT: usize = 1000;
+const MAX_PROGRAM_LEN: usize = 100_000;
+// the most memory the matcher's bitmap of tried states takes, in 64-bit words; searches with more
+// states than that keep the ones they try in a set instead
+const MAX_VISITED_WORDS: usize = 1 << 20;
+
+/// Letters match regardless of case
+pub const CASE_INSENSITIVE: u32 = 1 << 0;
+/// Matches must start and end at word boundaries
+pub const WHOLE_WORD: u32 = 1 << 1;
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    pub message: String,
+    /// Char index in the pattern where the problem was found
+    pub position: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.position)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn of(item: ClassItem, negated: bool) -> Self {
+        Self {
+            items: vec![item],
+            negated,
+        }
+    }
+
+    fn contains(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| match *item {
+            ClassItem::Range(first, last) => (first..=last).contains(&c),
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => is_word(c),
+            ClassItem::Space => c.is_whitespace(),
+        });
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// A group, capturing into the numbered group if any
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+impl Node {
+    /// Number of instructions the node compiles to, saturating rather than overflowing
+    fn program_len(&self) -> usize {
+        match self {
+            Node::Empty => 0,
+            Node::Literal(_) | Node::Any | Node::Class(_) | Node::Assert(_) => 1,
+            Node::Group(node, index) => node.program_len() + if index.is_some() { 2 } else { 0 },
+            Node::Concat(nodes) => nodes
+                .iter()
+                .fold(0, |len, node| len.saturating_add(node.program_len())),
+            // a split and a jump before each alternative but the last
+            Node::Alternate(alternatives) => alternatives
+                .iter()
+                .fold((alternatives.len() - 1) * 2, |len, alternative| {
+                    len.saturating_add(alternative.program_len())
+                }),
+            Node::Repeat { node, min, max, .. } => {
+                let len = node.program_len();
+                let optional = match max {
+                    None => len.saturating_add(2),
+                    Some(max) => (max - min).saturating_mul(len.saturating_add(1)),
+                };
+                min.saturating_mul(len).saturating_add(optional)
+            }
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+// region: parser
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, message: &str) -> Result<T, Error> {
+        Err(Error {
+            message: message.to_string(),
+            position: self.pos,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternate(&mut self) -> Result<Node, Error> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+        }
+
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alternate(alternatives)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, Error> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            nodes.push(self.parse_repeat()?);
+        }
+
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn parse_repeat(&mut self) -> Result<Node, Error> {
+        let mut node = self.parse_atom()?;
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.parse_counts() {
+                    Some(counts) => counts,
+                    // not a valid repetition, so it's a literal '{' for the next atom
+                    None => return Ok(node),
+                },
+                _ => return Ok(node),
+            };
+            if max.is_some_and(|max| max < min) {
+                return self.error("repetition maximum is less than its minimum");
+            }
+            if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+                return self.error("repetition count is too large");
+            }
+            // step over the quantifier, or the closing brace of a count
+            self.pos += 1;
+
+            let quantifier = self.pos - 1;
+            let greedy = !self.eat('?');
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+                greedy,
+            };
+            if node.program_len() > MAX_PROGRAM_LEN {
+                self.pos = quantifier;
+                return self.error("pattern is too large");
+            }
+        }
+    }
+
+    /// Parse `{m}`, `{m,}` or `{m,n}`, leaving the position on the closing brace
+    fn parse_counts(&mut self) -> Option<(usize, Option<usize>)> {
+        let start = self.pos;
+        let number = |parser: &mut Parser| {
+            let digits_start = parser.pos;
+            while parser.peek().is_some_and(|c| c.is_ascii_digit()) {
+                parser.pos += 1;
+            }
+            parser.chars[digits_start..parser.pos]
+                .iter()
+                .collect::<String>()
+                .parse::<usize>()
+                .ok()
+        };
+
+        self.pos += 1;
+        let counts = number(self).and_then(|min| {
+            if self.eat(',') {
+                if self.peek() == Some('}') {
+                    Some((min, None))
+                } else {
+                    number(self).map(|max| (min, Some(max)))
+                }
+            } else {
+                Some((min, Some(min)))
+            }
+        });
+
+        match counts {
+            Some(counts) if self.peek() == Some('}') => Some(counts),
+            _ => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, Error> {
+        let Some(c) = self.peek() else {
+            return self.error("unexpected end of pattern");
+        };
+        self.pos += 1;
+
+        Ok(match c {
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return self.error("unknown group flag");
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let node = self.parse_alternate()?;
+                if !self.eat(')') {
+                    return self.error("unclosed group");
+                }
+                Node::Group(Box::new(node), index)
+            }
+            ')' => {
+                self.pos -= 1;
+                return self.error("unmatched ')'");
+            }
+            '*' | '+' | '?' => {
+                self.pos -= 1;
+                return self.error("nothing to repeat");
+            }
+            '[' => Node::Class(self.parse_class()?),
+            '.' => Node::Any,
+            '^' => Node::Assert(Assertion::Start),
+            '$' => Node::Assert(Assertion::End),
+            '\\' => self.parse_escape()?,
+            c => Node::Literal(c),
+        })
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, Error> {
+        let Some(c) = self.peek() else {
+            return self.error("trailing backslash");
+        };
+        self.pos += 1;
+
+        Ok(match c {
+            'd' => Node::Class(Class::of(ClassItem::Digit, false)),
+            'D' => Node::Class(Class::of(ClassItem::Digit, true)),
+            'w' => Node::Class(Class::of(ClassItem::Word, false)),
+            'W' => Node::Class(Class::of(ClassItem::Word, true)),
+            's' => Node::Class(Class::of(ClassItem::Space, false)),
+            'S' => Node::Class(Class::of(ClassItem::Space, true)),
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            c => Node::Literal(escaped_char(c)),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Class, Error> {
+        let negated = self.eat('^');
+        let mut items = vec![];
+
+        // a ']' straight after the opening bracket is taken literally
+        let mut first = true;
+        loop {
+            let Some(c) = self.peek() else {
+                return self.error("unclosed character class");
+            };
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let start = if c == '\\' {
+                let Some(e) = self.peek() else {
+                    return self.error("trailing backslash");
+                };
+                self.pos += 1;
+                match e {
+                    'd' => {
+                        items.push(ClassItem::Digit);
+                        continue;
+                    }
+                    'w' => {
+                        items.push(ClassItem::Word);
+                        continue;
+                    }
+                    's' => {
+                        items.push(ClassItem::Space);
+                        continue;
+                    }
+                    e => escaped_char(e),
+                }
+            } else {
+                c
+            };
+
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let mut end = self.chars[self.pos];
+                self.pos += 1;
+                if end == '\\' {
+                    let Some(e) = self.peek() else {
+                        return self.error("trailing backslash");
+                    };
+                    self.pos += 1;
+                    end = escaped_char(e);
+                }
+                if end < start {
+                    return self.error("character range is out of order");
+                }
+                items.push(ClassItem::Range(start, end));
+            } else {
+                items.push(ClassItem::Range(start, start));
+            }
+        }
+
+        Ok(Class { items, negated })
+    }
+}
+
+fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+// endregion: parser
+
+// region: compiler
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// Continue at both, preferring the first
+    Split(usize, usize),
+    Jump(usize),
+    /// Record the position in a capture slot
+    Save(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn patch_split(&mut self, at: usize, first: usize, second: usize) {
+        self.program[at] = Inst::Split(first, second);
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Literal(c) => {
+                let c = if self.case_insensitive { fold(*c) } else { *c };
+                self.emit(Inst::Char(c));
+            }
+            Node::Any => {
+                self.emit(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(*assertion));
+            }
+            Node::Group(node, index) => match index {
+                Some(index) => {
+                    self.emit(Inst::Save(index * 2));
+                    self.compile(node);
+                    self.emit(Inst::Save(index * 2 + 1));
+                }
+                None => self.compile(node),
+            },
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node);
+                }
+            }
+            Node::Alternate(alternatives) => {
+                let mut jumps = vec![];
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i + 1 < alternatives.len() {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(alternative);
+                        jumps.push(self.emit(Inst::Jump(0)));
+                        let next = self.program.len();
+                        self.patch_split(split, split + 1, next);
+                    } else {
+                        self.compile(alternative);
+                    }
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.emit(Inst::Jump(split));
+                        let end = self.program.len();
+                        self.split_for(split, *greedy, split + 1, end);
+                    }
+                    Some(max) => {
+                        let mut splits = vec![];
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.split_for(split, *greedy, split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Patch a split between taking another repetition and moving on
+    fn split_for(&mut self, at: usize, greedy: bool, repeat: usize, skip: usize) {
+        if greedy {
+            self.patch_split(at, repeat, skip);
+        } else {
+            self.patch_split(at, skip, repeat);
+        }
+    }
+}
+
+// endregion: compiler
+
+/// A compiled pattern
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize,
+    case_insensitive: bool,
+    // states tried by the matcher, kept between matches so that searching row after row doesn't
+    // allocate each time
+    visited: RefCell<Visited>,
+}
+
+/// The states a search has tried, as a bitmap while there's room for every state in it, or else a
+/// set of just the ones tried. Either way, forgetting them takes time in proportion to how many
+/// were tried rather than how many there could have been.
+#[derive(Debug, Clone, Default)]
+struct Visited {
+    bits: Vec<u64>,
+    // words of `bits` that have bits set
+    touched: Vec<usize>,
+    sparse: Option<HashSet<usize>>,
+}
+
+impl Visited {
+    /// Forget every state, making room for states below `states`
+    fn reset(&mut self, states: usize) {
+        for &word in &self.touched {
+            self.bits[word] = 0;
+        }
+        self.touched.clear();
+
+        let words = states.div_ceil(64);
+        if words > MAX_VISITED_WORDS {
+            let mut sparse = self.sparse.take().unwrap_or_default();
+            sparse.clear();
+            self.sparse = Some(sparse);
+        } else {
+            self.sparse = None;
+            if self.bits.len() < words {
+                self.bits.resize(words, 0);
+            }
+        }
+    }
+
+    /// Mark a state as tried, returning whether it hadn't been
+    fn insert(&mut self, state: usize) -> bool {
+        if let Some(sparse) = &mut self.sparse {
+            return sparse.insert(state);
+        }
+        let word = &mut self.bits[state / 64];
+        let bit = 1 << (state % 64);
+        if *word & bit != 0 {
+            return false;
+        }
+        if *word == 0 {
+            self.touched.push(state / 64);
+        }
+        *word |= bit;
+        true
+    }
+}
+
+/// A match, with the char ranges of its capture groups. Group 0 is the whole match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    slots: Vec<Option<usize>>,
+}
+
+enum Job {
+    Thread { pc: usize, pos: usize },
+    RestoreSlot { slot: usize, value: Option<usize> },
+}
+
+impl Regex {
+    pub fn new(pattern: &str, flags: u32) -> Result<Regex, Error> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let mut node = parser.parse_alternate()?;
+        if parser.pos < parser.chars.len() {
+            return parser.error("unmatched ')'");
+        }
+        if node.program_len() > MAX_PROGRAM_LEN {
+            parser.pos = 0;
+            return parser.error("pattern is too large");
+        }
+
+        if flags & WHOLE_WORD != 0 {
+            node = Node::Concat(vec![
+                Node::Assert(Assertion::WordBoundary),
+                Node::Group(Box::new(node), None),
+                Node::Assert(Assertion::WordBoundary),
+            ]);
+        }
+
+        let case_insensitive = flags & CASE_INSENSITIVE != 0;
+        let mut compiler = Compiler {
+            program: vec![],
+            case_insensitive,
+        };
+        // an unanchored search: try a match at each position in turn, preferring the earliest
+        compiler.emit(Inst::Split(3, 1));
+        compiler.emit(Inst::Any);
+        compiler.emit(Inst::Jump(0));
+        compiler.emit(Inst::Save(0));
+        compiler.compile(&node);
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match);
+
+        Ok(Regex {
+            program: compiler.program,
+            groups: parser.groups,
+            case_insensitive,
+            visited: RefCell::default(),
+        })
+    }
+
+    /// Number of capture groups, not counting the whole match
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    fn char_matches(&self, expected: char, c: char) -> bool {
+        if self.case_insensitive {
+            expected == fold(c)
+        } else {
+            expected == c
+        }
+    }
+
+    fn class_matches(&self, class: &Class, c: char) -> bool {
+        if self.case_insensitive {
+            let upper = c.to_uppercase().next().unwrap_or(c);
+            if class.negated {
+                class.contains(fold(c)) && class.contains(upper)
+            } else {
+                class.contains(fold(c)) || class.contains(upper)
+            }
+        } else {
+            class.contains(c)
+        }
+    }
+
+    fn holds(assertion: Assertion, text: &[char], pos: usize) -> bool {
+        let boundary = || {
+            let before = pos > 0 && is_word(text[pos - 1]);
+            let after = pos < text.len() && is_word(text[pos]);
+            before != after
+        };
+        match assertion {
+            Assertion::Start => pos == 0,
+            Assertion::End => pos == text.len(),
+            Assertion::WordBoundary => boundary(),
+            Assertion::NotWordBoundary => !boundary(),
+        }
+    }
+
+    /// The first match starting at or after char index `start`. Anchors and word boundaries see
+    /// the whole text, not just the part after `start`.
+    pub fn find_at(&self, text: &[char], start: usize) -> Option<Match> {
+        if start > text.len() {
+            return None;
+        }
+
+        let positions = text.len() + 1;
+        let mut visited = self.visited.borrow_mut();
+        visited.reset(self.program.len() * positions);
+        let mut slots = vec![None; (self.groups + 1) * 2];
+        let mut jobs = vec![Job::Thread { pc: 0, pos: start }];
+
+        while let Some(job) = jobs.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::RestoreSlot { slot, value } => {
+                    slots[slot] = value;
+                    continue;
+                }
+                Job::Thread { pc, pos } => (pc, pos),
+            };
+
+            loop {
+                // a thread that reaches a state an earlier one already tried can't do any better
+                if !visited.insert(pc * positions + pos) {
+                    break;
+                }
+
+                match &self.program[pc] {
+                    Inst::Char(c) => {
+                        if pos < text.len() && self.char_matches(*c, text[pos]) {
+                            pc += 1;
+                            pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Any => {
+                        if pos < text.len() {
+                            pc += 1;
+                            pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Class(class) => {
+                        if pos < text.len() && self.class_matches(class, text[pos]) {
+                            pc += 1;
+                            pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Assert(assertion) => {
+                        if Self::holds(*assertion, text, pos) {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Split(first, second) => {
+                        jobs.push(Job::Thread { pc: *second, pos });
+                        pc = *first;
+                    }
+                    Inst::Jump(to) => pc = *to,
+                    Inst::Save(slot) => {
+                        jobs.push(Job::RestoreSlot {
+                            slot: *slot,
+                            value: slots[*slot],
+                        });
+                        slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::Match => return Some(Match { slots }),
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Every non-overlapping match in the text, in order
+    pub fn find_iter<'a>(&'a self, text: &'a [char]) -> impl Iterator<Item = Match> + 'a {
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let m = self.find_at(text, start)?;
+            // step past empty matches so the search moves on
+            start = if m.range().is_empty() {
+                m.end() + 1
+            } else {
+                m.end()
+            };
+            Some(m)
+        })
+    }
+}
+
+impl Match {
+    pub fn start(&self) -> usize {
+        self.slots[0].unwrap_or(0)
+    }
+
+    pub fn end(&self) -> usize {
+        self.slots[1].unwrap_or(0)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    /// The range of capture group `i`, if it took part in the match
+    pub fn group(&self, i: usize) -> Option<Range<usize>> {
+        match (self.slots.get(i * 2)?, self.slots.get(i * 2 + 1)?) {
+            (Some(start), Some(end)) => Some(*start..*end),
+            _ => None,
+        }
+    }
+
+    /// Substitute captures into a replacement: `$0` to `$9` or `${n}` for a group, `$$` for `$`
+    pub fn expand(&self, text: &[char], replacement: &str) -> String {
+        let mut out = String::new();
+        let mut chars = replacement.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                out.push(c);
+                continue;
+            }
+
+            let group = match chars.peek() {
+                Some('$') => {
+                    chars.next();
+                    out.push('$');
+                    continue;
+                }
+                Some(d) if d.is_ascii_digit() => {
+                    let d = chars.next().unwrap();
+                    d.to_digit(10).map(|d| d as usize)
+                }
+                Some('{') => {
+                    chars.next();
+                    let digits = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                    digits.parse::<usize>().ok()
+                }
+                _ => {
+                    out.push('$');
+                    continue;
+                }
+            };
+
+            if let Some(range) = group.and_then(|group| self.group(group)) {
+                out.extend(&text[range]);
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, flags: u32, text: &str) -> Option<(usize, usize)> {
+        let text = text.chars().collect::<Vec<_>>();
+        let m = Regex::new(pattern, flags).unwrap().find_at(&text, 0)?;
+        Some((m.start(), m.end()))
+    }
+
+    fn all(pattern: &str, flags: u32, text: &str) -> Vec<String> {
+        let text = text.chars().collect::<Vec<_>>();
+        let regex = Regex::new(pattern, flags).unwrap();
+        regex
+            .find_iter(&text)
+            .map(|m| text[m.range()].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        assert_eq!(find("lo", 0, "hello"), Some((3, 5)));
+        assert_eq!(find("x", 0, "hello"), None);
+        assert_eq!(find("h.l", 0, "hello"), Some((0, 3)));
+        assert_eq!(find("[aeiou]+", 0, "strength"), Some((3, 4)));
+        assert_eq!(find("[^a-z]", 0, "abc1"), Some((3, 4)));
+        assert_eq!(find("[]x]", 0, "a]"), Some((1, 2)));
+        assert_eq!(find(r"\d+\.\d*", 0, "v 12.5"), Some((2, 6)));
+        assert_eq!(find(r"\s\S", 0, "a  b"), Some((2, 4)));
+        assert_eq!(find("a{2,3}", 0, "caaaa"), Some((1, 4)));
+        assert_eq!(find("a{2}", 0, "caaaa"), Some((1, 3)));
+        assert_eq!(find("x{,2}", 0, "x{,2}"), Some((0, 5)));
+    }
+
+    #[test]
+    fn anchors_and_alternation() {
+        assert_eq!(find("^b", 0, "ab"), None);
+        assert_eq!(find("b$", 0, "ab"), Some((1, 2)));
+        assert_eq!(find("cat|dog", 0, "hotdog"), Some((3, 6)));
+        assert_eq!(find(r"\bis\b", 0, "this is"), Some((5, 7)));
+        assert_eq!(find(r"\Bis", 0, "this is"), Some((2, 4)));
+        assert_eq!(all("a|", 0, "ba"), ["", "a", ""]);
+    }
+
+    #[test]
+    fn greedy_and_lazy() {
+        assert_eq!(find("<.*>", 0, "<a><b>"), Some((0, 6)));
+        assert_eq!(find("<.*?>", 0, "<a><b>"), Some((0, 3)));
+        assert_eq!(find("a??b", 0, "ab"), Some((0, 2)));
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(find("HeLLo", CASE_INSENSITIVE, "say hello"), Some((4, 9)));
+        assert_eq!(find("[A-Z]+", CASE_INSENSITIVE, "abc"), Some((0, 3)));
+        assert_eq!(find("[^a-z]", CASE_INSENSITIVE, "aB1"), Some((2, 3)));
+        assert_eq!(all("in", WHOLE_WORD, "in print in"), ["in", "in"]);
+        assert_eq!(all("a|ab", WHOLE_WORD, "ab a"), ["ab", "a"]);
+    }
+
+    #[test]
+    fn captures_and_expand() {
+        let text = "key = value".chars().collect::<Vec<_>>();
+        let regex = Regex::new(r"(\w+)\s*=\s*(\w+)", 0).unwrap();
+        assert_eq!(regex.groups(), 2);
+        let m = regex.find_at(&text, 0).unwrap();
+        assert_eq!(m.group(1), Some(0..3));
+        assert_eq!(m.group(2), Some(6..11));
+        assert_eq!(
+            m.expand(&text, "$2: ${1} $$3 $0"),
+            "value: key $3 key = value"
+        );
+
+        // a group that didn't take part expands to nothing
+        let text = "b".chars().collect::<Vec<_>>();
+        let m = Regex::new("(a)|b", 0).unwrap().find_at(&text, 0).unwrap();
+        assert_eq!(m.group(1), None);
+        assert_eq!(m.expand(&text, "[$1]"), "[]");
+    }
+
+    #[test]
+    fn pathological_patterns_finish() {
+        let text = "a".repeat(5000);
+        assert_eq!(find("(a*)*b", 0, &text), None);
+        assert_eq!(find("(a|aa)+$", 0, &text), Some((0, 5000)));
+    }
+
+    #[test]
+    fn long_rows_keep_memory_bounded() {
+        // more states than the bitmap has room for
+        let regex = Regex::new("x{1000}", 0).unwrap();
+        let text = "y".repeat(100_000) + &"x".repeat(1000);
+        let text = text.chars().collect::<Vec<_>>();
+        let m = regex.find_at(&text, 0).unwrap();
+        assert_eq!(m.range(), 100_000..101_000);
+        assert!(regex.visited.borrow().bits.is_empty());
+
+        // and a short row after it goes back to the bitmap, with nothing left over from before
+        let text = "xx".repeat(1000).chars().collect::<Vec<_>>();
+        assert_eq!(regex.find_iter(&text).count(), 2);
+        assert!(regex.visited.borrow().sparse.is_none());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |pattern| Regex::new(pattern, 0).unwrap_err().message;
+        assert_eq!(error("(ab"), "unclosed group");
+        assert_eq!(error("ab)"), "unmatched ')'");
+        assert_eq!(error("*a"), "nothing to repeat");
+        assert_eq!(error("[ab"), "unclosed character class");
+        assert_eq!(error("[z-a]"), "character range is out of order");
+        assert_eq!(
+            error("a{3,2}"),
+            "repetition maximum is less than its minimum"
+        );
+        assert_eq!(error("ab\\"), "trailing backslash");
+        assert_eq!(error("a{1001}"), "repetition count is too large");
+
+        // nested repetitions multiply, whether they're nested or side by side
+        let error = Regex::new("(a{1000}){1000}", 0).unwrap_err();
+        assert_eq!(error.message, "pattern is too large");
+        assert_eq!(error.position, 14);
+        assert_eq!(
+            Regex::new(&"(a{1000}){90}".repeat(2), 0).unwrap_err(),
+            Error {
+                message: "pattern is too large".to_string(),
+                position: 0,
+            }
+        );
+        assert!(Regex::new("(a{1000}){90}", 0).is_ok());
+    }
+}
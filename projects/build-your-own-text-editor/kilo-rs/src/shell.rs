@@ -0,0 +1,128 @@
+//! Running text through shell commands, for the `!` command.
+
+use std::io::{self, Read, Write};
+use std::os::raw::c_int;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const SIGKILL: c_int = 9;
+/// How long to wait between checks on whether the command has finished or been cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+extern "C" {
+    fn kill(pid: c_int, sig: c_int) -> c_int;
+}
+
+fn read_all(mut pipe: impl Read) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    pipe.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Run `command` with `sh -c`, giving it `input` on stdin, and wait for it to finish. The
+/// command's exit status and what it wrote to stdout and stderr are returned however it exits.
+///
+/// While it runs, `cancel` is called every so often, and if it returns true the command is
+/// killed, along with anything it started, and `None` is returned.
+pub fn pipe_through(
+    command: &str,
+    input: &[u8],
+    mut cancel: impl FnMut() -> bool,
+) -> io::Result<Option<Output>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // a group of its own, so that cancelling it kills whatever the shell started too
+        .process_group(0)
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    thread::scope(|scope| {
+        // written from another thread so that a command can fill its stdout before it has read
+        // all of its input, and closed after so that it sees the end of it
+        let writer = scope.spawn(move || match stdin.write_all(input) {
+            // commands such as `head` needn't read all of their input, and a cancelled one
+            // stops reading it
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        });
+        let stdout = scope.spawn(move || read_all(stdout));
+        let stderr = scope.spawn(move || read_all(stderr));
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if cancel() {
+                unsafe {
+                    kill(-(child.id() as c_int), SIGKILL);
+                }
+                child.wait()?;
+                break None;
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let stdout = stdout.join().expect("the reader doesn't panic")?;
+        let stderr = stderr.join().expect("the reader doesn't panic")?;
+        writer.join().expect("the writer doesn't panic")?;
+        Ok(status.map(|status| Output {
+            status,
+            stdout,
+            stderr,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn run(command: &str, input: &[u8]) -> Output {
+        pipe_through(command, input, || false).unwrap().unwrap()
+    }
+
+    #[test]
+    fn pipes_input_through() {
+        let output = run("sort -r", b"a\nc\nb\n");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"c\nb\na\n");
+        assert!(output.stderr.is_empty());
+
+        // more input than a pipe holds, to a command that doesn't read it all
+        let input = "line\n".repeat(100_000);
+        let output = run("head -n 2", input.as_bytes());
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"line\nline\n");
+    }
+
+    #[test]
+    fn reports_failure() {
+        let output = run("echo partial; echo oops >&2; exit 3", b"");
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"partial\n");
+        assert_eq!(output.stderr, b"oops\n");
+    }
+
+    #[test]
+    fn cancel_kills_the_command() {
+        let start = Instant::now();
+        let mut polls = 0;
+        // the sleep is run by a shell of its own, which still holds stdout once it's killed
+        let output = pipe_through("sleep 10; echo done", b"", || {
+            polls += 1;
+            polls == 3
+        })
+        .unwrap();
+        assert!(output.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,105 @@
+use std::mem::MaybeUninit;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sys::*;
+
+// `struct sigaction` is laid out differently on each supported target, so each declares its own.
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::os::raw::c_int;
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_handler: usize,
+        pub sa_mask: [u64; 16],
+        pub sa_flags: c_int,
+        pub sa_restorer: usize,
+    }
+
+    pub const SIGWINCH: c_int = 28;
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::os::raw::{c_int, c_uint};
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_handler: usize,
+        pub sa_mask: c_uint,
+        pub sa_flags: c_int,
+    }
+
+    pub const SIGWINCH: c_int = 28;
+}
+
+extern "C" {
+    fn sigaction(signum: c_int, act: *const SigAction, oldact: *mut SigAction) -> c_int;
+}
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_resize(_: c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+/// Flag terminal resizes for `take_resized`. The handler is installed without `SA_RESTART`, so a
+/// read blocked waiting for input fails with `ErrorKind::Interrupted` when the window changes size
+/// instead of waiting for the next key.
+pub fn register_resize_handler() -> Result<(), c_int> {
+    unsafe {
+        // zeroed: no flags and an empty mask
+        let mut action = MaybeUninit::<SigAction>::zeroed().assume_init();
+        action.sa_handler = on_resize as extern "C" fn(c_int) as usize;
+
+        let result = sigaction(SIGWINCH, &action, std::ptr::null_mut());
+        if result == -1 {
+            return Err(result);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the terminal has been resized since the last call
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{ErrorKind, Read};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_kill(thread: usize, sig: c_int) -> c_int;
+    }
+
+    #[test]
+    fn resize_interrupts_read() {
+        register_resize_handler().unwrap();
+        take_resized();
+
+        let (mut reader, _writer) = std::io::pipe().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let reading = std::thread::spawn(move || {
+            sender.send(unsafe { pthread_self() }).unwrap();
+            let mut buf = [0; 1];
+            reader.read(&mut buf).map_err(|e| e.kind())
+        });
+
+        // give the thread time to block in read
+        let thread = receiver.recv().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(unsafe { pthread_kill(thread, SIGWINCH) }, 0);
+
+        assert_eq!(reading.join().unwrap(), Err(ErrorKind::Interrupted));
+        assert!(take_resized());
+        assert!(!take_resized());
+    }
+}
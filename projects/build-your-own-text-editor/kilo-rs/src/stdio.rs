@@ -14,6 +14,10 @@ impl BufferedCommands {
         self.buffer.extend_from_slice(cmd);
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
     pub fn execute(&mut self) -> Result<(), std::io::Error> {
         write_command(&self.buffer)?;
         self.buffer.clear();
@@ -27,7 +31,7 @@ extern "C" {
 
 const STDOUT_FILENO: RawFd = 1;
 
-fn write_command(cmd: &[u8]) -> Result<(), std::io::Error> {
+pub(crate) fn write_command(cmd: &[u8]) -> Result<(), std::io::Error> {
     let res = unsafe { write(STDOUT_FILENO, cmd.as_ptr(), cmd.len()) };
 
     if res != cmd.len() as isize {
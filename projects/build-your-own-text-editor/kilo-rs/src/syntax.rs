@@ -0,0 +1,327 @@
+/// The highlight class of a single rendered character
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    Normal,
+    Comment,
+    MultiLineComment,
+    Keyword1,
+    Keyword2,
+    String,
+    Number,
+    Match,
+}
+
+impl Highlight {
+    /// ANSI foreground color code used to draw this class
+    pub fn color(self) -> u8 {
+        match self {
+            Highlight::Comment | Highlight::MultiLineComment => 36,
+            Highlight::Keyword1 => 33,
+            Highlight::Keyword2 => 32,
+            Highlight::String => 35,
+            Highlight::Number => 31,
+            Highlight::Match => 34,
+            Highlight::Normal => 37,
+        }
+    }
+}
+
+pub const HL_NUMBERS: u32 = 1 << 0;
+pub const HL_STRINGS: u32 = 1 << 1;
+/// Lines starting with `#` are headings, highlighted as `Keyword1`
+pub const HL_HEADINGS: u32 = 1 << 2;
+
+/// How to highlight one kind of file
+pub struct Syntax {
+    pub file_type: &'static str,
+    /// File extensions (with the leading dot) or whole file names that select this syntax
+    pub file_match: &'static [&'static str],
+    /// Highlighted as `Keyword1`
+    pub keywords: &'static [&'static str],
+    /// Highlighted as `Keyword2`, typically type names
+    pub types: &'static [&'static str],
+    pub single_line_comment_start: Option<&'static str>,
+    pub multi_line_comment: Option<(&'static str, &'static str)>,
+    pub string_delimiters: &'static [char],
+    pub flags: u32,
+}
+
+// region: filetype database
+
+pub static HLDB: &[Syntax] = &[
+    Syntax {
+        file_type: "c",
+        file_match: &[".c", ".h", ".cpp", ".hpp", ".cc"],
+        keywords: &[
+            "switch", "if", "while", "for", "break", "continue", "return", "else", "struct",
+            "union", "typedef", "static", "enum", "class", "case", "default", "do", "goto",
+            "sizeof", "const", "extern", "#include", "#define", "#ifdef", "#ifndef", "#endif",
+        ],
+        types: &[
+            "int", "long", "double", "float", "char", "unsigned", "signed", "void", "short",
+            "size_t", "bool",
+        ],
+        single_line_comment_start: Some("//"),
+        multi_line_comment: Some(("/*", "*/")),
+        string_delimiters: &['"', '\''],
+        flags: HL_NUMBERS | HL_STRINGS,
+    },
+    Syntax {
+        file_type: "rust",
+        file_match: &[".rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+            "f32", "f64", "bool", "char", "str", "String", "Vec", "Option", "Result", "Box",
+        ],
+        single_line_comment_start: Some("//"),
+        multi_line_comment: Some(("/*", "*/")),
+        string_delimiters: &['"'],
+        flags: HL_NUMBERS | HL_STRINGS,
+    },
+    Syntax {
+        file_type: "python",
+        file_match: &[".py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+            "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+            "with", "yield", "None", "True", "False",
+        ],
+        types: &[
+            "int", "float", "str", "bytes", "list", "dict", "set", "tuple", "bool", "object",
+        ],
+        single_line_comment_start: Some("#"),
+        multi_line_comment: Some(("\"\"\"", "\"\"\"")),
+        string_delimiters: &['"', '\''],
+        flags: HL_NUMBERS | HL_STRINGS,
+    },
+    Syntax {
+        file_type: "markdown",
+        file_match: &[".md", ".markdown"],
+        keywords: &[],
+        types: &[],
+        single_line_comment_start: None,
+        multi_line_comment: Some(("<!--", "-->")),
+        string_delimiters: &['`'],
+        flags: HL_STRINGS | HL_HEADINGS,
+    },
+];
+
+// endregion: filetype database
+
+/// Find the syntax for a file by its name or extension
+pub fn select_syntax(file_name: &str) -> Option<&'static Syntax> {
+    let base_name = file_name.rsplit('/').next().unwrap_or(file_name);
+    let extension = base_name.rfind('.').map(|i| &base_name[i..]);
+
+    HLDB.iter().find(|syntax| {
+        syntax.file_match.iter().any(|pattern| {
+            if pattern.starts_with('.') {
+                extension == Some(*pattern)
+            } else {
+                base_name == *pattern
+            }
+        })
+    })
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == '\0' || ",.()+-/*=~%<>[];{}:&|!?".contains(c)
+}
+
+fn starts_with_at(chars: &[char], at: usize, pattern: &str) -> bool {
+    pattern
+        .chars()
+        .enumerate()
+        .all(|(offset, p)| chars.get(at + offset) == Some(&p))
+}
+
+/// Highlight a rendered row. `in_comment` is whether the previous row ended inside an unclosed
+/// multi-line comment; the returned flag is the same for this row, to be carried to the next.
+pub fn highlight_row(
+    render: &[char],
+    syntax: Option<&Syntax>,
+    in_comment: bool,
+) -> (Vec<Highlight>, bool) {
+    let mut hl = vec![Highlight::Normal; render.len()];
+    let Some(syntax) = syntax else {
+        return (hl, false);
+    };
+
+    if syntax.flags & HL_HEADINGS != 0 && !in_comment && render.first() == Some(&'#') {
+        hl.fill(Highlight::Keyword1);
+        return (hl, false);
+    }
+
+    let mut prev_sep = true;
+    let mut in_string: Option<char> = None;
+    let mut in_comment = in_comment;
+
+    let mut i = 0;
+    while i < render.len() {
+        let c = render[i];
+        let prev_hl = if i > 0 { hl[i - 1] } else { Highlight::Normal };
+
+        if let Some(start) = syntax.single_line_comment_start {
+            if in_string.is_none() && !in_comment && starts_with_at(render, i, start) {
+                hl[i..].fill(Highlight::Comment);
+                break;
+            }
+        }
+
+        if let Some((start, end)) = syntax.multi_line_comment {
+            if in_string.is_none() {
+                if in_comment {
+                    hl[i] = Highlight::MultiLineComment;
+                    if starts_with_at(render, i, end) {
+                        let len = end.chars().count();
+                        hl[i..i + len].fill(Highlight::MultiLineComment);
+                        i += len;
+                        in_comment = false;
+                        prev_sep = true;
+                    } else {
+                        i += 1;
+                    }
+                    continue;
+                } else if starts_with_at(render, i, start) {
+                    let len = start.chars().count();
+                    hl[i..i + len].fill(Highlight::MultiLineComment);
+                    i += len;
+                    in_comment = true;
+                    continue;
+                }
+            }
+        }
+
+        if syntax.flags & HL_STRINGS != 0 {
+            if let Some(delimiter) = in_string {
+                hl[i] = Highlight::String;
+                if c == '\\' && i + 1 < render.len() {
+                    hl[i + 1] = Highlight::String;
+                    i += 2;
+                    continue;
+                }
+                if c == delimiter {
+                    in_string = None;
+                }
+                i += 1;
+                prev_sep = true;
+                continue;
+            } else if syntax.string_delimiters.contains(&c) {
+                in_string = Some(c);
+                hl[i] = Highlight::String;
+                i += 1;
+                continue;
+            }
+        }
+
+        if syntax.flags & HL_NUMBERS != 0
+            && ((c.is_ascii_digit() && (prev_sep || prev_hl == Highlight::Number))
+                || (c == '.' && prev_hl == Highlight::Number))
+        {
+            hl[i] = Highlight::Number;
+            i += 1;
+            prev_sep = false;
+            continue;
+        }
+
+        if prev_sep {
+            let keyword = syntax
+                .keywords
+                .iter()
+                .map(|k| (k, Highlight::Keyword1))
+                .chain(syntax.types.iter().map(|k| (k, Highlight::Keyword2)))
+                .find(|(k, _)| {
+                    let len = k.chars().count();
+                    starts_with_at(render, i, k)
+                        && render.get(i + len).is_none_or(|c| is_separator(*c))
+                });
+
+            if let Some((k, class)) = keyword {
+                let len = k.chars().count();
+                hl[i..i + len].fill(class);
+                i += len;
+                prev_sep = false;
+                continue;
+            }
+        }
+
+        prev_sep = is_separator(c);
+        i += 1;
+    }
+
+    (hl, in_comment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(line: &str, file_name: &str, in_comment: bool) -> (Vec<Highlight>, bool) {
+        let chars = line.chars().collect::<Vec<_>>();
+        highlight_row(&chars, select_syntax(file_name), in_comment)
+    }
+
+    #[test]
+    fn filetype_by_extension() {
+        assert_eq!(select_syntax("src/main.rs").unwrap().file_type, "rust");
+        assert_eq!(select_syntax("kilo.c").unwrap().file_type, "c");
+        assert_eq!(select_syntax("setup.py").unwrap().file_type, "python");
+        assert_eq!(select_syntax("README.md").unwrap().file_type, "markdown");
+        assert!(select_syntax("notes.txt").is_none());
+        assert!(select_syntax("Makefile").is_none());
+    }
+
+    #[test]
+    fn keywords_numbers_and_strings() {
+        use Highlight::*;
+
+        let (hl, open) = highlight("let x: u8 = 42; \"a\\\"b\"", "a.rs", false);
+        assert!(!open);
+        assert_eq!(&hl[0..3], &[Keyword1; 3]);
+        assert_eq!(hl[3], Normal);
+        assert_eq!(&hl[7..9], &[Keyword2; 2]);
+        assert_eq!(&hl[12..14], &[Number; 2]);
+        assert_eq!(&hl[16..22], &[String; 6]);
+
+        // keywords must be whole words and numbers must start at a separator
+        let (hl, _) = highlight("letter x1", "a.rs", false);
+        assert!(hl.iter().all(|h| *h == Normal));
+    }
+
+    #[test]
+    fn comments() {
+        use Highlight::*;
+
+        let (hl, open) = highlight("x = 1 # note", "a.py", false);
+        assert!(!open);
+        assert_eq!(&hl[6..], &[Comment; 6]);
+
+        let (hl, open) = highlight("int /* open", "a.c", false);
+        assert!(open);
+        assert_eq!(&hl[4..], &[MultiLineComment; 7]);
+
+        let (hl, open) = highlight("still */ int", "a.c", true);
+        assert!(!open);
+        assert_eq!(&hl[..8], &[MultiLineComment; 8]);
+        assert_eq!(&hl[9..], &[Keyword2; 3]);
+    }
+
+    #[test]
+    fn markdown_headings_and_code() {
+        use Highlight::*;
+
+        let (hl, _) = highlight("# Title", "a.md", false);
+        assert!(hl.iter().all(|h| *h == Keyword1));
+
+        let (hl, _) = highlight("run `make` 2", "a.md", false);
+        assert_eq!(&hl[4..10], &[String; 6]);
+        assert_eq!(hl[11], Normal);
+    }
+}
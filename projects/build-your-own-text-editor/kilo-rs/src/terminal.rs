@@ -0,0 +1,384 @@
+//! Where the editor reads keys from and draws to.
+//!
+//! `Tty` is the terminal the editor runs in. `VirtualTerminal` is an in-memory screen that
+//! interprets the escape sequences the editor draws with, so that the editor can be driven by
+//! scripted input in tests and the result read back as text.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::rc::Rc;
+
+use crate::signal::take_resized;
+use crate::stdio::write_command;
+use crate::unicode::char_width;
+use crate::window::get_window_size;
+
+pub trait Terminal {
+    /// Read a byte of input, or `None` if there was none within the read timeout
+    fn read_byte(&mut self) -> Option<u8>;
+    /// Read a byte of input if there is one, for checking on keys while busy with something else
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.read_byte()
+    }
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()>;
+    /// The size of the screen as (rows, columns)
+    fn size(&mut self) -> std::io::Result<(usize, usize)>;
+    /// Whether the screen has changed size since the last call
+    fn take_resized(&mut self) -> bool;
+}
+
+/// The terminal on stdin and stdout, which should be in raw mode
+pub struct Tty;
+
+impl Terminal for Tty {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buffer = [0; 1];
+        std::io::stdin()
+            .lock()
+            .read(&mut buffer)
+            .is_ok_and(|n| n == 1)
+            .then_some(buffer[0])
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        write_command(bytes)
+    }
+
+    fn size(&mut self) -> std::io::Result<(usize, usize)> {
+        get_window_size()
+    }
+
+    fn take_resized(&mut self) -> bool {
+        take_resized()
+    }
+}
+
+/// A character cell of a virtual screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// The grapheme cluster drawn in the cell, empty for the second column of a wide character
+    pub text: String,
+    /// The ANSI foreground color code, if not the default
+    pub fg: Option<u8>,
+    pub reverse: bool,
+}
+
+impl Cell {
+    fn blank() -> Self {
+        Self {
+            text: " ".to_string(),
+            fg: None,
+            reverse: false,
+        }
+    }
+}
+
+struct Screen {
+    rows: usize,
+    cols: usize,
+    cells: Vec<Vec<Cell>>,
+    cursor: (usize, usize),
+    cursor_visible: bool,
+    fg: Option<u8>,
+    reverse: bool,
+    input: VecDeque<u8>,
+    // whether the last read found no input, so that another read would wait forever
+    starved: bool,
+    resized: bool,
+    osc: Vec<String>,
+}
+
+/// An in-memory terminal. Clones share the same screen, so a test can keep one to type into and
+/// inspect while the editor owns another.
+#[derive(Clone)]
+pub struct VirtualTerminal {
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl VirtualTerminal {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            screen: Rc::new(RefCell::new(Screen {
+                rows,
+                cols,
+                cells: vec![vec![Cell::blank(); cols]; rows],
+                cursor: (0, 0),
+                cursor_visible: true,
+                fg: None,
+                reverse: false,
+                input: VecDeque::new(),
+                starved: false,
+                resized: false,
+                osc: vec![],
+            })),
+        }
+    }
+
+    /// Queue input for the editor to read
+    pub fn send(&self, input: &[u8]) {
+        let mut screen = self.screen.borrow_mut();
+        screen.input.extend(input);
+        screen.starved = false;
+    }
+
+    pub fn has_input(&self) -> bool {
+        !self.screen.borrow().input.is_empty()
+    }
+
+    /// Change the size of the screen, clearing it as a terminal would be redrawn
+    pub fn resize(&self, rows: usize, cols: usize) {
+        let mut screen = self.screen.borrow_mut();
+        screen.rows = rows;
+        screen.cols = cols;
+        screen.cells = vec![vec![Cell::blank(); cols]; rows];
+        screen.cursor = (0, 0);
+        screen.resized = true;
+    }
+
+    /// The text on row `y`, without trailing spaces
+    pub fn row(&self, y: usize) -> String {
+        let screen = self.screen.borrow();
+        let text = screen.cells[y]
+            .iter()
+            .map(|cell| cell.text.as_str())
+            .collect::<String>();
+        text.trim_end().to_string()
+    }
+
+    /// The text of every row
+    pub fn rows(&self) -> Vec<String> {
+        (0..self.screen.borrow().rows)
+            .map(|y| self.row(y))
+            .collect()
+    }
+
+    pub fn cell(&self, y: usize, x: usize) -> Cell {
+        self.screen.borrow().cells[y][x].clone()
+    }
+
+    /// The cursor position as (row, column)
+    pub fn cursor(&self) -> (usize, usize) {
+        self.screen.borrow().cursor
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.screen.borrow().cursor_visible
+    }
+
+    /// The operating system commands received, such as OSC 52 clipboard copies, without their
+    /// `ESC ]` and terminator
+    pub fn osc(&self) -> Vec<String> {
+        self.screen.borrow().osc.clone()
+    }
+}
+
+impl Terminal for VirtualTerminal {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut screen = self.screen.borrow_mut();
+        let byte = screen.input.pop_front();
+        if byte.is_none() {
+            // a real terminal would block here until a key arrived, so a test that doesn't
+            // script enough input would hang
+            assert!(!screen.starved, "the editor is waiting for more input");
+            screen.starved = true;
+        }
+        byte
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.screen.borrow_mut().input.pop_front()
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.screen
+            .borrow_mut()
+            .write(&String::from_utf8_lossy(bytes));
+        Ok(())
+    }
+
+    fn size(&mut self) -> std::io::Result<(usize, usize)> {
+        let screen = self.screen.borrow();
+        Ok((screen.rows, screen.cols))
+    }
+
+    fn take_resized(&mut self) -> bool {
+        std::mem::take(&mut self.screen.borrow_mut().resized)
+    }
+}
+
+impl Screen {
+    /// Interpret output. Escape sequences must not be split between writes, which the editor
+    /// never does as it draws each frame in one write.
+    fn write(&mut self, output: &str) {
+        let mut chars = output.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => match chars.next() {
+                    Some('[') => {
+                        let mut params = String::new();
+                        while let Some(&c) = chars.peek() {
+                            if !matches!(c, '0'..='9' | ';' | '?') {
+                                break;
+                            }
+                            params.push(c);
+                            chars.next();
+                        }
+                        if let Some(command) = chars.next() {
+                            self.control_sequence(&params, command);
+                        }
+                    }
+                    Some(']') => {
+                        let mut command = String::new();
+                        for c in chars.by_ref() {
+                            if c == '\x07' {
+                                break;
+                            }
+                            command.push(c);
+                        }
+                        self.osc.push(command);
+                    }
+                    _ => {}
+                },
+                '\r' => self.cursor.1 = 0,
+                '\n' => self.line_feed(),
+                c if c.is_control() => {}
+                c => self.print(c),
+            }
+        }
+    }
+
+    fn control_sequence(&mut self, params: &str, command: char) {
+        let private = params.starts_with('?');
+        let args = params
+            .trim_start_matches('?')
+            .split(';')
+            .map(|arg| arg.parse::<usize>().unwrap_or(0))
+            .collect::<Vec<_>>();
+        let arg = |i: usize| args.get(i).copied().unwrap_or(0);
+        let (y, x) = self.cursor;
+        match command {
+            // cursor position, 1-based
+            'H' => {
+                let row = arg(0).max(1).min(self.rows);
+                let col = arg(1).max(1).min(self.cols);
+                self.cursor = (row - 1, col - 1);
+            }
+            'C' => self.cursor.1 = (x + arg(0).max(1)).min(self.cols - 1),
+            'B' => self.cursor.0 = (y + arg(0).max(1)).min(self.rows - 1),
+            // erase to the end of the line
+            'K' => self.cells[y][x.min(self.cols)..].fill(Cell::blank()),
+            // erase characters from the cursor
+            'X' => {
+                let end = (x + arg(0).max(1)).min(self.cols);
+                self.cells[y][x.min(end)..end].fill(Cell::blank());
+            }
+            'm' => {
+                for &arg in &args {
+                    match arg {
+                        0 => {
+                            self.fg = None;
+                            self.reverse = false;
+                        }
+                        7 => self.reverse = true,
+                        27 => self.reverse = false,
+                        39 => self.fg = None,
+                        30..=37 => self.fg = Some(arg as u8),
+                        _ => {}
+                    }
+                }
+            }
+            'h' | 'l' if private && arg(0) == 25 => self.cursor_visible = command == 'h',
+            _ => {}
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor.0 + 1 < self.rows {
+            self.cursor.0 += 1;
+        } else {
+            self.cells.remove(0);
+            self.cells.push(vec![Cell::blank(); self.cols]);
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        let width = char_width(c);
+        let (y, x) = self.cursor;
+        if width == 0 {
+            // combining characters join the cell before
+            if let Some(cell) = x.checked_sub(1).map(|x| &mut self.cells[y][x]) {
+                cell.text.push(c);
+            }
+            return;
+        }
+
+        if x + width > self.cols {
+            self.cursor.1 = 0;
+            self.line_feed();
+        }
+        let (y, x) = self.cursor;
+        self.cells[y][x] = Cell {
+            text: c.to_string(),
+            fg: self.fg,
+            reverse: self.reverse,
+        };
+        if width == 2 {
+            self.cells[y][x + 1] = Cell {
+                text: String::new(),
+                ..self.cells[y][x].clone()
+            };
+        }
+        self.cursor.1 = x + width;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interprets_output() {
+        let mut terminal = VirtualTerminal::new(3, 10);
+        let output =
+            "\x1b[?25lhello\r\nworld\x1b[1;3H\x1b[2X\x1b[2;1H\x1b[7m日本\x1b[27m\x1b[K\x1b[?25h";
+        terminal.write_all(output.as_bytes()).unwrap();
+
+        assert_eq!(terminal.rows(), vec!["he  o", "日本", ""]);
+        assert!(terminal.cell(1, 0).reverse);
+        assert_eq!(terminal.cell(1, 1).text, "");
+        assert!(!terminal.cell(1, 4).reverse);
+        assert_eq!(terminal.cursor(), (1, 4));
+        assert!(terminal.cursor_visible());
+
+        terminal
+            .write_all(b"\x1b]52;c;aGkK\x07\x1b[34mx\x1b[39m")
+            .unwrap();
+        assert_eq!(terminal.osc(), vec!["52;c;aGkK"]);
+        assert_eq!(terminal.cell(1, 4).fg, Some(34));
+    }
+
+    #[test]
+    fn scripted_input() {
+        let terminal = VirtualTerminal::new(3, 10);
+        let mut editor_side = terminal.clone();
+        terminal.send(b"ab");
+        assert_eq!(editor_side.read_byte(), Some(b'a'));
+        assert_eq!(editor_side.read_byte(), Some(b'b'));
+        assert_eq!(editor_side.read_byte(), None);
+        assert!(!terminal.has_input());
+
+        terminal.resize(5, 20);
+        assert!(editor_side.take_resized());
+        assert!(!editor_side.take_resized());
+        assert_eq!(editor_side.size().unwrap(), (5, 20));
+    }
+
+    #[test]
+    #[should_panic(expected = "waiting for more input")]
+    fn starved_read_panics() {
+        let mut terminal = VirtualTerminal::new(3, 10);
+        terminal.read_byte();
+        terminal.read_byte();
+    }
+}
@@ -1,18 +1,90 @@
 use std::mem::MaybeUninit;
-use std::os::raw::{c_int, c_ulong};
+use std::os::fd::RawFd;
+use std::os::raw::c_int;
 use std::sync::Mutex;
 
-#[repr(C)]
-#[derive(Clone, Copy)]
+use sys::*;
+
+use crate::stdio::write_command;
+
+// The layout of `struct termios` and the values of its flags differ between platforms, so each
+// supported target declares its own.
+
+#[cfg(target_os = "linux")]
+#[allow(non_camel_case_types)]
+mod sys {
+    use std::os::raw::{c_int, c_uint};
+
+    pub type tcflag_t = c_uint;
+    pub const NCCS: usize = 32;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: tcflag_t,
+        pub c_oflag: tcflag_t,
+        pub c_cflag: tcflag_t,
+        pub c_lflag: tcflag_t,
+        pub c_line: u8,
+        pub c_cc: [u8; NCCS],
+        pub c_ispeed: c_uint,
+        pub c_ospeed: c_uint,
+    }
+
+    pub const ECHO: tcflag_t = 0o10;
+    pub const ICANON: tcflag_t = 0o2;
+    pub const ISIG: tcflag_t = 0o1;
+    pub const IXON: tcflag_t = 0o2000;
+    pub const IEXTEN: tcflag_t = 0o100000;
+    pub const ICRNL: tcflag_t = 0o400;
+    pub const OPOST: tcflag_t = 0o1;
+    pub const BRKINT: tcflag_t = 0o2;
+    pub const INPCK: tcflag_t = 0o20;
+    pub const ISTRIP: tcflag_t = 0o40;
+    pub const CS8: tcflag_t = 0o60;
+
+    pub const TCSAFLUSH: c_int = 2;
+
+    pub const VMIN: usize = 6;
+    pub const VTIME: usize = 5;
+}
+
 #[cfg(target_os = "macos")]
-struct Termios {
-    c_iflag: c_ulong,
-    c_oflag: c_ulong,
-    c_cflag: c_ulong,
-    c_lflag: c_ulong,
-    c_cc: [u8; 20],
-    c_ispeed: c_ulong,
-    c_ospeed: c_ulong,
+#[allow(non_camel_case_types)]
+mod sys {
+    use std::os::raw::{c_int, c_ulong};
+
+    pub type tcflag_t = c_ulong;
+    pub const NCCS: usize = 20;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: tcflag_t,
+        pub c_oflag: tcflag_t,
+        pub c_cflag: tcflag_t,
+        pub c_lflag: tcflag_t,
+        pub c_cc: [u8; NCCS],
+        pub c_ispeed: c_ulong,
+        pub c_ospeed: c_ulong,
+    }
+
+    pub const ECHO: tcflag_t = 0x00000008;
+    pub const ICANON: tcflag_t = 0x00000100;
+    pub const ISIG: tcflag_t = 0x00000080;
+    pub const IXON: tcflag_t = 0x00000200;
+    pub const IEXTEN: tcflag_t = 0x00000400;
+    pub const ICRNL: tcflag_t = 0x00000100;
+    pub const OPOST: tcflag_t = 0x00000001;
+    pub const BRKINT: tcflag_t = 0x00000002;
+    pub const INPCK: tcflag_t = 0x00000010;
+    pub const ISTRIP: tcflag_t = 0x00000020;
+    pub const CS8: tcflag_t = 0x00000300;
+
+    pub const TCSAFLUSH: c_int = 2;
+
+    pub const VMIN: usize = 16;
+    pub const VTIME: usize = 17;
 }
 
 extern "C" {
@@ -21,38 +93,37 @@ extern "C" {
     fn tcsetattr(fd: c_int, optional_actions: c_int, termios_p: *const Termios) -> c_int;
 }
 
-const ECHO: c_ulong = 0o10;
-const ICANON: c_ulong = 0x00000100;
-const ISIG: c_ulong = 0x00000080;
-const IXON: c_ulong = 0x00000200;
-const IEXTEN: c_ulong = 0x00000400;
-const ICRNL: c_ulong = 0x00000100;
-const OPOST: c_ulong = 0x00000001;
-const BRKINT: c_ulong = 0x00000002;
-const INPCK: c_ulong = 0x00000010;
-const ISTRIP: c_ulong = 0x00000020;
-const CS8: c_ulong = 0x00000300;
-const IFLAG_MASK: c_ulong = BRKINT | ICRNL | INPCK | ISTRIP | IXON;
-const OFLAG_MASK: c_ulong = OPOST;
-const CFLAG_MASK: c_ulong = CS8;
-const LFLAG_MASK: c_ulong = ECHO | ICANON | IEXTEN | ISIG;
-
-const STDIN_FILENO: c_int = 0;
-const TCSAFLUSH: c_int = 2;
-
-const VMIN: usize = 16;
-const VTIME: usize = 17;
+const IFLAG_MASK: tcflag_t = BRKINT | ICRNL | INPCK | ISTRIP | IXON;
+const OFLAG_MASK: tcflag_t = OPOST;
+const CFLAG_MASK: tcflag_t = CS8;
+const LFLAG_MASK: tcflag_t = ECHO | ICANON | IEXTEN | ISIG;
+
+const STDIN_FILENO: RawFd = 0;
+
+// report mouse buttons, drags and the wheel as input, in the SGR format, and stop again
+const MOUSE_REPORTING_ON: &[u8] = b"\x1b[?1002h\x1b[?1006h";
+const MOUSE_REPORTING_OFF: &[u8] = b"\x1b[?1006l\x1b[?1002l";
 
+/// Put the terminal on stdin into raw mode, with the mouse reported as input until raw mode is
+/// disabled
 pub fn enable_raw_mode() -> Result<(), c_int> {
+    enable_raw_mode_fd(STDIN_FILENO)?;
+    write_command(MOUSE_REPORTING_ON).map_err(|e| e.raw_os_error().unwrap_or(-1))
+}
+
+/// Put the terminal referred to by `fd` into raw mode, remembering its current mode so that it
+/// can be restored by `disable_raw_mode` or on exit.
+pub fn enable_raw_mode_fd(fd: RawFd) -> Result<(), c_int> {
     let mut original_termios = TERMINAL_MODE_PRIOR_RAW_MODE.lock().unwrap();
     if original_termios.is_some() {
         println!("raw mode already enabled");
         return Ok(());
     }
 
-    *original_termios = get_termios().ok();
+    *original_termios = get_termios(fd).ok().map(|termios| (fd, termios));
 
     update_termios(
+        fd,
         |iflag| iflag & !IFLAG_MASK,
         |oflag| oflag & !OFLAG_MASK,
         |cflag| cflag | CFLAG_MASK,
@@ -68,19 +139,21 @@ pub fn enable_raw_mode() -> Result<(), c_int> {
     Ok(())
 }
 
-fn disable_raw_mode() -> Result<(), c_int> {
+pub fn disable_raw_mode() -> Result<(), c_int> {
     let mut original_mode = TERMINAL_MODE_PRIOR_RAW_MODE.lock().unwrap();
-    if let Some(termios) = original_mode.take() {
-        set_termios(&termios)?;
-        *original_mode = None;
+    if let Some((fd, termios)) = original_mode.take() {
+        if fd == STDIN_FILENO {
+            _ = write_command(MOUSE_REPORTING_OFF);
+        }
+        set_termios(fd, &termios)?;
     }
 
     Ok(())
 }
 
-// Some(Termios) -> we're in the raw mode and this is the previous mode
+// Some((fd, Termios)) -> the terminal on fd is in the raw mode and this is the previous mode
 // None -> we're not in the raw mode
-static TERMINAL_MODE_PRIOR_RAW_MODE: Mutex<Option<Termios>> = Mutex::new(None);
+static TERMINAL_MODE_PRIOR_RAW_MODE: Mutex<Option<(RawFd, Termios)>> = Mutex::new(None);
 
 extern "C" fn disable_raw_mode_on_exit() {
     disable_raw_mode().expect("failed to disable raw mode");
@@ -98,13 +171,14 @@ fn register_exit_cleanup() -> Result<(), c_int> {
 }
 
 fn update_termios(
-    i_flag: impl FnOnce(c_ulong) -> c_ulong,
-    o_flag: impl FnOnce(c_ulong) -> c_ulong,
-    c_flag: impl FnOnce(c_ulong) -> c_ulong,
-    l_flag: impl FnOnce(c_ulong) -> c_ulong,
-    cc: impl FnOnce([u8; 20]) -> [u8; 20],
+    fd: RawFd,
+    i_flag: impl FnOnce(tcflag_t) -> tcflag_t,
+    o_flag: impl FnOnce(tcflag_t) -> tcflag_t,
+    c_flag: impl FnOnce(tcflag_t) -> tcflag_t,
+    l_flag: impl FnOnce(tcflag_t) -> tcflag_t,
+    cc: impl FnOnce([u8; NCCS]) -> [u8; NCCS],
 ) -> Result<(), c_int> {
-    let mut termios = get_termios()?;
+    let mut termios = get_termios(fd)?;
 
     termios.c_iflag = i_flag(termios.c_iflag);
     termios.c_oflag = o_flag(termios.c_oflag);
@@ -112,13 +186,13 @@ fn update_termios(
     termios.c_lflag = l_flag(termios.c_lflag);
     termios.c_cc = cc(termios.c_cc);
 
-    set_termios(&termios)
+    set_termios(fd, &termios)
 }
 
-fn get_termios() -> Result<Termios, c_int> {
+fn get_termios(fd: RawFd) -> Result<Termios, c_int> {
     unsafe {
         let mut termios = MaybeUninit::<Termios>::uninit();
-        let result = tcgetattr(STDIN_FILENO, termios.as_mut_ptr());
+        let result = tcgetattr(fd, termios.as_mut_ptr());
         if result == -1 {
             return Err(result);
         }
@@ -127,9 +201,9 @@ fn get_termios() -> Result<Termios, c_int> {
     }
 }
 
-fn set_termios(termios: &Termios) -> Result<(), c_int> {
+fn set_termios(fd: RawFd, termios: &Termios) -> Result<(), c_int> {
     unsafe {
-        let result = tcsetattr(STDIN_FILENO, TCSAFLUSH, termios);
+        let result = tcsetattr(fd, TCSAFLUSH, termios);
         if result == -1 {
             return Err(result);
         }
@@ -137,3 +211,36 @@ fn set_termios(termios: &Termios) -> Result<(), c_int> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pty::Pty;
+
+    #[test]
+    fn raw_mode_round_trip() {
+        let pty = Pty::open().expect("failed to open pty");
+        let fd = pty.slave_fd();
+
+        let cooked = get_termios(fd).unwrap();
+        assert_ne!(cooked.c_lflag & ICANON, 0);
+        assert_ne!(cooked.c_lflag & ECHO, 0);
+
+        enable_raw_mode_fd(fd).unwrap();
+        let raw = get_termios(fd).unwrap();
+        assert_eq!(raw.c_lflag & LFLAG_MASK, 0);
+        assert_eq!(raw.c_iflag & IFLAG_MASK, 0);
+        assert_eq!(raw.c_oflag & OFLAG_MASK, 0);
+        assert_eq!(raw.c_cflag & CS8, CS8);
+        assert_eq!(raw.c_cc[VMIN], 0);
+        assert_eq!(raw.c_cc[VTIME], 1);
+
+        disable_raw_mode().unwrap();
+        let restored = get_termios(fd).unwrap();
+        assert_eq!(restored.c_iflag, cooked.c_iflag);
+        assert_eq!(restored.c_oflag, cooked.c_oflag);
+        assert_eq!(restored.c_cflag, cooked.c_cflag);
+        assert_eq!(restored.c_lflag, cooked.c_lflag);
+        assert_eq!(restored.c_cc, cooked.c_cc);
+    }
+}
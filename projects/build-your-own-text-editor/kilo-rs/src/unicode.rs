@@ -0,0 +1,361 @@
+//! Display width and grapheme clusters, enough for editing CJK text and emoji in a terminal.
+//!
+//! The tables cover the East Asian Wide and Fullwidth blocks, emoji drawn with wide presentation
+//! and the common combining marks; they approximate the Unicode data rather than reproduce it.
+//! Clusters follow the core rules of UAX #29: marks, joiners and modifiers extend the character
+//! before them, a zero width joiner glues two characters together and regional indicators pair up
+//! into flags.
+
+use std::ops::Range;
+
+const ZWJ: char = '\u{200d}';
+
+// sorted, inclusive ranges of characters that take two columns
+static WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f),
+    (0x231a, 0x231b),
+    (0x2329, 0x232a),
+    (0x23e9, 0x23ec),
+    (0x23f0, 0x23f0),
+    (0x23f3, 0x23f3),
+    (0x25fd, 0x25fe),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x267f, 0x267f),
+    (0x2693, 0x2693),
+    (0x26a1, 0x26a1),
+    (0x26aa, 0x26ab),
+    (0x26bd, 0x26be),
+    (0x26c4, 0x26c5),
+    (0x26ce, 0x26ce),
+    (0x26d4, 0x26d4),
+    (0x26ea, 0x26ea),
+    (0x26f2, 0x26f3),
+    (0x26f5, 0x26f5),
+    (0x26fa, 0x26fa),
+    (0x26fd, 0x26fd),
+    (0x2705, 0x2705),
+    (0x270a, 0x270b),
+    (0x2728, 0x2728),
+    (0x274c, 0x274c),
+    (0x274e, 0x274e),
+    (0x2753, 0x2755),
+    (0x2757, 0x2757),
+    (0x2795, 0x2797),
+    (0x27b0, 0x27b0),
+    (0x27bf, 0x27bf),
+    (0x2b1b, 0x2b1c),
+    (0x2b50, 0x2b50),
+    (0x2b55, 0x2b55),
+    (0x2e80, 0x303e),
+    (0x3041, 0x33ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf),
+    (0xa960, 0xa97f),
+    (0xac00, 0xd7a3),
+    (0xf900, 0xfaff),
+    (0xfe10, 0xfe19),
+    (0xfe30, 0xfe6f),
+    (0xff00, 0xff60),
+    (0xffe0, 0xffe6),
+    (0x16fe0, 0x16fe4),
+    (0x17000, 0x18aff),
+    (0x1b000, 0x1b16f),
+    (0x1f004, 0x1f004),
+    (0x1f0cf, 0x1f0cf),
+    (0x1f18e, 0x1f18e),
+    (0x1f191, 0x1f19a),
+    (0x1f1e6, 0x1f1ff),
+    (0x1f200, 0x1f251),
+    (0x1f300, 0x1f320),
+    (0x1f32d, 0x1f335),
+    (0x1f337, 0x1f37c),
+    (0x1f37e, 0x1f393),
+    (0x1f3a0, 0x1f3ca),
+    (0x1f3cf, 0x1f3d3),
+    (0x1f3e0, 0x1f3f0),
+    (0x1f3f4, 0x1f3f4),
+    (0x1f3f8, 0x1f3fa),
+    (0x1f400, 0x1f43e),
+    (0x1f440, 0x1f440),
+    (0x1f442, 0x1f4fc),
+    (0x1f4ff, 0x1f53d),
+    (0x1f54b, 0x1f54e),
+    (0x1f550, 0x1f567),
+    (0x1f57a, 0x1f57a),
+    (0x1f595, 0x1f596),
+    (0x1f5a4, 0x1f5a4),
+    (0x1f5fb, 0x1f64f),
+    (0x1f680, 0x1f6c5),
+    (0x1f6cc, 0x1f6cc),
+    (0x1f6d0, 0x1f6d2),
+    (0x1f6d5, 0x1f6d7),
+    (0x1f6dc, 0x1f6df),
+    (0x1f6eb, 0x1f6ec),
+    (0x1f6f4, 0x1f6fc),
+    (0x1f7e0, 0x1f7eb),
+    (0x1f7f0, 0x1f7f0),
+    (0x1f90c, 0x1f93a),
+    (0x1f93c, 0x1f945),
+    (0x1f947, 0x1f9ff),
+    (0x1fa70, 0x1faff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+];
+
+// sorted, inclusive ranges of combining marks, joiners, variation selectors and emoji modifiers,
+// which attach to the character before them and take no columns of their own
+static EXTEND: &[(u32, u32)] = &[
+    (0x0300, 0x036f),
+    (0x0483, 0x0489),
+    (0x0591, 0x05bd),
+    (0x05bf, 0x05bf),
+    (0x05c1, 0x05c2),
+    (0x05c4, 0x05c5),
+    (0x05c7, 0x05c7),
+    (0x0610, 0x061a),
+    (0x064b, 0x065f),
+    (0x0670, 0x0670),
+    (0x06d6, 0x06dc),
+    (0x06df, 0x06e4),
+    (0x06e7, 0x06e8),
+    (0x06ea, 0x06ed),
+    (0x0900, 0x0902),
+    (0x093a, 0x093a),
+    (0x093c, 0x093c),
+    (0x0941, 0x0948),
+    (0x094d, 0x094d),
+    (0x0951, 0x0957),
+    (0x0962, 0x0963),
+    (0x0e31, 0x0e31),
+    (0x0e34, 0x0e3a),
+    (0x0e47, 0x0e4e),
+    (0x1ab0, 0x1aff),
+    (0x1dc0, 0x1dff),
+    (0x200c, 0x200d),
+    (0x20d0, 0x20ff),
+    (0x302a, 0x302d),
+    (0x3099, 0x309a),
+    (0xfe00, 0xfe0f),
+    (0xfe20, 0xfe2f),
+    (0x1f3fb, 0x1f3ff),
+    (0xe0020, 0xe007f),
+    (0xe0100, 0xe01ef),
+];
+
+// invisible formatting characters that are not part of a cluster
+static ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x200b, 0x200b),
+    (0x200e, 0x200f),
+    (0x2028, 0x202e),
+    (0x2060, 0x2064),
+    (0xfeff, 0xfeff),
+];
+
+fn in_table(c: char, table: &[(u32, u32)]) -> bool {
+    let c = c as u32;
+    let i = table.partition_point(|&(_, last)| last < c);
+    table.get(i).is_some_and(|&(first, _)| first <= c)
+}
+
+fn is_extend(c: char) -> bool {
+    in_table(c, EXTEND)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Number of columns a character takes on its own. Control characters count as one column, as
+/// the editor draws them as a single symbol.
+pub fn char_width(c: char) -> usize {
+    if is_extend(c) || in_table(c, ZERO_WIDTH) {
+        0
+    } else if in_table(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Number of columns a grapheme cluster takes: its first character's width, or two if it asks for
+/// emoji presentation
+pub fn grapheme_width(cluster: &[char]) -> usize {
+    let width = cluster.first().map_or(0, |&c| char_width(c));
+    if cluster.contains(&'\u{fe0f}') {
+        width.max(2)
+    } else {
+        width
+    }
+}
+
+/// Whether a cluster starts at index `i`. The start and end of the chars are boundaries.
+pub fn is_grapheme_boundary(chars: &[char], i: usize) -> bool {
+    if i == 0 || i >= chars.len() {
+        return true;
+    }
+
+    let (prev, c) = (chars[i - 1], chars[i]);
+    if is_extend(c) || prev == ZWJ {
+        return false;
+    }
+    if is_regional_indicator(prev) && is_regional_indicator(c) {
+        // indicators pair up from the start of the run, so break before the odd ones
+        let run = chars[..i]
+            .iter()
+            .rev()
+            .take_while(|c| is_regional_indicator(**c))
+            .count();
+        return run % 2 == 0;
+    }
+
+    true
+}
+
+/// Start of the cluster after the one at `i`
+pub fn next_grapheme_boundary(chars: &[char], i: usize) -> usize {
+    let mut i = i + 1;
+    while i < chars.len() && !is_grapheme_boundary(chars, i) {
+        i += 1;
+    }
+    i.min(chars.len())
+}
+
+/// Start of the cluster before `i`
+pub fn prev_grapheme_boundary(chars: &[char], i: usize) -> usize {
+    let mut i = i.saturating_sub(1);
+    while i > 0 && !is_grapheme_boundary(chars, i) {
+        i -= 1;
+    }
+    i
+}
+
+/// Start of the cluster containing `i`
+pub fn grapheme_floor(chars: &[char], i: usize) -> usize {
+    if is_grapheme_boundary(chars, i) {
+        i.min(chars.len())
+    } else {
+        prev_grapheme_boundary(chars, i)
+    }
+}
+
+/// The index ranges of each cluster in turn
+pub fn graphemes(chars: &[char]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start >= chars.len() {
+            return None;
+        }
+        let end = next_grapheme_boundary(chars, start);
+        let range = start..end;
+        start = end;
+        Some(range)
+    })
+}
+
+/// Number of columns a string takes
+pub fn str_width(s: &str) -> usize {
+    let chars = s.chars().collect::<Vec<_>>();
+    graphemes(&chars).map(|g| grapheme_width(&chars[g])).sum()
+}
+
+/// The longest prefix of a string that fits in `width` columns
+pub fn truncate_to_width(s: &str, width: usize) -> &str {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut columns = 0;
+    let mut bytes = 0;
+    for g in graphemes(&chars) {
+        columns += grapheme_width(&chars[g.clone()]);
+        if columns > width {
+            break;
+        }
+        bytes += chars[g].iter().map(|c| c.len_utf8()).sum::<usize>();
+    }
+    &s[..bytes]
+}
+
+/// Length of the UTF-8 sequence started by a byte, or `None` if it can't start one
+pub fn utf8_sequence_len(first: u8) -> Option<usize> {
+    match first {
+        0x00..=0x7f => Some(1),
+        0xc2..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf4 => Some(4),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn clusters(s: &str) -> Vec<String> {
+        let chars = chars(s);
+        graphemes(&chars)
+            .map(|g| chars[g].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('\t'), 1);
+        assert_eq!(char_width('日'), 2);
+        assert_eq!(char_width('Ａ'), 2);
+        assert_eq!(char_width('ｱ'), 1);
+        assert_eq!(char_width('한'), 2);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('😀'), 2);
+        assert_eq!(str_width("日本語 text"), 11);
+        assert_eq!(str_width("e\u{301}"), 1);
+        assert_eq!(str_width("👍🏽"), 2);
+        assert_eq!(str_width("❤\u{fe0f}"), 2);
+    }
+
+    #[test]
+    fn grapheme_clusters() {
+        assert_eq!(clusters("ae\u{301}b"), ["a", "e\u{301}", "b"]);
+        // a family joined by zero width joiners, and a skin tone modifier
+        assert_eq!(
+            clusters("👨\u{200d}👩\u{200d}👧!👍🏽"),
+            ["👨\u{200d}👩\u{200d}👧", "!", "👍🏽"]
+        );
+        // three regional indicators: one flag and a lone indicator
+        assert_eq!(clusters("🇯🇵🇫"), ["🇯🇵", "🇫"]);
+    }
+
+    #[test]
+    fn cursor_steps() {
+        let chars = chars("ae\u{301}\u{302}b");
+        assert_eq!(next_grapheme_boundary(&chars, 0), 1);
+        assert_eq!(next_grapheme_boundary(&chars, 1), 4);
+        assert_eq!(next_grapheme_boundary(&chars, 4), 5);
+        assert_eq!(prev_grapheme_boundary(&chars, 5), 4);
+        assert_eq!(prev_grapheme_boundary(&chars, 4), 1);
+        assert_eq!(prev_grapheme_boundary(&chars, 1), 0);
+        assert_eq!(grapheme_floor(&chars, 3), 1);
+        assert_eq!(grapheme_floor(&chars, 4), 4);
+        assert_eq!(grapheme_floor(&chars, 9), 5);
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(truncate_to_width("日本語", 5), "日本");
+        assert_eq!(truncate_to_width("日本語", 6), "日本語");
+        assert_eq!(truncate_to_width("ae\u{301}b", 2), "ae\u{301}");
+    }
+
+    #[test]
+    fn utf8_lengths() {
+        assert_eq!(utf8_sequence_len(b'a'), Some(1));
+        assert_eq!(utf8_sequence_len("é".as_bytes()[0]), Some(2));
+        assert_eq!(utf8_sequence_len("日".as_bytes()[0]), Some(3));
+        assert_eq!(utf8_sequence_len("😀".as_bytes()[0]), Some(4));
+        assert_eq!(utf8_sequence_len(0x80), None);
+    }
+}
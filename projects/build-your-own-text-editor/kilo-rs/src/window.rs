@@ -18,6 +18,10 @@ struct Winsize {
 }
 
 const STDOUT_FILENO: RawFd = 1;
+
+#[cfg(target_os = "linux")]
+const TIOCGWINSZ: c_ulong = 0x5413;
+#[cfg(target_os = "macos")]
 const TIOCGWINSZ: c_ulong = 0x40087468;
 
 extern "C" {
@@ -25,16 +29,30 @@ extern "C" {
 }
 
 pub fn get_window_size() -> Result<(usize, usize), std::io::Error> {
+    match get_window_size_fd(STDOUT_FILENO) {
+        Ok(size) => Ok(size),
+        Err(_) => {
+            let move_curosor_bottom_right_cmd = b"\x1b[999C\x1b[999B";
+            let mut commands = BufferedCommands::new(move_curosor_bottom_right_cmd.to_vec());
+            get_cursor_position(&mut commands)
+        }
+    }
+}
+
+/// Query the size of the terminal referred to by `fd` as (rows, columns)
+pub fn get_window_size_fd(fd: RawFd) -> Result<(usize, usize), std::io::Error> {
     unsafe {
         let mut winsize = std::mem::MaybeUninit::<Winsize>::uninit();
 
-        if ioctl(STDOUT_FILENO, TIOCGWINSZ, winsize.as_mut_ptr()) == -1 {
-            let move_curosor_bottom_right_cmd = b"\x1b[999C\x1b[999B";
-            let mut commands = BufferedCommands::new(move_curosor_bottom_right_cmd.to_vec());
-            return get_cursor_position(&mut commands);
+        if ioctl(fd, TIOCGWINSZ, winsize.as_mut_ptr()) == -1 {
+            return Err(std::io::Error::last_os_error());
         }
 
         let winsize = winsize.assume_init();
+        if winsize.ws_col == 0 {
+            return Err(std::io::Error::other("terminal reported zero columns"));
+        }
+
         Ok((winsize.ws_row as usize, winsize.ws_col as usize))
     }
 }
@@ -62,10 +80,10 @@ fn get_cursor_position(commands: &mut BufferedCommands) -> Result<(usize, usize)
             }
             '0'..='9' => match st {
                 1 => {
-                    rows = rows * 10 + (buffer[0] - '0' as u8) as usize;
+                    rows = rows * 10 + (buffer[0] - b'0') as usize;
                 }
                 2 => {
-                    cols = cols * 10 + (buffer[0] - '0' as u8) as usize;
+                    cols = cols * 10 + (buffer[0] - b'0') as usize;
                 }
                 _ => {}
             },
@@ -78,3 +96,31 @@ fn get_cursor_position(commands: &mut BufferedCommands) -> Result<(usize, usize)
 
     Ok((rows, cols))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pty::Pty;
+
+    #[cfg(target_os = "linux")]
+    const TIOCSWINSZ: c_ulong = 0x5414;
+    #[cfg(target_os = "macos")]
+    const TIOCSWINSZ: c_ulong = 0x80087467;
+
+    #[test]
+    fn window_size_of_pty() {
+        let pty = Pty::open().expect("failed to open pty");
+        let winsize = Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        assert_ne!(
+            unsafe { ioctl(pty.master_fd(), TIOCSWINSZ, &winsize as *const Winsize) },
+            -1
+        );
+
+        assert_eq!(get_window_size_fd(pty.slave_fd()).unwrap(), (24, 80));
+    }
+}
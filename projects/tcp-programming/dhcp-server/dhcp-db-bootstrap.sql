@@ -4,5 +4,34 @@ CREATE TABLE "lease_entries" (
 	"id"	INTEGER PRIMARY KEY AUTOINCREMENT,
 	"mac_addr"	TEXT NOT NULL UNIQUE,
 	"ip_addr"	TEXT NOT NULL,
+	"leased_at"	INTEGER NOT NULL DEFAULT 0,
+	"expires_at"	INTEGER NOT NULL DEFAULT 0,
 	"deleted"	unsigned INTEGER NOT NULL DEFAULT 0
 );
+
+-- Fixed addresses for clients, found by client identifier (option 61, as lowercase hex such as
+-- 01aabbccddeeff) or else by MAC address. These addresses are left out of the dynamic pool when
+-- the server starts. The other columns override the server's settings for the client when set.
+CREATE TABLE "reservations" (
+	"id"	INTEGER PRIMARY KEY AUTOINCREMENT,
+	"mac_addr"	TEXT UNIQUE,
+	"client_id"	TEXT UNIQUE,
+	"ip_addr"	TEXT NOT NULL UNIQUE,
+	"hostname"	TEXT,
+	"default_gateway"	TEXT,
+	"dns_server"	TEXT,
+	"lease_time"	INTEGER,
+	CHECK ("mac_addr" IS NOT NULL OR "client_id" IS NOT NULL)
+);
+
+-- Addresses that a client declined because another host was using them, kept out of the pool
+-- until the time given, in seconds since the Unix epoch
+CREATE TABLE "quarantined_addresses" (
+	"ip_addr"	TEXT NOT NULL PRIMARY KEY,
+	"quarantined_until"	INTEGER NOT NULL
+);
+
+-- The server brings a database made by an older version up to date when it starts. It adds the
+-- leased_at and expires_at columns to lease_entries, whose leases then run out at once and whose
+-- clients are offered the same addresses again, and creates the reservations and
+-- quarantined_addresses tables.
@@ -34,15 +34,268 @@ fn get_addresses_from_row(mut ip_addrs: Rows) -> Result<Vec<Ipv4Addr>, failure::
     Ok(leased_addrs)
 }
 
-pub fn select_entry(con: &Connection, mac_addr: MacAddr) -> Result<Option<Ipv4Addr>, failure::Error> {
-    let mut stmt = con.prepare("SELECT ip_addr FROM lease_entries WHERE mac_addr = ?1")?;
+pub struct LeaseEntry {
+    pub ip_addr: Ipv4Addr,
+    /// Whether the address was released or reclaimed, and so is back in the address pool
+    pub deleted: bool,
+    /// When the lease runs out, in seconds since the Unix epoch
+    pub expires_at: i64,
+}
+
+pub fn select_entry(
+    con: &Connection,
+    mac_addr: MacAddr,
+) -> Result<Option<LeaseEntry>, failure::Error> {
+    let mut stmt =
+        con.prepare("SELECT ip_addr, deleted, expires_at FROM lease_entries WHERE mac_addr = ?1")?;
     let mut row = stmt.query(params![mac_addr.to_string()])?;
     if let Some(entry) = row.next()? {
-        let ip = entry.get(0)?;
-        let ip_string: String = ip;
-        Ok(Some(ip_string.parse()?))
+        let ip_string: String = entry.get(0)?;
+        let deleted: i64 = entry.get(1)?;
+        Ok(Some(LeaseEntry {
+            ip_addr: ip_string.parse()?,
+            deleted: deleted != 0,
+            expires_at: entry.get(2)?,
+        }))
     } else {
         info!("Specified MAC addr was not found.");
         Ok(None)
     }
-}
\ No newline at end of file
+}
+
+/// Lease `ip_addr` to `mac_addr` from `leased_at` until `expires_at`, replacing any entry the
+/// client had before
+pub fn upsert_entry(
+    con: &Connection,
+    mac_addr: MacAddr,
+    ip_addr: Ipv4Addr,
+    leased_at: i64,
+    expires_at: i64,
+) -> Result<(), failure::Error> {
+    con.execute(
+        "INSERT OR REPLACE INTO lease_entries (mac_addr, ip_addr, leased_at, expires_at, deleted)
+         VALUES (?1, ?2, ?3, ?4, 0)",
+        params![
+            mac_addr.to_string(),
+            ip_addr.to_string(),
+            leased_at,
+            expires_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// The client other than `mac_addr` that holds a lease on `ip_addr` at `now`, if there is one
+pub fn select_other_holder(
+    con: &Connection,
+    ip_addr: Ipv4Addr,
+    mac_addr: MacAddr,
+    now: i64,
+) -> Result<Option<String>, failure::Error> {
+    let mut stmt = con.prepare(
+        "SELECT mac_addr FROM lease_entries
+         WHERE ip_addr = ?1 AND mac_addr != ?2 AND deleted = 0 AND expires_at > ?3",
+    )?;
+    let mut row = stmt.query(params![ip_addr.to_string(), mac_addr.to_string(), now])?;
+    match row.next()? {
+        Some(entry) => Ok(Some(entry.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+/// Mark the client's lease as given up, keeping the entry so that the client can be offered the
+/// same address again
+pub fn delete_entry(con: &Connection, mac_addr: MacAddr) -> Result<(), failure::Error> {
+    con.execute(
+        "UPDATE lease_entries SET deleted = 1 WHERE mac_addr = ?1",
+        params![mac_addr.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Mark every lease that ran out by `now` as given up, returning their addresses
+pub fn expire_entries(con: &Connection, now: i64) -> Result<Vec<Ipv4Addr>, failure::Error> {
+    let mut statement =
+        con.prepare("SELECT ip_addr FROM lease_entries WHERE deleted = 0 AND expires_at <= ?1")?;
+    let expired = get_addresses_from_row(statement.query(params![now])?)?;
+    con.execute(
+        "UPDATE lease_entries SET deleted = 1 WHERE deleted = 0 AND expires_at <= ?1",
+        params![now],
+    )?;
+    Ok(expired)
+}
+
+/// A fixed address for a client, with settings that replace the server's own
+pub struct Reservation {
+    pub ip_addr: Ipv4Addr,
+    pub hostname: Option<String>,
+    pub default_gateway: Option<Ipv4Addr>,
+    pub dns_server: Option<Ipv4Addr>,
+    /// The lease time in seconds
+    pub lease_time: Option<u32>,
+}
+
+/// The reservation for a client, found by its client identifier (option 61) if it sent one, or
+/// else by its MAC address
+pub fn select_reservation(
+    con: &Connection,
+    mac_addr: MacAddr,
+    client_id: Option<&[u8]>,
+) -> Result<Option<Reservation>, failure::Error> {
+    const COLUMNS: &str = "ip_addr, hostname, default_gateway, dns_server, lease_time";
+    if let Some(client_id) = client_id {
+        let mut stmt = con.prepare(&format!(
+            "SELECT {} FROM reservations WHERE client_id = ?1",
+            COLUMNS
+        ))?;
+        let reservation = get_reservation_from_row(stmt.query(params![to_hex(client_id)])?)?;
+        if reservation.is_some() {
+            return Ok(reservation);
+        }
+    }
+
+    let mut stmt = con.prepare(&format!(
+        "SELECT {} FROM reservations WHERE mac_addr = ?1",
+        COLUMNS
+    ))?;
+    let reservation = get_reservation_from_row(stmt.query(params![mac_addr.to_string()])?)?;
+    Ok(reservation)
+}
+
+fn get_reservation_from_row(mut row: Rows) -> Result<Option<Reservation>, failure::Error> {
+    let entry = match row.next()? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let ip_string: String = entry.get(0)?;
+    let default_gateway: Option<String> = entry.get(2)?;
+    let dns_server: Option<String> = entry.get(3)?;
+    let lease_time: Option<i64> = entry.get(4)?;
+    Ok(Some(Reservation {
+        ip_addr: ip_string.parse()?,
+        hostname: entry.get(1)?,
+        default_gateway: default_gateway.map(|addr| addr.parse()).transpose()?,
+        dns_server: dns_server.map(|addr| addr.parse()).transpose()?,
+        lease_time: lease_time.map(|secs| secs as u32),
+    }))
+}
+
+pub fn select_reserved_addresses(con: &Connection) -> Result<Vec<Ipv4Addr>, failure::Error> {
+    let mut statement = con.prepare("SELECT ip_addr FROM reservations")?;
+    let ip_addrs = statement.query(NO_PARAMS)?;
+    get_addresses_from_row(ip_addrs)
+}
+
+/// Create the tables that a database made by an older version of the server is missing
+pub fn create_missing_tables(con: &Connection) -> Result<(), failure::Error> {
+    con.execute_batch(
+        "CREATE TABLE IF NOT EXISTS reservations (
+             id INTEGER PRIMARY KEY AUTOINCREMENT,
+             mac_addr TEXT UNIQUE,
+             client_id TEXT UNIQUE,
+             ip_addr TEXT NOT NULL UNIQUE,
+             hostname TEXT,
+             default_gateway TEXT,
+             dns_server TEXT,
+             lease_time INTEGER,
+             CHECK (mac_addr IS NOT NULL OR client_id IS NOT NULL)
+         );
+         CREATE TABLE IF NOT EXISTS quarantined_addresses (
+             ip_addr TEXT NOT NULL PRIMARY KEY,
+             quarantined_until INTEGER NOT NULL
+         );",
+    )?;
+    Ok(())
+}
+
+/// Add the lease times to the lease entries of a database made before leases expired. Its leases
+/// then run out at once, and their clients are offered the same addresses again.
+pub fn add_missing_columns(con: &Connection) -> Result<(), failure::Error> {
+    let mut columns: Vec<String> = Vec::new();
+    {
+        let mut statement = con.prepare("PRAGMA table_info(lease_entries)")?;
+        let mut rows = statement.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            columns.push(row.get(1)?);
+        }
+    }
+    for column in &["leased_at", "expires_at"] {
+        if !columns.iter().any(|name| name == column) {
+            con.execute_batch(&format!(
+                "ALTER TABLE lease_entries ADD COLUMN {} INTEGER NOT NULL DEFAULT 0;",
+                column
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Keep `ip_addr` out of the pool until `until`
+pub fn quarantine_address(
+    con: &Connection,
+    ip_addr: Ipv4Addr,
+    until: i64,
+) -> Result<(), failure::Error> {
+    con.execute(
+        "INSERT OR REPLACE INTO quarantined_addresses (ip_addr, quarantined_until)
+         VALUES (?1, ?2)",
+        params![ip_addr.to_string(), until],
+    )?;
+    Ok(())
+}
+
+/// The addresses still in quarantine at `now`
+pub fn select_quarantined_addresses(
+    con: &Connection,
+    now: i64,
+) -> Result<Vec<Ipv4Addr>, failure::Error> {
+    let mut statement =
+        con.prepare("SELECT ip_addr FROM quarantined_addresses WHERE quarantined_until > ?1")?;
+    let ip_addrs = statement.query(params![now])?;
+    get_addresses_from_row(ip_addrs)
+}
+
+/// Whether `ip_addr` is still in quarantine at `now`
+pub fn is_quarantined(
+    con: &Connection,
+    ip_addr: Ipv4Addr,
+    now: i64,
+) -> Result<bool, failure::Error> {
+    let mut stmt = con.prepare(
+        "SELECT 1 FROM quarantined_addresses WHERE ip_addr = ?1 AND quarantined_until > ?2",
+    )?;
+    let mut row = stmt.query(params![ip_addr.to_string(), now])?;
+    Ok(row.next()?.is_some())
+}
+
+/// End every quarantine that is over by `now`, returning their addresses
+pub fn end_quarantines(con: &Connection, now: i64) -> Result<Vec<Ipv4Addr>, failure::Error> {
+    let mut statement =
+        con.prepare("SELECT ip_addr FROM quarantined_addresses WHERE quarantined_until <= ?1")?;
+    let ended = get_addresses_from_row(statement.query(params![now])?)?;
+    con.execute(
+        "DELETE FROM quarantined_addresses WHERE quarantined_until <= ?1",
+        params![now],
+    )?;
+    Ok(ended)
+}
+
+/// Client identifiers are stored as lowercase hex, such as `01aabbccddeeff` for a type 1
+/// (ethernet) identifier
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ids_as_hex() {
+        assert_eq!(to_hex(&[]), "");
+        assert_eq!(
+            to_hex(&[0x01, 0xaa, 0xbb, 0x0c, 0x00, 0xee, 0xff]),
+            "01aabb0c00eeff"
+        );
+    }
+}
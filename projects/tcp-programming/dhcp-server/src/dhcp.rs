@@ -31,6 +31,9 @@ pub const OPTIONS: usize = 236;
 
 const OPTION_END: u8 = 255;
 
+// how long a declined address is kept out of the pool when `.env` doesn't set QUARANTINE_TIME
+const DEFAULT_QUARANTINE_SECS: u32 = 600;
+
 const PACKET_FORMAT: [usize; 15] = [
     OP, HTYPE, HLEN, HOPS, XID, SECS, FLAGS, CIADDR, YIADDR, SIADDR, GIADDR, CHADDR, SNAME, FILE,
     OPTIONS,
@@ -156,6 +159,10 @@ impl DhcpPacket {
         None
     }
 
+    pub fn get_buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
     fn get_options(&self) -> &[u8] {
         &self.buffer[OPTIONS..]
     }
@@ -169,45 +176,42 @@ fn find_field_range(field: usize) -> Range<usize> {
     field..PACKET_FORMAT[p + 1]
 }
 
-pub struct DhcpServer {
+/// A network that addresses are leased on: the server's own, or one behind a relay agent
+pub struct Scope {
     address_pool: RwLock<Vec<Ipv4Addr>>,
-    pub db_connection: Mutex<Connection>,
     pub network_addr: Ipv4Network,
-    pub server_address: Ipv4Addr,
-    pub default_gateway: Ipv4Addr,
     pub subnet_mask: Ipv4Addr,
+    pub default_gateway: Ipv4Addr,
     pub dns_server: Ipv4Addr,
-    pub lease_time: Vec<u8>,
 }
 
-impl DhcpServer {
-    pub fn new() -> Result<DhcpServer, failure::Error> {
-        let env = util::load_env();
-        let static_addresses = util::obtain_static_addresses(&env)?;
-
-        let subnet_mask = static_addresses[util::SUBNET_MASK_KEY];
+impl Scope {
+    /// A scope from its addresses in `.env`, with a pool of every address on the network that
+    /// isn't leased, in `excluded`, or one of the scope's own
+    fn new(
+        con: &Connection,
+        addresses: &HashMap<String, Ipv4Addr>,
+        excluded: &[Ipv4Addr],
+    ) -> Result<Scope, failure::Error> {
+        let subnet_mask = addresses[util::SUBNET_MASK_KEY];
         let network_addr_with_prefix: Ipv4Network = Ipv4Network::new(
-            static_addresses[util::NETWORK_ADDR_KEY],
+            addresses[util::NETWORK_ADDR_KEY],
             ipnetwork::ipv4_mask_to_prefix(subnet_mask)?,
         )?;
-
-        let con = Connection::open("dhcp.db")?;
-        let addr_pool = Self::init_address_pool(&con, &static_addresses, network_addr_with_prefix)?;
-
-        info!("Ther are {} addresses in the address pool", addr_pool.len());
-        let raw_lease_time: u32 = util::get_and_parse_addr(util::LEASE_TIME_KEY, &env)?;
-        let lease_time = util::make_big_endian_vec_from_u32(raw_lease_time)?;
-
-        let dummyAddr = Ipv4Addr::new(127, 0, 0, 1);
-        Ok(DhcpServer {
+        let addr_pool =
+            Self::init_address_pool(con, addresses, excluded, network_addr_with_prefix)?;
+        info!(
+            "There are {} addresses in the address pool of {}",
+            addr_pool.len(),
+            network_addr_with_prefix
+        );
+
+        Ok(Scope {
             address_pool: RwLock::new(addr_pool),
-            db_connection: Mutex::new(con),
             network_addr: network_addr_with_prefix,
-            server_address: static_addresses[util::SERVER_IDENTIFIER_KEY],
-            default_gateway: static_addresses[util::DEFAULT_GATEWAY_KEY],
-            subnet_mask: static_addresses[util::SUBNET_MASK_KEY],
-            dns_server: static_addresses[util::DNS_SERVER_KEY],
-            lease_time,
+            subnet_mask,
+            default_gateway: addresses[util::DEFAULT_GATEWAY_KEY],
+            dns_server: addresses[util::DNS_SERVER_KEY],
         })
     }
 
@@ -223,30 +227,30 @@ impl DhcpServer {
             .map(|i| lock.remove(i))
     }
 
-    pub fn release_address(&self, release_ip: Ipv4Addr) {
+    fn release_address(&self, release_ip: Ipv4Addr) {
         let mut lock = self.address_pool.write().unwrap();
         lock.insert(0, release_ip);
     }
 
     fn init_address_pool(
         con: &Connection,
-        static_addresses: &HashMap<String, Ipv4Addr>,
+        addresses: &HashMap<String, Ipv4Addr>,
+        excluded: &[Ipv4Addr],
         network_addr_with_prefix: Ipv4Network,
     ) -> Result<Vec<Ipv4Addr>, failure::Error> {
-        let network_addr = static_addresses.get(util::NETWORK_ADDR_KEY).unwrap();
-        let default_gateway = static_addresses.get(util::DEFAULT_GATEWAY_KEY).unwrap();
-        let dhcp_server_addr = static_addresses.get(util::SERVER_IDENTIFIER_KEY).unwrap();
-        let dns_server_addr = static_addresses.get(util::DNS_SERVER_KEY).unwrap();
+        let network_addr = addresses.get(util::NETWORK_ADDR_KEY).unwrap();
+        let default_gateway = addresses.get(util::DEFAULT_GATEWAY_KEY).unwrap();
+        let dns_server_addr = addresses.get(util::DNS_SERVER_KEY).unwrap();
         let broadcast = network_addr_with_prefix.broadcast();
 
         let mut used_ip_addrs = database::select_addresses(con, Some(0))?;
         used_ip_addrs.extend(vec![
             *network_addr,
             *default_gateway,
-            *dhcp_server_addr,
             *dns_server_addr,
             broadcast,
         ]);
+        used_ip_addrs.extend_from_slice(excluded);
         let mut addr_pool: Vec<Ipv4Addr> = network_addr_with_prefix
             .iter()
             .filter(|addr| !used_ip_addrs.contains(addr))
@@ -256,3 +260,323 @@ impl DhcpServer {
         Ok(addr_pool)
     }
 }
+
+pub struct DhcpServer {
+    // the server's own network first, then those behind relay agents
+    scopes: Vec<Scope>,
+    pub db_connection: Mutex<Connection>,
+    pub server_address: Ipv4Addr,
+    // reserved addresses are never handed out from the pool
+    reserved_addresses: Vec<Ipv4Addr>,
+    pub lease_secs: u32,
+    pub quarantine_secs: u32,
+}
+
+impl DhcpServer {
+    pub fn new() -> Result<DhcpServer, failure::Error> {
+        Self::from_env(&util::load_env(), Connection::open("dhcp.db")?)
+    }
+
+    /// A server configured by the settings in `env`, as read from `.env`, keeping its leases in
+    /// the database `con`
+    pub fn from_env(
+        env: &HashMap<String, String>,
+        con: Connection,
+    ) -> Result<DhcpServer, failure::Error> {
+        let static_addresses = util::obtain_static_addresses(env)?;
+        let server_address = static_addresses[util::SERVER_IDENTIFIER_KEY];
+
+        database::add_missing_columns(&con)?;
+        database::create_missing_tables(&con)?;
+        let reserved_addresses = database::select_reserved_addresses(&con)?;
+        let mut excluded = reserved_addresses.clone();
+        excluded.push(server_address);
+        // quarantines that ran out while the server was down are over, and the rest carry on
+        let now = util::unix_time_now();
+        database::end_quarantines(&con, now)?;
+        excluded.extend(database::select_quarantined_addresses(&con, now)?);
+
+        let mut scopes = vec![Scope::new(&con, &static_addresses, &excluded)?];
+        // scopes behind relay agents are numbered from 1, each with the same keys as the
+        // server's own under a prefix, as in SCOPE1_NETWORK_ADDR
+        for n in 1.. {
+            match util::obtain_scope_addresses(env, &format!("SCOPE{}_", n))? {
+                Some(addresses) => scopes.push(Scope::new(&con, &addresses, &excluded)?),
+                None => break,
+            }
+        }
+
+        let raw_lease_time: u32 = util::get_and_parse_addr(util::LEASE_TIME_KEY, env)?;
+        let quarantine_secs: u32 = match env.get(util::QUARANTINE_TIME_KEY) {
+            Some(secs) => secs.parse()?,
+            None => DEFAULT_QUARANTINE_SECS,
+        };
+
+        Ok(DhcpServer {
+            scopes,
+            db_connection: Mutex::new(con),
+            server_address,
+            reserved_addresses,
+            lease_secs: raw_lease_time,
+            quarantine_secs,
+        })
+    }
+
+    /// The scope of the client that sent `packet`. A relayed message is from the relay agent's
+    /// network (`giaddr`), and a client renewing its lease is on the network of its address.
+    /// Otherwise the client is on the server's own network, since the socket listens on every
+    /// interface and a broadcast doesn't say which one it came in on.
+    pub fn select_scope(&self, packet: &DhcpPacket) -> Option<&Scope> {
+        let giaddr = packet.get_giaddr();
+        if !giaddr.is_unspecified() {
+            return self.scope_of(giaddr);
+        }
+        self.scope_of(packet.get_ciaddr())
+            .or_else(|| self.scopes.first())
+    }
+
+    fn scope_of(&self, ip_addr: Ipv4Addr) -> Option<&Scope> {
+        self.scopes
+            .iter()
+            .find(|scope| scope.network_addr.contains(ip_addr))
+    }
+
+    pub fn is_reserved(&self, ip_addr: Ipv4Addr) -> bool {
+        self.reserved_addresses.contains(&ip_addr)
+    }
+
+    pub fn release_address(&self, release_ip: Ipv4Addr) {
+        if self.is_reserved(release_ip) {
+            return;
+        }
+        if let Some(scope) = self.scope_of(release_ip) {
+            scope.release_address(release_ip);
+        }
+    }
+
+    /// Return the addresses of leases that have run out to the pool, giving how many there were
+    pub fn reclaim_expired_leases(&self) -> Result<usize, failure::Error> {
+        let con = self.db_connection.lock().unwrap();
+        let expired = database::expire_entries(&con, util::unix_time_now())?;
+        for &ip_addr in &expired {
+            info!("Lease of {} expired", ip_addr);
+            self.release_address(ip_addr);
+        }
+        Ok(expired.len())
+    }
+
+    /// Keep an address that a client found in use out of the pool for the quarantine time. It's
+    /// kept in the database, so that the quarantine outlasts a restart.
+    pub fn quarantine_address(
+        &self,
+        con: &Connection,
+        declined_ip: Ipv4Addr,
+    ) -> Result<(), failure::Error> {
+        let until = util::unix_time_now() + i64::from(self.quarantine_secs);
+        database::quarantine_address(con, declined_ip, until)
+    }
+
+    /// Return addresses whose quarantine is over to the pool, giving how many there were
+    pub fn end_quarantines(&self) -> Result<usize, failure::Error> {
+        let con = self.db_connection.lock().unwrap();
+        let ended = database::end_quarantines(&con, util::unix_time_now())?;
+        for &ip_addr in &ended {
+            info!("Quarantine of {} is over", ip_addr);
+            self.release_address(ip_addr);
+        }
+        Ok(ended.len())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A server on 10.0.1.0/24 that also serves 10.0.2.0/24 through a relay agent, with an empty
+    /// database in memory
+    pub fn test_server() -> DhcpServer {
+        test_server_with(test_database())
+    }
+
+    pub fn test_server_with(con: Connection) -> DhcpServer {
+        DhcpServer::from_env(&test_env(), con).unwrap()
+    }
+
+    fn test_env() -> HashMap<String, String> {
+        [
+            ("NETWORK_ADDR", "10.0.1.0"),
+            ("SUBNET_MASK", "255.255.255.0"),
+            ("SERVER_IDENTIFIER", "10.0.1.4"),
+            ("DEFAULT_GATEWAY", "10.0.1.1"),
+            ("DNS_SERVER", "10.0.1.1"),
+            ("LEASE_TIME", "300"),
+            ("QUARANTINE_TIME", "900"),
+            ("SCOPE1_NETWORK_ADDR", "10.0.2.0"),
+            ("SCOPE1_SUBNET_MASK", "255.255.255.0"),
+            ("SCOPE1_DEFAULT_GATEWAY", "10.0.2.1"),
+            ("SCOPE1_DNS_SERVER", "10.0.2.2"),
+        ]
+        .iter()
+        .map(|&(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+    }
+
+    pub fn test_database() -> Connection {
+        let con = Connection::open_in_memory().unwrap();
+        con.execute_batch(include_str!("../dhcp-db-bootstrap.sql"))
+            .unwrap();
+        con
+    }
+
+    fn packet_from(ciaddr: Ipv4Addr, giaddr: Ipv4Addr) -> DhcpPacket {
+        let mut packet = DhcpPacket::new(vec![0u8; OPTIONS + 4]).unwrap();
+        packet.set_ciaddr(ciaddr);
+        packet.set_giaddr(giaddr);
+        packet
+    }
+
+    #[test]
+    fn select_scope() {
+        let server = test_server();
+        let scope_network = |ciaddr, giaddr| {
+            server
+                .select_scope(&packet_from(ciaddr, giaddr))
+                .map(|scope| scope.network_addr.ip())
+        };
+        let none = Ipv4Addr::UNSPECIFIED;
+        let local = Some(Ipv4Addr::new(10, 0, 1, 0));
+        let relayed = Some(Ipv4Addr::new(10, 0, 2, 0));
+
+        assert_eq!(scope_network(none, none), local);
+        assert_eq!(scope_network(Ipv4Addr::new(10, 0, 2, 9), none), relayed);
+        // an address on no network served falls back to the server's own
+        assert_eq!(scope_network(Ipv4Addr::new(10, 0, 9, 9), none), local);
+
+        // the relay agent's network wins over the client's address
+        assert_eq!(
+            scope_network(Ipv4Addr::new(10, 0, 1, 9), Ipv4Addr::new(10, 0, 2, 1)),
+            relayed
+        );
+        assert_eq!(scope_network(none, Ipv4Addr::new(10, 0, 9, 1)), None);
+    }
+
+    #[test]
+    fn scope_pools_leave_out_fixed_addresses() {
+        let server = test_server();
+        let scope = &server.scopes[1];
+        assert_eq!(scope.default_gateway, Ipv4Addr::new(10, 0, 2, 1));
+        assert_eq!(scope.dns_server, Ipv4Addr::new(10, 0, 2, 2));
+        for &ip_addr in &[
+            Ipv4Addr::new(10, 0, 2, 0),
+            Ipv4Addr::new(10, 0, 2, 1),
+            Ipv4Addr::new(10, 0, 2, 2),
+            Ipv4Addr::new(10, 0, 2, 255),
+        ] {
+            assert_eq!(scope.pick_specified_ip(ip_addr), None);
+        }
+        assert_eq!(scope.pick_available_ip(), Some(Ipv4Addr::new(10, 0, 2, 3)));
+        assert_eq!(
+            server.scopes[0].pick_specified_ip(server.server_address),
+            None
+        );
+    }
+
+    #[test]
+    fn quarantines_outlast_a_restart() {
+        let con = test_database();
+        let now = util::unix_time_now();
+        let quarantined = Ipv4Addr::new(10, 0, 1, 5);
+        let ended = Ipv4Addr::new(10, 0, 1, 6);
+        database::quarantine_address(&con, quarantined, now + 600).unwrap();
+        database::quarantine_address(&con, ended, now).unwrap();
+
+        let server = test_server_with(con);
+        let scope = &server.scopes[0];
+        assert_eq!(scope.pick_specified_ip(quarantined), None);
+        assert_eq!(scope.pick_specified_ip(ended), Some(ended));
+        // the quarantine that ran out was ended as the server started, not left to end again
+        assert_eq!(server.end_quarantines().unwrap(), 0);
+    }
+
+    #[test]
+    fn quarantine_ends() {
+        let mut server = test_server();
+        server.quarantine_secs = 0;
+        let declined = server.scopes[0].pick_available_ip().unwrap();
+        {
+            let con = server.db_connection.lock().unwrap();
+            server.quarantine_address(&con, declined).unwrap();
+            assert!(database::is_quarantined(&con, declined, util::unix_time_now() - 1).unwrap());
+        }
+        assert_eq!(server.end_quarantines().unwrap(), 1);
+        assert_eq!(server.scopes[0].pick_specified_ip(declined), Some(declined));
+    }
+
+    #[test]
+    fn reservations_table_is_created_in_an_old_database() {
+        let con = Connection::open_in_memory().unwrap();
+        con.execute_batch(
+            "CREATE TABLE lease_entries (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 mac_addr TEXT NOT NULL UNIQUE,
+                 ip_addr TEXT NOT NULL,
+                 leased_at INTEGER NOT NULL DEFAULT 0,
+                 expires_at INTEGER NOT NULL DEFAULT 0,
+                 deleted unsigned INTEGER NOT NULL DEFAULT 0
+             );",
+        )
+        .unwrap();
+        let server = test_server_with(con);
+        let con = server.db_connection.lock().unwrap();
+        assert!(database::select_reserved_addresses(&con)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn lease_times_are_added_to_an_old_database() {
+        let con = Connection::open_in_memory().unwrap();
+        con.execute_batch(
+            "CREATE TABLE lease_entries (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 mac_addr TEXT NOT NULL UNIQUE,
+                 ip_addr TEXT NOT NULL,
+                 deleted unsigned INTEGER NOT NULL DEFAULT 0
+             );
+             INSERT INTO lease_entries (mac_addr, ip_addr)
+             VALUES ('02:00:00:00:00:01', '10.0.1.20');",
+        )
+        .unwrap();
+        let leased = Ipv4Addr::new(10, 0, 1, 20);
+        let server = test_server_with(con);
+        assert_eq!(server.scopes[0].pick_specified_ip(leased), None);
+
+        // the old lease has run out, and its address goes back to the pool
+        assert_eq!(server.reclaim_expired_leases().unwrap(), 1);
+        assert_eq!(server.scopes[0].pick_specified_ip(leased), Some(leased));
+        let con = server.db_connection.lock().unwrap();
+        let entry = database::select_entry(&con, MacAddr::new(0x02, 0, 0, 0, 0, 0x01))
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.ip_addr, leased);
+        assert!(entry.deleted);
+    }
+
+    #[test]
+    fn quarantine_time_is_optional() {
+        let mut env = test_env();
+        assert_eq!(
+            DhcpServer::from_env(&env, test_database())
+                .unwrap()
+                .quarantine_secs,
+            900
+        );
+        env.remove(util::QUARANTINE_TIME_KEY);
+        assert_eq!(
+            DhcpServer::from_env(&env, test_database())
+                .unwrap()
+                .quarantine_secs,
+            DEFAULT_QUARANTINE_SECS
+        );
+    }
+}
@@ -1,41 +1,61 @@
 use std::{
     env,
-    net::{Ipv4Addr, UdpSocket},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
     sync::Arc,
     thread,
+    time::Duration,
 };
 
 use byteorder::{BigEndian, ByteOrder};
-use dhcp::{DhcpPacket, DhcpServer};
+use database::Reservation;
+use dhcp::{DhcpPacket, DhcpServer, Scope};
 use log::{debug, error, info};
 use pnet::util::MacAddr;
+use rusqlite::Connection;
 mod database;
 mod dhcp;
 mod util;
 
-const DHCP_SIZE: usize = 400;
+// every client accepts messages of this size (RFC 2131 2), which leaves room for a relay agent's
+// information
+const DHCP_SIZE: usize = 576;
 
 const BOOTREQUEST: u8 = 1;
 const BOOTREPLY: u8 = 2;
 
 const HTYPE_ETHER: u8 = 1;
+// the high bit of flags, for replies that must be broadcast to the client
+const BROADCAST_FLAG: u8 = 0x80;
 const MACADDR_SIZE: u8 = 6;
 
 const DHCPDISCOVER: u8 = 1;
 const DHCPOFFER: u8 = 2;
 const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
 const DHCPACK: u8 = 5;
 const DHCPNAK: u8 = 6;
 const DHCPRELEASE: u8 = 7;
+const DHCPINFORM: u8 = 8;
+
+// how long an offered address is kept for the client before it's returned to the pool
+const OFFER_HOLD_SECS: i64 = 60;
+const LEASE_REAPER_INTERVAL: Duration = Duration::from_secs(10);
+// longer host names are cut short to leave room in the options for the rest
+const MAX_HOST_NAME_LEN: usize = 63;
 
 enum Code {
     MessageType = 53,
     IPAddressLeaseTime = 51,
     ServerIdentifier = 54,
     RequestedIpAddress = 50,
+    RenewalTimeValue = 58,
+    RebindingTimeValue = 59,
     SubnetMask = 1,
     Router = 3,
-    DNS = 6,
+    Dns = 6,
+    HostName = 12,
+    ClientIdentifier = 61,
+    RelayAgentInformation = 82,
     End = 255,
 }
 
@@ -50,6 +70,17 @@ fn main() {
         DhcpServer::new().unwrap_or_else(|e| panic!("Failed to start dhcp server. {:?}", e)),
     );
 
+    let reaper = dhcp_server.clone();
+    thread::spawn(move || loop {
+        thread::sleep(LEASE_REAPER_INTERVAL);
+        if let Err(e) = reaper.reclaim_expired_leases() {
+            error!("Failed to reclaim expired leases: {}", e);
+        }
+        if let Err(e) = reaper.end_quarantines() {
+            error!("Failed to end quarantines: {}", e);
+        }
+    });
+
     loop {
         let mut recv_buf = [0u8; 1024];
         match server_socket.recv_from(&mut recv_buf) {
@@ -67,7 +98,7 @@ fn main() {
                         }
 
                         if let Err(e) =
-                            dhcp_handler(&dhcp_packet, &&transmission_socket, cloned_dhcp_server)
+                            dhcp_handler(&dhcp_packet, &transmission_socket, cloned_dhcp_server)
                         {
                             error!("{}", e);
                         }
@@ -92,12 +123,20 @@ fn dhcp_handler(
     let message_type = message[0];
     let tx_id = BigEndian::read_u32(packet.get_xid());
     let client_macaddr = packet.get_chaddr();
+    let scope = dhcp_server.select_scope(packet).ok_or_else(|| {
+        failure::format_err!(
+            "{:x}: no scope serves the relay agent {}",
+            tx_id,
+            packet.get_giaddr()
+        )
+    })?;
     match message_type {
-        DHCPDISCOVER => dhcp_discover_message_handler(tx_id, dhcp_server, packet, soc),
+        DHCPDISCOVER => dhcp_discover_message_handler(tx_id, &dhcp_server, scope, packet, soc),
         DHCPREQUEST => match packet.get_option(Code::ServerIdentifier as u8) {
             Some(server_id) => dhcp_request_message_handler_responded_to_offer(
                 tx_id,
-                dhcp_server,
+                &dhcp_server,
+                scope,
                 packet,
                 client_macaddr,
                 soc,
@@ -105,13 +144,16 @@ fn dhcp_handler(
             ),
             None => dhcp_request_message_handler_to_reallocate(
                 tx_id,
-                dhcp_server,
+                &dhcp_server,
+                scope,
                 packet,
                 client_macaddr,
                 soc,
             ),
         },
-        DHCPRELEASE => dhcp_release_message_handler(tx_id, dhcp_server, packet, client_macaddr),
+        DHCPDECLINE => dhcp_decline_message_handler(tx_id, &dhcp_server, packet, client_macaddr),
+        DHCPRELEASE => dhcp_release_message_handler(tx_id, &dhcp_server, packet, client_macaddr),
+        DHCPINFORM => dhcp_inform_message_handler(tx_id, &dhcp_server, scope, packet, soc),
         _ => {
             let msg = format!(
                 "{:x}: received unimplemented message, message_type: {}",
@@ -124,32 +166,97 @@ fn dhcp_handler(
 
 fn dhcp_discover_message_handler(
     tx_id: u32,
-    dhcp_server: Arc<DhcpServer>,
+    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
     received_packet: &DhcpPacket,
     soc: &UdpSocket,
 ) -> Result<(), failure::Error> {
     info!("{:x}: received DHCPDISCOVER", tx_id);
 
-    let ip_to_be_leased = select_lease_ip(&dhcp_server, &received_packet)?;
+    let reservation = find_reservation(dhcp_server, received_packet)?;
+    let ip_to_be_leased =
+        select_lease_ip(dhcp_server, scope, received_packet, reservation.as_ref())?;
+    hold_offered_ip(dhcp_server, received_packet.get_chaddr(), ip_to_be_leased)?;
+
+    let dhcp_packet = make_dhcp_packet(
+        received_packet,
+        dhcp_server,
+        scope,
+        DHCPOFFER,
+        ip_to_be_leased,
+        reservation.as_ref(),
+    )?;
+    util::send_dhcp_response(
+        soc,
+        dhcp_packet.get_buffer(),
+        reply_destination(received_packet, DHCPOFFER),
+    )?;
+    info!("{:x}: sent DHCPOFFER of {}", tx_id, ip_to_be_leased);
     Ok(())
 }
 
 fn select_lease_ip(
     dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
     received_packet: &DhcpPacket,
+    reservation: Option<&Reservation>,
 ) -> Result<Ipv4Addr, failure::Error> {
     let con = dhcp_server.db_connection.lock().unwrap();
-    if let Some(ip_from_used) = database::select_entry(&con, received_packet.get_chaddr())? {
-        if dhcp_server.network_addr.contains(ip_from_used) && util::is_ipaddr_available(ip_from_used).is_ok() {
-            return Ok(ip_from_used);
+    // a reserved address is the client's alone, so it isn't in the pool or checked with ping
+    if let Some(reservation) = reservation {
+        if !scope.network_addr.contains(reservation.ip_addr) {
+            return Err(failure::format_err!(
+                "Reserved address {} is not on this network.",
+                reservation.ip_addr
+            ));
+        }
+        // offering it again would only have the client decline it again
+        if database::is_quarantined(&con, reservation.ip_addr, util::unix_time_now())? {
+            return Err(failure::format_err!(
+                "Reserved address {} is quarantined.",
+                reservation.ip_addr
+            ));
+        }
+        // a reservation made while another client held the address waits for that lease to end
+        if let Some(holder) = database::select_other_holder(
+            &con,
+            reservation.ip_addr,
+            received_packet.get_chaddr(),
+            util::unix_time_now(),
+        )? {
+            return Err(failure::format_err!(
+                "Reserved address {} is leased to {}.",
+                reservation.ip_addr,
+                holder
+            ));
+        }
+        return Ok(reservation.ip_addr);
+    }
+
+    // an address reserved since it was leased to this client is another client's now, so this one
+    // is given a new address
+    let entry = database::select_entry(&con, received_packet.get_chaddr())?
+        .filter(|entry| !dhcp_server.is_reserved(entry.ip_addr));
+    if let Some(entry) = entry {
+        // an address the client gave up is back in the pool, unless another client has it now
+        let reclaimed = entry.deleted && scope.pick_specified_ip(entry.ip_addr).is_some();
+        if (!entry.deleted || reclaimed)
+            && scope.network_addr.contains(entry.ip_addr)
+            && util::is_ipaddr_available(entry.ip_addr).is_ok()
+        {
+            return Ok(entry.ip_addr);
+        }
+        if reclaimed {
+            dhcp_server.release_address(entry.ip_addr);
         }
     }
 
-    if let Some(ip_to_be_leased) = obtain_available_ip_from_requested_option(dhcp_server, &received_packet) {
+    if let Some(ip_to_be_leased) = obtain_available_ip_from_requested_option(scope, received_packet)
+    {
         return Ok(ip_to_be_leased);
     }
 
-    while let Some(ip_addr) = dhcp_server.pick_available_ip() {
+    while let Some(ip_addr) = scope.pick_available_ip() {
         if util::is_ipaddr_available(ip_addr).is_ok() {
             return Ok(ip_addr);
         }
@@ -158,12 +265,12 @@ fn select_lease_ip(
 }
 
 fn obtain_available_ip_from_requested_option(
-    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
     received_packet: &DhcpPacket,
 ) -> Option<Ipv4Addr> {
     let ip = received_packet.get_option(Code::RequestedIpAddress as u8)?;
     let requested_ip = util::u8_to_ipv4addr(&ip)?;
-    let ip_from_pool = dhcp_server.pick_specified_ip(requested_ip)?;
+    let ip_from_pool = scope.pick_specified_ip(requested_ip)?;
     if util::is_ipaddr_available(ip_from_pool).is_ok() {
         Some(requested_ip)
     } else {
@@ -171,54 +278,432 @@ fn obtain_available_ip_from_requested_option(
     }
 }
 
+/// Keep an offered address out of the pool while the client decides between offers. The reaper
+/// returns it if the client doesn't request it in time. A lease the client already holds for
+/// longer is left as it is.
+fn hold_offered_ip(
+    dhcp_server: &Arc<DhcpServer>,
+    client_macaddr: MacAddr,
+    offered_ip: Ipv4Addr,
+) -> Result<(), failure::Error> {
+    let now = util::unix_time_now();
+    let hold_until = now + OFFER_HOLD_SECS;
+    let con = dhcp_server.db_connection.lock().unwrap();
+    if let Some(entry) = database::select_entry(&con, client_macaddr)? {
+        if !entry.deleted && entry.ip_addr == offered_ip && entry.expires_at >= hold_until {
+            return Ok(());
+        }
+    }
+    record_lease(
+        dhcp_server,
+        &con,
+        client_macaddr,
+        offered_ip,
+        now,
+        hold_until,
+    )
+}
+
+/// Record that `ip_addr` is the client's from `leased_at` until `expires_at`. A client only has
+/// the one entry, so an address it held until now that it hadn't given up goes back to the pool
+/// rather than being lost with the entry.
+fn record_lease(
+    dhcp_server: &Arc<DhcpServer>,
+    con: &Connection,
+    client_macaddr: MacAddr,
+    ip_addr: Ipv4Addr,
+    leased_at: i64,
+    expires_at: i64,
+) -> Result<(), failure::Error> {
+    if let Some(entry) = database::select_entry(con, client_macaddr)? {
+        if !entry.deleted && entry.ip_addr != ip_addr {
+            info!(
+                "{} moves from {} to {}",
+                client_macaddr, entry.ip_addr, ip_addr
+            );
+            dhcp_server.release_address(entry.ip_addr);
+        }
+    }
+    database::upsert_entry(con, client_macaddr, ip_addr, leased_at, expires_at)
+}
+
+/// The reservation for the client that sent `packet`, if it has one
+fn find_reservation(
+    dhcp_server: &Arc<DhcpServer>,
+    packet: &DhcpPacket,
+) -> Result<Option<Reservation>, failure::Error> {
+    let client_id = packet.get_option(Code::ClientIdentifier as u8);
+    let con = dhcp_server.db_connection.lock().unwrap();
+    database::select_reservation(&con, packet.get_chaddr(), client_id.as_deref())
+}
+
+/// The lease time for a client, which its reservation may set
+fn lease_secs(dhcp_server: &Arc<DhcpServer>, reservation: Option<&Reservation>) -> u32 {
+    reservation
+        .and_then(|reservation| reservation.lease_time)
+        .unwrap_or(dhcp_server.lease_secs)
+}
+
+/// Whether a message names this server in its server identifier option
+fn is_for_this_server(
+    dhcp_server: &Arc<DhcpServer>,
+    server_id: &[u8],
+) -> Result<bool, failure::Error> {
+    let server_ip = util::u8_to_ipv4addr(server_id)
+        .ok_or_else(|| failure::err_msg("Failed to convert ip addr."))?;
+    Ok(server_ip == dhcp_server.server_address)
+}
+
+fn get_requested_ip(packet: &DhcpPacket) -> Result<Ipv4Addr, failure::Error> {
+    let ip_bin = packet
+        .get_option(Code::RequestedIpAddress as u8)
+        .ok_or_else(|| failure::err_msg("Failed to obtain requested ip address."))?;
+    util::u8_to_ipv4addr(&ip_bin).ok_or_else(|| failure::err_msg("Failed to convert ip addr."))
+}
+
+/// Lease `ip_addr` to the client for the full lease time from now, and acknowledge it
+fn ack_lease(
+    tx_id: u32,
+    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
+    packet: &DhcpPacket,
+    soc: &UdpSocket,
+    ip_addr: Ipv4Addr,
+) -> Result<(), failure::Error> {
+    let reservation = find_reservation(dhcp_server, packet)?;
+    let lease_secs = lease_secs(dhcp_server, reservation.as_ref());
+    let now = util::unix_time_now();
+    let expires_at = now + i64::from(lease_secs);
+    {
+        let con = dhcp_server.db_connection.lock().unwrap();
+        record_lease(
+            dhcp_server,
+            &con,
+            packet.get_chaddr(),
+            ip_addr,
+            now,
+            expires_at,
+        )?;
+    }
+
+    let dhcp_packet = make_dhcp_packet(
+        packet,
+        dhcp_server,
+        scope,
+        DHCPACK,
+        ip_addr,
+        reservation.as_ref(),
+    )?;
+    util::send_dhcp_response(
+        soc,
+        dhcp_packet.get_buffer(),
+        reply_destination(packet, DHCPACK),
+    )?;
+    info!(
+        "{:x}: sent DHCPACK, {} is leased for {}s",
+        tx_id, ip_addr, lease_secs
+    );
+    Ok(())
+}
+
+fn send_dhcp_nak(
+    tx_id: u32,
+    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
+    packet: &DhcpPacket,
+    soc: &UdpSocket,
+) -> Result<(), failure::Error> {
+    let dhcp_packet = make_dhcp_packet(
+        packet,
+        dhcp_server,
+        scope,
+        DHCPNAK,
+        Ipv4Addr::UNSPECIFIED,
+        None,
+    )?;
+    util::send_dhcp_response(
+        soc,
+        dhcp_packet.get_buffer(),
+        reply_destination(packet, DHCPNAK),
+    )?;
+    info!("{:x}: sent DHCPNAK", tx_id);
+    Ok(())
+}
+
+/// Where a reply goes: back to the relay agent that passed on the message, which hands it to the
+/// client, or else straight to a client that's using its address already, or broadcast to one
+/// that has none yet. NAKs are always broadcast, since the client's address may be wrong.
+fn reply_destination(received_packet: &DhcpPacket, message_type: u8) -> SocketAddr {
+    let giaddr = received_packet.get_giaddr();
+    if !giaddr.is_unspecified() {
+        return SocketAddr::from((giaddr, util::DHCP_SERVER_PORT));
+    }
+
+    let ciaddr = received_packet.get_ciaddr();
+    let destination = if message_type == DHCPNAK || ciaddr.is_unspecified() {
+        Ipv4Addr::BROADCAST
+    } else {
+        ciaddr
+    };
+    SocketAddr::from((destination, util::DHCP_CLIENT_PORT))
+}
+
+/// A DHCPREQUEST in SELECTING state, accepting an offer from one of the servers that replied
 fn dhcp_request_message_handler_responded_to_offer(
     tx_id: u32,
-    dhcp_server: Arc<DhcpServer>,
+    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
     packet: &DhcpPacket,
     client_macaddr: MacAddr,
     soc: &UdpSocket,
     server_id: Vec<u8>,
 ) -> Result<(), failure::Error> {
-    Ok(())
+    info!("{:x}: received DHCPREQUEST with server identifier", tx_id);
+
+    if !is_for_this_server(dhcp_server, &server_id)? {
+        // the address held for the offer goes back to the pool when the hold runs out
+        info!("{:x}: client has chosen another dhcp server", tx_id);
+        return Ok(());
+    }
+
+    let requested_ip = get_requested_ip(packet)?;
+    let offered = {
+        let con = dhcp_server.db_connection.lock().unwrap();
+        match database::select_entry(&con, client_macaddr)? {
+            Some(entry) => !entry.deleted && entry.ip_addr == requested_ip,
+            None => false,
+        }
+    };
+    if offered {
+        ack_lease(tx_id, dhcp_server, scope, packet, soc, requested_ip)
+    } else {
+        info!(
+            "{:x}: {} was not offered to {}",
+            tx_id, requested_ip, client_macaddr
+        );
+        send_dhcp_nak(tx_id, dhcp_server, scope, packet, soc)
+    }
 }
 
+/// A DHCPREQUEST without a server identifier, from a client that has an address already: after
+/// a restart (INIT-REBOOT), with the address in the requested ip address option, or to extend
+/// its lease (RENEWING, or REBINDING with any server), with the address in `ciaddr`.
+/// As RFC 2131 4.3.2 has it, an address that isn't on this network is refused, but a client
+/// this server has no record of is left to whichever server leased it the address.
 fn dhcp_request_message_handler_to_reallocate(
     tx_id: u32,
-    dhcp_server: Arc<DhcpServer>,
+    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
     packet: &DhcpPacket,
     client_macaddr: MacAddr,
     soc: &UdpSocket,
 ) -> Result<(), failure::Error> {
+    let ciaddr = packet.get_ciaddr();
+    let requested_ip = if ciaddr.is_unspecified() {
+        info!("{:x}: received DHCPREQUEST to reuse an address", tx_id);
+        get_requested_ip(packet)?
+    } else {
+        info!(
+            "{:x}: received DHCPREQUEST to renew the lease of {}",
+            tx_id, ciaddr
+        );
+        ciaddr
+    };
+
+    // such as a client that has moved from another network
+    if !scope.network_addr.contains(requested_ip) {
+        info!("{:x}: {} is not on this network", tx_id, requested_ip);
+        return send_dhcp_nak(tx_id, dhcp_server, scope, packet, soc);
+    }
+
+    // a client with a reservation can only have its reserved address, leased before or not
+    if let Some(reservation) = find_reservation(dhcp_server, packet)? {
+        if reservation.ip_addr == requested_ip {
+            let holder = {
+                let con = dhcp_server.db_connection.lock().unwrap();
+                let now = util::unix_time_now();
+                database::select_other_holder(&con, requested_ip, client_macaddr, now)?
+            };
+            if let Some(holder) = holder {
+                info!(
+                    "{:x}: {} is reserved for {}, but leased to {}",
+                    tx_id, requested_ip, client_macaddr, holder
+                );
+                return send_dhcp_nak(tx_id, dhcp_server, scope, packet, soc);
+            }
+            return ack_lease(tx_id, dhcp_server, scope, packet, soc, requested_ip);
+        }
+        info!(
+            "{:x}: {} is reserved {}, not {}",
+            tx_id, client_macaddr, reservation.ip_addr, requested_ip
+        );
+        return send_dhcp_nak(tx_id, dhcp_server, scope, packet, soc);
+    }
+
+    // an address reserved since it was leased to this client is handed over to the client it's
+    // reserved for, and this one starts over with DHCPDISCOVER
+    if dhcp_server.is_reserved(requested_ip) {
+        info!(
+            "{:x}: {} is reserved for another client than {}",
+            tx_id, requested_ip, client_macaddr
+        );
+        {
+            let con = dhcp_server.db_connection.lock().unwrap();
+            if let Some(entry) = database::select_entry(&con, client_macaddr)? {
+                if entry.ip_addr == requested_ip {
+                    database::delete_entry(&con, client_macaddr)?;
+                }
+            }
+        }
+        return send_dhcp_nak(tx_id, dhcp_server, scope, packet, soc);
+    }
+
+    let entry = {
+        let con = dhcp_server.db_connection.lock().unwrap();
+        database::select_entry(&con, client_macaddr)?
+    };
+    match entry {
+        None => {
+            info!("{:x}: no lease is known for {}", tx_id, client_macaddr);
+            Ok(())
+        }
+        // a lease that ran out can be taken up again while no one else has the address
+        Some(entry)
+            if entry.ip_addr == requested_ip
+                && (!entry.deleted || scope.pick_specified_ip(requested_ip).is_some()) =>
+        {
+            ack_lease(tx_id, dhcp_server, scope, packet, soc, requested_ip)
+        }
+        Some(_) => {
+            info!(
+                "{:x}: {} is not leased to {}",
+                tx_id, requested_ip, client_macaddr
+            );
+            send_dhcp_nak(tx_id, dhcp_server, scope, packet, soc)
+        }
+    }
+}
+
+/// A client found the address it was given in use by another host. The address is kept out of
+/// the pool for the quarantine time, and the client starts over with DHCPDISCOVER.
+fn dhcp_decline_message_handler(
+    tx_id: u32,
+    dhcp_server: &Arc<DhcpServer>,
+    packet: &DhcpPacket,
+    client_macaddr: MacAddr,
+) -> Result<(), failure::Error> {
+    info!("{:x}: received DHCPDECLINE", tx_id);
+
+    let server_id = packet
+        .get_option(Code::ServerIdentifier as u8)
+        .ok_or_else(|| failure::err_msg("Failed to obtain server identifier."))?;
+    if !is_for_this_server(dhcp_server, &server_id)? {
+        return Ok(());
+    }
+
+    let declined_ip = get_requested_ip(packet)?;
+    let con = dhcp_server.db_connection.lock().unwrap();
+    match database::select_entry(&con, client_macaddr)? {
+        Some(entry) if !entry.deleted && entry.ip_addr == declined_ip => {
+            database::delete_entry(&con, client_macaddr)?;
+            dhcp_server.quarantine_address(&con, declined_ip)?;
+            info!(
+                "{:x}: {} is in use by another host, and quarantined",
+                tx_id, declined_ip
+            );
+        }
+        _ => info!(
+            "{:x}: {} is not leased to {}",
+            tx_id, declined_ip, client_macaddr
+        ),
+    }
+    Ok(())
+}
+
+/// A client that configured its address some other way asks for the rest of its configuration,
+/// which is sent without leasing anything
+fn dhcp_inform_message_handler(
+    tx_id: u32,
+    dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
+    packet: &DhcpPacket,
+    soc: &UdpSocket,
+) -> Result<(), failure::Error> {
+    info!(
+        "{:x}: received DHCPINFORM from {}",
+        tx_id,
+        packet.get_ciaddr()
+    );
+
+    let reservation = find_reservation(dhcp_server, packet)?;
+    let dhcp_packet = make_dhcp_packet(
+        packet,
+        dhcp_server,
+        scope,
+        DHCPACK,
+        Ipv4Addr::UNSPECIFIED,
+        reservation.as_ref(),
+    )?;
+    util::send_dhcp_response(
+        soc,
+        dhcp_packet.get_buffer(),
+        reply_destination(packet, DHCPACK),
+    )?;
+    info!("{:x}: sent DHCPACK with configuration only", tx_id);
     Ok(())
 }
 
 fn dhcp_release_message_handler(
     tx_id: u32,
-    dhcp_server: Arc<DhcpServer>,
+    dhcp_server: &Arc<DhcpServer>,
     packet: &DhcpPacket,
     client_macaddr: MacAddr,
 ) -> Result<(), failure::Error> {
+    info!("{:x}: received DHCPRELEASE", tx_id);
+
+    let released_ip = packet.get_ciaddr();
+    let con = dhcp_server.db_connection.lock().unwrap();
+    match database::select_entry(&con, client_macaddr)? {
+        Some(entry) if !entry.deleted && entry.ip_addr == released_ip => {
+            database::delete_entry(&con, client_macaddr)?;
+            dhcp_server.release_address(released_ip);
+            info!("{:x}: {} was released", tx_id, released_ip);
+        }
+        _ => info!(
+            "{:x}: {} is not leased to {}",
+            tx_id, released_ip, client_macaddr
+        ),
+    }
     Ok(())
 }
 
 fn make_dhcp_packet(
     received_packet: &DhcpPacket,
     dhcp_server: &Arc<DhcpServer>,
+    scope: &Scope,
     message_type: u8,
     ip_to_be_leased: Ipv4Addr,
+    reservation: Option<&Reservation>,
 ) -> Result<DhcpPacket, failure::Error> {
     let buffer = vec![0u8; DHCP_SIZE];
     let mut dhcp_packet = DhcpPacket::new(buffer).unwrap();
 
     dhcp_packet.set_op(BOOTREPLY);
     dhcp_packet.set_htype(HTYPE_ETHER);
-    dhcp_packet.set_hlen(6);
+    dhcp_packet.set_hlen(MACADDR_SIZE);
     dhcp_packet.set_xid(received_packet.get_xid());
     if message_type == DHCPACK {
         dhcp_packet.set_ciaddr(received_packet.get_ciaddr());
     }
     dhcp_packet.set_yiaddr(ip_to_be_leased);
-    dhcp_packet.set_flags(received_packet.get_flags());
+    if message_type == DHCPNAK && !received_packet.get_giaddr().is_unspecified() {
+        // the relay agent is to broadcast it, as the client's address may be wrong
+        let mut flags = received_packet.get_flags().to_vec();
+        flags[0] |= BROADCAST_FLAG;
+        dhcp_packet.set_flags(&flags);
+    } else {
+        dhcp_packet.set_flags(received_packet.get_flags());
+    }
     dhcp_packet.set_giaddr(received_packet.get_giaddr());
     dhcp_packet.set_chaddr(received_packet.get_chaddr());
 
@@ -230,37 +715,450 @@ fn make_dhcp_packet(
         1,
         Some(&[message_type]),
     );
-    dhcp_packet.set_option(
-        &mut cursor,
-        Code::IPAddressLeaseTime as u8,
-        4,
-        Some(&dhcp_server.lease_time),
-    );
     dhcp_packet.set_option(
         &mut cursor,
         Code::ServerIdentifier as u8,
         4,
         Some(&dhcp_server.server_address.octets()),
     );
-    dhcp_packet.set_option(
-        &mut &mut cursor,
-        Code::SubnetMask as u8,
-        4,
-        Some(&dhcp_server.subnet_mask.octets()),
-    );
-    dhcp_packet.set_option(
-        &mut &mut cursor,
-        Code::Router as u8,
-        4,
-        Some(&dhcp_server.default_gateway.octets()),
-    );
-    dhcp_packet.set_option(
-        &mut &mut cursor,
-        Code::DNS as u8,
-        4,
-        Some(&dhcp_server.dns_server.octets()),
-    );
+    // a NAK carries no configuration, and an ACK to DHCPINFORM leases nothing
+    if message_type != DHCPNAK {
+        if !ip_to_be_leased.is_unspecified() {
+            let lease_secs = lease_secs(dhcp_server, reservation);
+            dhcp_packet.set_option(
+                &mut cursor,
+                Code::IPAddressLeaseTime as u8,
+                4,
+                Some(&util::make_big_endian_vec_from_u32(lease_secs)?),
+            );
+            // clients renew with this server at half the lease, and with any server at 7/8 of it
+            dhcp_packet.set_option(
+                &mut cursor,
+                Code::RenewalTimeValue as u8,
+                4,
+                Some(&util::make_big_endian_vec_from_u32(lease_secs / 2)?),
+            );
+            dhcp_packet.set_option(
+                &mut cursor,
+                Code::RebindingTimeValue as u8,
+                4,
+                Some(&util::make_big_endian_vec_from_u32(
+                    (u64::from(lease_secs) * 7 / 8) as u32,
+                )?),
+            );
+        }
+        let default_gateway = reservation
+            .and_then(|reservation| reservation.default_gateway)
+            .unwrap_or(scope.default_gateway);
+        let dns_server = reservation
+            .and_then(|reservation| reservation.dns_server)
+            .unwrap_or(scope.dns_server);
+        dhcp_packet.set_option(
+            &mut cursor,
+            Code::SubnetMask as u8,
+            4,
+            Some(&scope.subnet_mask.octets()),
+        );
+        dhcp_packet.set_option(
+            &mut cursor,
+            Code::Router as u8,
+            4,
+            Some(&default_gateway.octets()),
+        );
+        dhcp_packet.set_option(&mut cursor, Code::Dns as u8, 4, Some(&dns_server.octets()));
+        if let Some(hostname) = reservation.and_then(|reservation| reservation.hostname.as_ref()) {
+            let hostname = &hostname.as_bytes()[..hostname.len().min(MAX_HOST_NAME_LEN)];
+            dhcp_packet.set_option(
+                &mut cursor,
+                Code::HostName as u8,
+                hostname.len(),
+                Some(hostname),
+            );
+        }
+    }
+    // relay agents expect their information back as they sent it (RFC 3046 2.2)
+    if let Some(relay_agent_info) = received_packet.get_option(Code::RelayAgentInformation as u8) {
+        // with one byte to spare for the end option
+        if cursor + 2 + relay_agent_info.len() < DHCP_SIZE {
+            dhcp_packet.set_option(
+                &mut cursor,
+                Code::RelayAgentInformation as u8,
+                relay_agent_info.len(),
+                Some(&relay_agent_info),
+            );
+        } else {
+            error!("No room is left for the relay agent information");
+        }
+    }
     dhcp_packet.set_option(&mut cursor, Code::End as u8, 0, None);
 
     Ok(dhcp_packet)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcp::tests::test_server;
+
+    const CLIENT_MACADDR: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+
+    fn client_macaddr() -> MacAddr {
+        let b = CLIENT_MACADDR;
+        MacAddr::new(b[0], b[1], b[2], b[3], b[4], b[5])
+    }
+
+    /// A message from the client, with `options` after its message type
+    fn client_packet(
+        message_type: u8,
+        ciaddr: Ipv4Addr,
+        giaddr: Ipv4Addr,
+        options: &[(u8, &[u8])],
+    ) -> DhcpPacket {
+        let mut packet = DhcpPacket::new(vec![0u8; DHCP_SIZE]).unwrap();
+        packet.set_op(BOOTREQUEST);
+        packet.set_htype(HTYPE_ETHER);
+        packet.set_hlen(MACADDR_SIZE);
+        packet.set_xid(&[0x12, 0x34, 0x56, 0x78]);
+        packet.set_ciaddr(ciaddr);
+        packet.set_giaddr(giaddr);
+        packet.set_chaddr(client_macaddr());
+
+        let mut cursor = dhcp::OPTIONS;
+        packet.set_magic_cookie(&mut cursor);
+        packet.set_option(
+            &mut cursor,
+            Code::MessageType as u8,
+            1,
+            Some(&[message_type]),
+        );
+        for &(code, contents) in options {
+            packet.set_option(&mut cursor, code, contents.len(), Some(contents));
+        }
+        packet.set_option(&mut cursor, Code::End as u8, 0, None);
+        packet
+    }
+
+    fn u32_option(packet: &DhcpPacket, code: Code) -> Option<u32> {
+        packet
+            .get_option(code as u8)
+            .map(|value| BigEndian::read_u32(&value))
+    }
+
+    #[test]
+    fn replies_go_to_relay_agent_or_client() {
+        let none = Ipv4Addr::UNSPECIFIED;
+        let client = Ipv4Addr::new(10, 0, 1, 20);
+        let relay_agent = Ipv4Addr::new(10, 0, 2, 1);
+        let destination = |message_type, ciaddr, giaddr| {
+            reply_destination(
+                &client_packet(DHCPREQUEST, ciaddr, giaddr, &[]),
+                message_type,
+            )
+        };
+
+        assert_eq!(
+            destination(DHCPOFFER, none, none),
+            SocketAddr::from((Ipv4Addr::BROADCAST, util::DHCP_CLIENT_PORT))
+        );
+        assert_eq!(
+            destination(DHCPACK, client, none),
+            SocketAddr::from((client, util::DHCP_CLIENT_PORT))
+        );
+        assert_eq!(
+            destination(DHCPNAK, client, none),
+            SocketAddr::from((Ipv4Addr::BROADCAST, util::DHCP_CLIENT_PORT))
+        );
+        assert_eq!(
+            destination(DHCPNAK, client, relay_agent),
+            SocketAddr::from((relay_agent, util::DHCP_SERVER_PORT))
+        );
+    }
+
+    #[test]
+    fn offer_carries_lease_and_configuration() {
+        let dhcp_server = Arc::new(test_server());
+        let received = client_packet(
+            DHCPDISCOVER,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        let scope = dhcp_server.select_scope(&received).unwrap();
+        let offered = Ipv4Addr::new(10, 0, 1, 20);
+        let packet =
+            make_dhcp_packet(&received, &dhcp_server, scope, DHCPOFFER, offered, None).unwrap();
+
+        assert_eq!(packet.get_op(), BOOTREPLY);
+        assert_eq!(packet.get_xid(), received.get_xid());
+        assert_eq!(&packet.get_buffer()[16..20], &offered.octets());
+        assert_eq!(
+            packet.get_option(Code::MessageType as u8),
+            Some(vec![DHCPOFFER])
+        );
+        assert_eq!(
+            packet.get_option(Code::ServerIdentifier as u8),
+            Some(vec![10, 0, 1, 4])
+        );
+        assert_eq!(u32_option(&packet, Code::IPAddressLeaseTime), Some(300));
+        assert_eq!(u32_option(&packet, Code::RenewalTimeValue), Some(150));
+        assert_eq!(u32_option(&packet, Code::RebindingTimeValue), Some(262));
+        assert_eq!(
+            packet.get_option(Code::SubnetMask as u8),
+            Some(vec![255, 255, 255, 0])
+        );
+        assert_eq!(
+            packet.get_option(Code::Router as u8),
+            Some(vec![10, 0, 1, 1])
+        );
+        assert_eq!(packet.get_option(Code::Dns as u8), Some(vec![10, 0, 1, 1]));
+        assert_eq!(packet.get_option(Code::RelayAgentInformation as u8), None);
+    }
+
+    #[test]
+    fn nak_and_inform_lease_nothing() {
+        let dhcp_server = Arc::new(test_server());
+        let client = Ipv4Addr::new(10, 0, 1, 20);
+
+        let received = client_packet(DHCPINFORM, client, Ipv4Addr::UNSPECIFIED, &[]);
+        let scope = dhcp_server.select_scope(&received).unwrap();
+        let packet = make_dhcp_packet(
+            &received,
+            &dhcp_server,
+            scope,
+            DHCPACK,
+            Ipv4Addr::UNSPECIFIED,
+            None,
+        )
+        .unwrap();
+        assert_eq!(packet.get_option(Code::IPAddressLeaseTime as u8), None);
+        assert_eq!(packet.get_option(Code::RenewalTimeValue as u8), None);
+        assert_eq!(packet.get_option(Code::RebindingTimeValue as u8), None);
+        assert_eq!(
+            packet.get_option(Code::Router as u8),
+            Some(vec![10, 0, 1, 1])
+        );
+
+        let received = client_packet(DHCPREQUEST, client, Ipv4Addr::UNSPECIFIED, &[]);
+        let packet = make_dhcp_packet(
+            &received,
+            &dhcp_server,
+            scope,
+            DHCPNAK,
+            Ipv4Addr::UNSPECIFIED,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            packet.get_option(Code::MessageType as u8),
+            Some(vec![DHCPNAK])
+        );
+        assert_eq!(packet.get_option(Code::IPAddressLeaseTime as u8), None);
+        assert_eq!(packet.get_option(Code::SubnetMask as u8), None);
+        assert_eq!(packet.get_option(Code::Router as u8), None);
+        assert_eq!(packet.get_option(Code::Dns as u8), None);
+        assert_eq!(packet.get_flags()[0] & BROADCAST_FLAG, 0);
+    }
+
+    #[test]
+    fn relayed_replies_return_agent_information() {
+        let dhcp_server = Arc::new(test_server());
+        let relay_agent = Ipv4Addr::new(10, 0, 2, 1);
+        let agent_information: &[u8] = &[1, 4, b'e', b't', b'h', b'0'];
+        let received = client_packet(
+            DHCPREQUEST,
+            Ipv4Addr::UNSPECIFIED,
+            relay_agent,
+            &[(Code::RelayAgentInformation as u8, agent_information)],
+        );
+        let scope = dhcp_server.select_scope(&received).unwrap();
+        assert_eq!(scope.default_gateway, relay_agent);
+
+        let packet = make_dhcp_packet(
+            &received,
+            &dhcp_server,
+            scope,
+            DHCPNAK,
+            Ipv4Addr::UNSPECIFIED,
+            None,
+        )
+        .unwrap();
+        assert_eq!(packet.get_giaddr(), relay_agent);
+        assert_eq!(packet.get_flags()[0] & BROADCAST_FLAG, BROADCAST_FLAG);
+        assert_eq!(
+            packet.get_option(Code::RelayAgentInformation as u8),
+            Some(agent_information.to_vec())
+        );
+
+        let offered = Ipv4Addr::new(10, 0, 2, 20);
+        let packet =
+            make_dhcp_packet(&received, &dhcp_server, scope, DHCPOFFER, offered, None).unwrap();
+        assert_eq!(packet.get_flags()[0] & BROADCAST_FLAG, 0);
+        assert_eq!(
+            packet.get_option(Code::Router as u8),
+            Some(vec![10, 0, 2, 1])
+        );
+        assert_eq!(
+            packet.get_option(Code::RelayAgentInformation as u8),
+            Some(agent_information.to_vec())
+        );
+    }
+
+    #[test]
+    fn holding_an_offer_returns_the_address_it_replaces() {
+        let dhcp_server = Arc::new(test_server());
+        let received = client_packet(
+            DHCPDISCOVER,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        let scope = dhcp_server.select_scope(&received).unwrap();
+        let leased = scope.pick_available_ip().unwrap();
+        let offered = scope.pick_available_ip().unwrap();
+        {
+            let con = dhcp_server.db_connection.lock().unwrap();
+            let now = util::unix_time_now();
+            database::upsert_entry(&con, client_macaddr(), leased, now, now + 300).unwrap();
+        }
+
+        hold_offered_ip(&dhcp_server, client_macaddr(), offered).unwrap();
+        assert_eq!(scope.pick_specified_ip(leased), Some(leased));
+        assert_eq!(scope.pick_specified_ip(offered), None);
+        let con = dhcp_server.db_connection.lock().unwrap();
+        let entry = database::select_entry(&con, client_macaddr())
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.ip_addr, offered);
+        assert!(!entry.deleted);
+    }
+
+    #[test]
+    fn quarantined_reservation_is_not_offered() {
+        let dhcp_server = Arc::new(test_server());
+        let received = client_packet(
+            DHCPDISCOVER,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        let scope = dhcp_server.select_scope(&received).unwrap();
+        let reservation = Reservation {
+            ip_addr: Ipv4Addr::new(10, 0, 1, 9),
+            hostname: None,
+            default_gateway: None,
+            dns_server: None,
+            lease_time: None,
+        };
+        assert_eq!(
+            select_lease_ip(&dhcp_server, scope, &received, Some(&reservation)).unwrap(),
+            reservation.ip_addr
+        );
+
+        {
+            let con = dhcp_server.db_connection.lock().unwrap();
+            dhcp_server
+                .quarantine_address(&con, reservation.ip_addr)
+                .unwrap();
+        }
+        assert!(select_lease_ip(&dhcp_server, scope, &received, Some(&reservation)).is_err());
+    }
+
+    #[test]
+    fn reservation_leased_to_another_client_is_not_offered() {
+        let dhcp_server = Arc::new(test_server());
+        let received = client_packet(
+            DHCPDISCOVER,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        let scope = dhcp_server.select_scope(&received).unwrap();
+        let reservation = Reservation {
+            ip_addr: Ipv4Addr::new(10, 0, 1, 9),
+            hostname: None,
+            default_gateway: None,
+            dns_server: None,
+            lease_time: None,
+        };
+        let other_macaddr = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+        let now = util::unix_time_now();
+        {
+            let con = dhcp_server.db_connection.lock().unwrap();
+            database::upsert_entry(&con, other_macaddr, reservation.ip_addr, now, now + 300)
+                .unwrap();
+        }
+        assert!(select_lease_ip(&dhcp_server, scope, &received, Some(&reservation)).is_err());
+
+        // the client's own lease, or one that has run out, is no obstacle
+        {
+            let con = dhcp_server.db_connection.lock().unwrap();
+            database::upsert_entry(&con, other_macaddr, reservation.ip_addr, now - 300, now)
+                .unwrap();
+            database::upsert_entry(&con, client_macaddr(), reservation.ip_addr, now, now + 300)
+                .unwrap();
+        }
+        assert_eq!(
+            select_lease_ip(&dhcp_server, scope, &received, Some(&reservation)).unwrap(),
+            reservation.ip_addr
+        );
+    }
+
+    #[test]
+    fn address_reserved_since_it_was_leased_is_handed_over() {
+        let reserved = Ipv4Addr::new(10, 0, 1, 9);
+        let now = util::unix_time_now();
+        let con = dhcp::tests::test_database();
+        con.execute(
+            "INSERT INTO reservations (mac_addr, ip_addr) VALUES ('02:00:00:00:00:02', ?1)",
+            &[&reserved.to_string()],
+        )
+        .unwrap();
+        database::upsert_entry(&con, client_macaddr(), reserved, now, now + 300).unwrap();
+        let dhcp_server = Arc::new(dhcp::tests::test_server_with(con));
+
+        let discover = client_packet(
+            DHCPDISCOVER,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        let scope = dhcp_server.select_scope(&discover).unwrap();
+        let offered = select_lease_ip(&dhcp_server, scope, &discover, None).unwrap();
+        assert_ne!(offered, reserved);
+        dhcp_server.release_address(offered);
+
+        // the renewal is refused, through a relay agent on this host so that the NAK can be sent
+        let soc = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let renewal = client_packet(DHCPREQUEST, reserved, Ipv4Addr::LOCALHOST, &[]);
+        dhcp_request_message_handler_to_reallocate(
+            0,
+            &dhcp_server,
+            scope,
+            &renewal,
+            client_macaddr(),
+            &soc,
+        )
+        .unwrap();
+
+        // and the client the address is reserved for can have it
+        let owner = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+        let reservation = {
+            let con = dhcp_server.db_connection.lock().unwrap();
+            assert!(
+                database::select_entry(&con, client_macaddr())
+                    .unwrap()
+                    .unwrap()
+                    .deleted
+            );
+            database::select_reservation(&con, owner, None)
+                .unwrap()
+                .unwrap()
+        };
+        let mut discover = discover;
+        discover.set_chaddr(owner);
+        assert_eq!(
+            select_lease_ip(&dhcp_server, scope, &discover, Some(&reservation)).unwrap(),
+            reserved
+        );
+    }
+}
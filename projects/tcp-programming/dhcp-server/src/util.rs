@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fs, io, net::{AddrParseError, IpAddr, Ipv4Addr}, str::FromStr, sync::mpsc, thread, time::Duration};
+use std::{collections::HashMap, fs, io, net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr, UdpSocket}, str::FromStr, sync::mpsc, thread, time::{Duration, SystemTime, UNIX_EPOCH}};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use log::{debug, info};
@@ -10,9 +10,13 @@ pub const SERVER_IDENTIFIER_KEY: &str = "SERVER_IDENTIFIER";
 pub const DEFAULT_GATEWAY_KEY: &str = "DEFAULT_GATEWAY";
 pub const DNS_SERVER_KEY: &str = "DNS_SERVER";
 pub const LEASE_TIME_KEY: &str = "LEASE_TIME";
+pub const QUARANTINE_TIME_KEY: &str = "QUARANTINE_TIME";
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
 
 pub fn load_env() -> HashMap<String, String> {
-    return fs::read_to_string(".env")
+    fs::read_to_string(".env")
         .expect("Failed to read .env file")
         .lines()
         .filter_map(|line| {
@@ -22,7 +26,7 @@ pub fn load_env() -> HashMap<String, String> {
                 _ => None,
             }
         })
-        .collect();
+        .collect()
 }
 
 pub fn obtain_static_addresses(
@@ -39,6 +43,29 @@ pub fn obtain_static_addresses(
     Ok(map)
 }
 
+/// The addresses of the scope whose keys in `.env` start with `prefix`, keyed without it, or
+/// `None` if there's no such scope. A scope without all of its keys is an error.
+pub fn obtain_scope_addresses(
+    env: &HashMap<String, String>,
+    prefix: &str,
+) -> Result<Option<HashMap<String, Ipv4Addr>>, failure::Error> {
+    if !env.contains_key(&format!("{}{}", prefix, NETWORK_ADDR_KEY)) {
+        return Ok(None);
+    }
+
+    let mut map = HashMap::new();
+    for &key in &[NETWORK_ADDR_KEY, SUBNET_MASK_KEY, DEFAULT_GATEWAY_KEY, DNS_SERVER_KEY] {
+        let prefixed_key = format!("{}{}", prefix, key);
+        let addr = env
+            .get(&prefixed_key)
+            .ok_or_else(|| failure::format_err!("Missing {:?} entry", prefixed_key))?
+            .parse::<Ipv4Addr>()?;
+        map.insert(key.to_string(), addr);
+    }
+
+    Ok(Some(map))
+}
+
 fn parse_and_insert(
     key: &str,
     env: &HashMap<String, String>,
@@ -58,7 +85,7 @@ where
     F: FromStr,
 {
     env.get(key)
-        .expect(format!("Missing {:?} entry", key).as_str())
+        .unwrap_or_else(|| panic!("Missing {:?} entry", key))
         .parse::<F>()
 }
 
@@ -68,6 +95,23 @@ pub fn make_big_endian_vec_from_u32(i: u32) -> Result<Vec<u8>, io::Error> {
     Ok(v)
 }
 
+/// Seconds since the Unix epoch, as lease times are stored
+pub fn unix_time_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn send_dhcp_response(
+    soc: &UdpSocket,
+    data: &[u8],
+    destination: SocketAddr,
+) -> Result<(), failure::Error> {
+    soc.send_to(data, destination)?;
+    Ok(())
+}
+
 pub fn is_ipaddr_available(target_ip: Ipv4Addr) -> Result<(), failure::Error> {
     let icmp_buf = create_default_icmp_buffer();
     let icmp_packet = EchoRequestPacket::new(&icmp_buf).unwrap();
@@ -81,15 +125,8 @@ pub fn is_ipaddr_available(target_ip: Ipv4Addr) -> Result<(), failure::Error> {
     thread::spawn(move || {
         let mut iter = icmp_packet_iter(&mut transport_receiver);
         let (packet, _) = iter.next().unwrap();
-        if packet.get_icmp_type() == IcmpTypes::EchoReply {
-            match sender.send(true) {
-                Err(_) => {
-                    info!("icmp timeout");
-                }
-                _ => {
-                    return;
-                }
-            }
+        if packet.get_icmp_type() == IcmpTypes::EchoReply && sender.send(true).is_err() {
+            info!("icmp timeout");
         }
     });
 
@@ -116,4 +153,46 @@ pub fn u8_to_ipv4addr(buf: &[u8]) -> Option<Ipv4Addr> {
     } else {
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn scope_addresses_by_prefix() {
+        let env = env(&[
+            ("NETWORK_ADDR", "10.0.1.0"),
+            ("SCOPE1_NETWORK_ADDR", "10.0.2.0"),
+            ("SCOPE1_SUBNET_MASK", "255.255.255.0"),
+            ("SCOPE1_DEFAULT_GATEWAY", "10.0.2.1"),
+            ("SCOPE1_DNS_SERVER", "10.0.2.2"),
+            ("SCOPE2_NETWORK_ADDR", "10.0.3.0"),
+            ("SCOPE2_SUBNET_MASK", "255.255.255.0"),
+            ("SCOPE2_DEFAULT_GATEWAY", "10.0.3.1"),
+            ("SCOPE2_DNS_SERVER", "not an address"),
+            ("SCOPE4_NETWORK_ADDR", "10.0.4.0"),
+            ("SCOPE4_SUBNET_MASK", "255.255.255.0"),
+            ("SCOPE4_DEFAULT_GATEWAY", "10.0.4.1"),
+        ]);
+
+        let addresses = obtain_scope_addresses(&env, "SCOPE1_").unwrap().unwrap();
+        assert_eq!(addresses.len(), 4);
+        assert_eq!(addresses[NETWORK_ADDR_KEY], Ipv4Addr::new(10, 0, 2, 0));
+        assert_eq!(addresses[SUBNET_MASK_KEY], Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(addresses[DEFAULT_GATEWAY_KEY], Ipv4Addr::new(10, 0, 2, 1));
+        assert_eq!(addresses[DNS_SERVER_KEY], Ipv4Addr::new(10, 0, 2, 2));
+
+        assert!(obtain_scope_addresses(&env, "SCOPE2_").is_err());
+        assert!(obtain_scope_addresses(&env, "SCOPE3_").unwrap().is_none());
+        let missing = obtain_scope_addresses(&env, "SCOPE4_").unwrap_err();
+        assert!(missing.to_string().contains("SCOPE4_DNS_SERVER"));
+    }
+}
@@ -216,6 +216,19 @@ pub enum Opcode {
         dest: Register,
         arg_count: NumArgs,
     },
+    MakeCoroutine {
+        dest: Register,
+        function: Register,
+    },
+    Yield {
+        dest: Register,
+        src: Register,
+    },
+    Resume {
+        dest: Register,
+        coroutine: Register,
+        value: Register,
+    },
 }
 
 /// An InstructionStream is a pointer to a ByteCode instance and an instruction pointer giving the
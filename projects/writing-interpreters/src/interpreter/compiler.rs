@@ -18,6 +18,28 @@ use super::{
     ArrayU16, CellPtr, MutatorView, RuntimeError, ScopedPtr,
 };
 
+/// Symbols that `Compiler::compile_apply` compiles as special forms rather than function calls
+pub const SPECIAL_FORMS: &[&str] = &[
+    "quote",
+    "atom?",
+    "nil?",
+    "car",
+    "cdr",
+    "cons",
+    "cond",
+    "is?",
+    "+",
+    "*",
+    "set",
+    "def",
+    "lambda",
+    "\\",
+    "let",
+    "coroutine",
+    "yield",
+    "resume",
+];
+
 /// Compile the given AST and return an anonymous Function object
 pub fn compile<'guard>(
     mem: &'guard MutatorView,
@@ -284,6 +306,16 @@ impl<'parent> Compiler<'parent> {
                 // ANCHOR_END: DefCompileApplyLambda
                 "\\" => self.compile_anonymous_function(mem, args),
                 "let" => self.compile_apply_let(mem, args),
+                "coroutine" => self.push_op2(mem, args, |dest, function| Opcode::MakeCoroutine {
+                    dest,
+                    function,
+                }),
+                "yield" => self.push_op2(mem, args, |dest, src| Opcode::Yield { dest, src }),
+                "resume" => self.push_op3(mem, args, |dest, coroutine, value| Opcode::Resume {
+                    dest,
+                    coroutine,
+                    value,
+                }),
                 _ => self.compile_apply_call(mem, function, args),
             },
 
@@ -1035,4 +1067,71 @@ mod integration {
 
         test_helper(test_inner);
     }
+
+    #[test]
+    fn compile_coroutine_yield_resume() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // the first resume value is the function argument, later ones are yield results
+            let def_fn = "(def g (x) (+ (yield x) (yield (* x 2))))";
+            let make_co = "(set (quote co) (coroutine g))";
+
+            let t = Thread::alloc(mem)?;
+            eval_helper(mem, t, def_fn)?;
+            eval_helper(mem, t, make_co)?;
+
+            let result = eval_helper(mem, t, "(resume co 1)")?;
+            assert!(result == mem.number(1));
+
+            let result = eval_helper(mem, t, "(resume co 10)")?;
+            assert!(result == mem.number(2));
+
+            let result = eval_helper(mem, t, "(resume co 5)")?;
+            assert!(result == mem.number(15));
+
+            // the function has returned so the coroutine cannot be resumed again
+            assert!(eval_helper(mem, t, "(resume co 0)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_coroutine_generator() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // a generator that counts up forever, yielding from nested recursive calls
+            let def_fn = "(def count (n) (cond (yield n) (count (+ n 1))))";
+            let make_co = "(set (quote gen) (coroutine count))";
+
+            let t = Thread::alloc(mem)?;
+            eval_helper(mem, t, def_fn)?;
+            eval_helper(mem, t, make_co)?;
+
+            eval_helper(mem, t, "(resume gen 5)")?;
+            eval_helper(mem, t, "(resume gen true)")?;
+            let result = eval_helper(mem, t, "(resume gen true)")?;
+            assert!(result == mem.number(7));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_yield_outside_coroutine() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            assert!(eval_helper(mem, t, "(yield 1)").is_err());
+
+            // the thread is still usable afterwards
+            let result = eval_helper(mem, t, "(+ 1 2)")?;
+            assert!(result == mem.number(3));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
 }
@@ -0,0 +1,213 @@
+use std::{collections::HashMap, hash::Hash};
+
+use super::{
+    containers::HashIndexedAnyContainer,
+    dict::Dict,
+    error::err_eval,
+    pair::{vec_from_pairs, Pair},
+    safeptr::{MutatorScope, TaggedScopedPtr},
+    taggedptr::Value,
+    text::Text,
+    MutatorView, RuntimeError,
+};
+
+/// Conversion of a Rust value into a runtime `Value`, allocating on the heap where needed
+pub trait IntoValue {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+}
+
+/// Conversion of a runtime `Value` into an owned Rust value
+pub trait FromValue: Sized {
+    fn from_value<'guard>(
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<Self, RuntimeError>;
+}
+
+/// A set of Rust values that can be passed as arguments to a function call
+pub trait IntoArgs {
+    fn into_args<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError>;
+}
+
+impl IntoValue for isize {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        Ok(mem.number(self))
+    }
+}
+
+impl FromValue for isize {
+    fn from_value<'guard>(
+        _guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<isize, RuntimeError> {
+        match *value {
+            Value::Number(n) => Ok(n),
+            _ => Err(err_eval(&format!("Expected a Number, got {}", value))),
+        }
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.alloc_tagged(Text::new_from_str(mem, self)?)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        self.as_str().into_value(mem)
+    }
+}
+
+/// Both Text and Symbol values can be read as a String
+impl FromValue for String {
+    fn from_value<'guard>(
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<String, RuntimeError> {
+        match *value {
+            Value::Text(t) => Ok(String::from(t.as_str(guard))),
+            Value::Symbol(s) => Ok(String::from(s.as_str(guard))),
+            _ => Err(err_eval(&format!("Expected a Text, got {}", value))),
+        }
+    }
+}
+
+/// `None` is represented as nil
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match self {
+            Some(value) => value.into_value(mem),
+            None => Ok(mem.nil()),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value<'guard>(
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<T>, RuntimeError> {
+        match *value {
+            Value::Nil => Ok(None),
+            _ => Ok(Some(T::from_value(guard, value)?)),
+        }
+    }
+}
+
+/// A Vec becomes a nil-terminated Pair list. An empty Vec is nil.
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let items = self
+            .into_iter()
+            .map(|item| item.into_value(mem))
+            .collect::<Result<Vec<_>, RuntimeError>>()?;
+
+        let mut list = mem.nil();
+        for item in items.into_iter().rev() {
+            list = Pair::cons(mem, item, list)?;
+        }
+
+        Ok(list)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value<'guard>(
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<Vec<T>, RuntimeError> {
+        vec_from_pairs(guard, value)?
+            .into_iter()
+            .map(|item| T::from_value(guard, item))
+            .collect()
+    }
+}
+
+/// Keys must convert to a hashable type - a Number, Symbol or Text
+impl<K: IntoValue, V: IntoValue> IntoValue for HashMap<K, V> {
+    fn into_value<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let dict = Dict::alloc(mem)?;
+
+        for (key, value) in self {
+            let key = key.into_value(mem)?;
+            let value = value.into_value(mem)?;
+            dict.assoc(mem, key, value)?;
+        }
+
+        Ok(dict.as_tagged(mem))
+    }
+}
+
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for HashMap<K, V> {
+    fn from_value<'guard>(
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<HashMap<K, V>, RuntimeError> {
+        match *value {
+            Value::Dict(dict) => dict
+                .entries(guard)
+                .into_iter()
+                .map(|(key, value)| Ok((K::from_value(guard, key)?, V::from_value(guard, value)?)))
+                .collect(),
+            _ => Err(err_eval(&format!("Expected a Dict, got {}", value))),
+        }
+    }
+}
+
+/// Discard the result of an evaluation
+impl FromValue for () {
+    fn from_value<'guard>(
+        _guard: &'guard dyn MutatorScope,
+        _value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+/// Implement `IntoArgs` for a tuple of `IntoValue` types
+macro_rules! into_args_for_tuple {
+    ($($T:ident),*) => {
+        impl<$($T: IntoValue),*> IntoArgs for ($($T,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn into_args<'guard>(
+                self,
+                mem: &'guard MutatorView,
+            ) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+                let ($($T,)*) = self;
+                Ok(vec![$($T.into_value(mem)?),*])
+            }
+        }
+    };
+}
+into_args_for_tuple!();
+into_args_for_tuple!(A);
+into_args_for_tuple!(A, B);
+into_args_for_tuple!(A, B, C);
+into_args_for_tuple!(A, B, C, D);
+into_args_for_tuple!(A, B, C, D, E);
+into_args_for_tuple!(A, B, C, D, E, F);
@@ -8,7 +8,7 @@ use super::{
     containers::{Container, HashIndexedAnyContainer},
     error::ErrorKind,
     hashable::Hashable,
-    printer::Print,
+    printer::{print_with, Print, PrintOptions},
     rawarray::{default_array_growth, RawArray},
     safeptr::{MutatorScope, TaggedCellPtr, TaggedScopedPtr},
     taggedptr::Value,
@@ -51,6 +51,7 @@ impl Dict {
 
         let new_capacity = default_array_growth(data.capacity())?;
         let new_data = RawArray::<DictItem>::with_capacity(mem, new_capacity)?;
+        fill_with_blank_entries(mem, &new_data)?;
 
         let maybe_ptr = data.as_ptr();
         if let Some(ptr) = maybe_ptr {
@@ -67,6 +68,26 @@ impl Dict {
         self.data.set(new_data);
         Ok(())
     }
+
+    /// Return a copy of every key/value association in the Dict, in table order
+    pub fn entries<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        let data = self.data.get();
+        let mut entries = Vec::with_capacity(self.length.get() as usize);
+
+        if let Some(ptr) = data.as_ptr() {
+            for index in 0..data.capacity() {
+                let entry = unsafe { &*(ptr.offset(index as isize)) as &DictItem };
+                if !entry.key.is_nil() {
+                    entries.push((entry.key.get(guard), entry.value.get(guard)));
+                }
+            }
+        }
+
+        entries
+    }
 }
 
 /// Generate a hash value for a key
@@ -81,6 +102,11 @@ fn hash_key<'guard>(
             Ok(hasher.finish())
         }
         Value::Number(n) => Ok(n as u64),
+        Value::Text(text) => {
+            let mut hasher = FnvHasher::default();
+            text.hash(guard, &mut hasher);
+            Ok(hasher.finish())
+        }
         _ => Err(RuntimeError::new(ErrorKind::UnhashableError)),
     }
 }
@@ -279,12 +305,9 @@ impl Container<DictItem> for Dict {
 }
 
 impl Print for Dict {
-    fn print<'guard>(
-        &self,
-        _guard: &'guard dyn MutatorScope,
-        f: &mut fmt::Formatter,
-    ) -> fmt::Result {
-        write!(f, "Dict[unimplemented]")
+    fn print(&self, guard: &dyn MutatorScope, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = Value::Dict(ScopedPtr::new(guard, self));
+        f.write_str(&print_with(guard, value, &PrintOptions::default()))
     }
 }
 
@@ -0,0 +1,180 @@
+use std::marker::PhantomData;
+
+use super::{
+    compiler::compile,
+    conversion::{FromValue, IntoArgs},
+    memory::Memory,
+    pair::Pair,
+    parser::parse,
+    safeptr::TaggedScopedPtr,
+    vm::Thread,
+    CellPtr, Mutator, MutatorView, RuntimeError,
+};
+
+/// A high level interface for embedding the interpreter in a Rust program.
+///
+/// All heap access is wrapped in `Mutator` implementations internally so that values only cross
+/// the boundary by way of the `IntoValue` and `FromValue` conversion traits.
+pub struct Interpreter {
+    mem: Memory,
+    main_thread: CellPtr<Thread>,
+}
+
+impl Interpreter {
+    /// Instantiate a new interpreter with its own heap and an empty set of globals
+    pub fn new() -> Result<Interpreter, RuntimeError> {
+        let mem = Memory::new();
+        let main_thread = mem.mutate(&ThreadMaker {}, ())?;
+        Ok(Interpreter { mem, main_thread })
+    }
+
+    /// Evaluate a single expression and convert the result to a Rust value
+    pub fn eval_str<T: FromValue>(&self, source: &str) -> Result<T, RuntimeError> {
+        let eval = EvalStr {
+            thread: &self.main_thread,
+            _result: PhantomData,
+        };
+        self.mem.mutate(&eval, String::from(source))
+    }
+
+    /// Call the function bound to the global `name`, passing a tuple of arguments, and convert
+    /// the result to a Rust value
+    pub fn call<A: IntoArgs, T: FromValue>(&self, name: &str, args: A) -> Result<T, RuntimeError> {
+        let call = Call {
+            thread: &self.main_thread,
+            _result: PhantomData,
+        };
+        self.mem.mutate(&call, (String::from(name), args))
+    }
+}
+
+/// Mutator that allocates the main Thread
+struct ThreadMaker {}
+
+impl Mutator for ThreadMaker {
+    type Input = ();
+    type Output = CellPtr<Thread>;
+
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<CellPtr<Thread>, RuntimeError> {
+        Ok(CellPtr::new_with(Thread::alloc(mem)?))
+    }
+}
+
+/// Mutator that parses, compiles and evaluates an expression
+struct EvalStr<'thread, T> {
+    thread: &'thread CellPtr<Thread>,
+    _result: PhantomData<T>,
+}
+
+impl<'thread, T: FromValue> Mutator for EvalStr<'thread, T> {
+    type Input = String;
+    type Output = T;
+
+    fn run(&self, mem: &MutatorView, source: String) -> Result<T, RuntimeError> {
+        let ast = parse(mem, &source)?;
+        let value = eval(mem, self.thread, ast)?;
+        T::from_value(mem, value)
+    }
+}
+
+/// Mutator that applies a global function to converted arguments
+struct Call<'thread, A, T> {
+    thread: &'thread CellPtr<Thread>,
+    _result: PhantomData<(A, T)>,
+}
+
+impl<'thread, A: IntoArgs, T: FromValue> Mutator for Call<'thread, A, T> {
+    type Input = (String, A);
+    type Output = T;
+
+    fn run(&self, mem: &MutatorView, (name, args): (String, A)) -> Result<T, RuntimeError> {
+        // Build the expression (name (quote arg1) .. (quote argn)) so that each argument is
+        // loaded as a literal rather than evaluated
+        let quote = mem.lookup_sym("quote");
+        let mut ast = mem.nil();
+        for arg in args.into_args(mem)?.into_iter().rev() {
+            let quoted = Pair::cons(mem, quote, Pair::cons(mem, arg, mem.nil())?)?;
+            ast = Pair::cons(mem, quoted, ast)?;
+        }
+        let ast = Pair::cons(mem, mem.lookup_sym(&name), ast)?;
+
+        let value = eval(mem, self.thread, ast)?;
+        T::from_value(mem, value)
+    }
+}
+
+/// Compile the AST and evaluate it on the given thread
+fn eval<'guard>(
+    mem: &'guard MutatorView,
+    thread: &CellPtr<Thread>,
+    ast: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let function = compile(mem, ast)?;
+    thread.get(mem).quick_vm_eval(mem, function)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::interpreter::error::ErrorKind;
+
+    #[test]
+    fn eval_number() {
+        let interpreter = Interpreter::new().unwrap();
+        let result: isize = interpreter.eval_str("(* 3 (+ 1 2))").unwrap();
+        assert_eq!(result, 9);
+    }
+
+    #[test]
+    fn call_global_function() {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter
+            .eval_str::<()>("(def fact (n) (cond (is? n 0) 1 true (* n (fact (+ n -1)))))")
+            .unwrap();
+
+        let result: isize = interpreter.call("fact", (5,)).unwrap();
+        assert_eq!(result, 120);
+    }
+
+    #[test]
+    fn round_trip_list() {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter
+            .eval_str::<()>("(def second (l) (car (cdr l)))")
+            .unwrap();
+        interpreter.eval_str::<()>("(def id (x) x)").unwrap();
+
+        let input = vec![Some(1), None, Some(3)];
+        let result: Vec<Option<isize>> = interpreter.call("id", (input.clone(),)).unwrap();
+        assert_eq!(result, input);
+
+        let words = vec![String::from("a"), String::from("b")];
+        let result: String = interpreter.call("second", (words,)).unwrap();
+        assert_eq!(result, "b");
+    }
+
+    #[test]
+    fn round_trip_dict() {
+        let interpreter = Interpreter::new().unwrap();
+        interpreter.eval_str::<()>("(def id (x) x)").unwrap();
+
+        let mut input = HashMap::new();
+        input.insert(String::from("one"), 1);
+        input.insert(String::from("two"), 2);
+
+        let result: HashMap<String, isize> = interpreter.call("id", (input.clone(),)).unwrap();
+        assert_eq!(result, input);
+    }
+
+    #[test]
+    fn conversion_type_error() {
+        let interpreter = Interpreter::new().unwrap();
+        let result = interpreter.eval_str::<isize>("(quote a)");
+        assert!(matches!(
+            result.unwrap_err().error_kind(),
+            ErrorKind::EvalError(_)
+        ));
+    }
+}
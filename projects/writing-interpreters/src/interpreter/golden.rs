@@ -0,0 +1,288 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    compiler::compile, error::ErrorKind, memory::Memory, parser::parse_all, vm::Thread, Mutator,
+    MutatorView, RuntimeError,
+};
+
+// annotation markers, written as comments following the expression they describe
+const EXPECT_VALUE: &str = "; =>";
+const EXPECT_ERROR: &str = "; !!";
+
+/// What an annotated expression is expected to evaluate to
+#[derive(Debug, PartialEq)]
+enum Expected {
+    /// `; => <printed value>`
+    Value(String),
+    /// `; !! <ErrorKind>` or `; !! <ErrorKind>: <message fragment>`
+    Error {
+        kind: String,
+        message: Option<String>,
+    },
+}
+
+impl Expected {
+    fn parse(annotation: &str) -> Option<Expected> {
+        if let Some(value) = annotation.strip_prefix(EXPECT_VALUE) {
+            return Some(Expected::Value(String::from(value.trim())));
+        }
+
+        let error = annotation.strip_prefix(EXPECT_ERROR)?.trim();
+        Some(match error.split_once(':') {
+            Some((kind, message)) => Expected::Error {
+                kind: String::from(kind.trim()),
+                message: Some(String::from(message.trim())),
+            },
+            None => Expected::Error {
+                kind: String::from(error),
+                message: None,
+            },
+        })
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Value(value) => write!(f, "{}", value),
+            Expected::Error {
+                kind,
+                message: Some(message),
+            } => write!(f, "{}: {}", kind, message),
+            Expected::Error {
+                kind,
+                message: None,
+            } => write!(f, "{}", kind),
+        }
+    }
+}
+
+/// A section of a script: source code followed by an optional annotation that applies to the
+/// last expression in the code
+struct Chunk {
+    code: String,
+    expected: Option<Expected>,
+    /// Line number of the annotation, or of the end of the script
+    line: usize,
+}
+
+/// Split a script into chunks at each annotation. Annotations may follow an expression on the
+/// same line or be on a line of their own.
+fn chunks(source: &str) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut code = String::new();
+    let mut line = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        line = index + 1;
+
+        let marker = [EXPECT_VALUE, EXPECT_ERROR]
+            .iter()
+            .filter_map(|marker| text.find(marker))
+            .min();
+
+        match marker {
+            Some(start) => {
+                code.push_str(&text[..start]);
+                code.push('\n');
+                chunks.push(Chunk {
+                    code: std::mem::take(&mut code),
+                    expected: Expected::parse(&text[start..]),
+                    line,
+                });
+            }
+            None => {
+                code.push_str(text);
+                code.push('\n');
+            }
+        }
+    }
+
+    if !code.trim().is_empty() {
+        chunks.push(Chunk {
+            code,
+            expected: None,
+            line,
+        });
+    }
+
+    chunks
+}
+
+/// A mismatch between an annotation and the actual result of evaluating a script
+#[derive(Debug, PartialEq)]
+pub struct Failure {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Failure {
+    fn new(line: usize, message: String) -> Failure {
+        Failure { line, message }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The name of an error kind as written in annotations, e.g. `EvalError`
+fn kind_name(kind: &ErrorKind) -> String {
+    let debug = format!("{:?}", kind);
+    match debug.find('(') {
+        Some(end) => String::from(&debug[..end]),
+        None => debug,
+    }
+}
+
+/// Compare the result of the last expression in a chunk with its annotation
+fn check(
+    expected: &Expected,
+    result: Result<String, RuntimeError>,
+    line: usize,
+) -> Option<Failure> {
+    let mismatch = |actual: String| {
+        Some(Failure::new(
+            line,
+            format!("expected {}, got {}", expected, actual),
+        ))
+    };
+
+    match (expected, result) {
+        (Expected::Value(value), Ok(actual)) if *value == actual => None,
+        (Expected::Error { kind, message }, Err(e))
+            if *kind == kind_name(e.error_kind())
+                && message
+                    .as_ref()
+                    .is_none_or(|message| format!("{}", e).contains(message.as_str())) =>
+        {
+            None
+        }
+        (_, Ok(actual)) => mismatch(actual),
+        (_, Err(e)) => mismatch(format!("{} ({})", kind_name(e.error_kind()), e)),
+    }
+}
+
+/// Mutator that evaluates a script on a fresh Thread, checking each annotation in turn
+struct RunScript {}
+
+impl Mutator for RunScript {
+    type Input = String;
+    type Output = Vec<Failure>;
+
+    fn run(&self, mem: &MutatorView, source: String) -> Result<Vec<Failure>, RuntimeError> {
+        let thread = Thread::alloc(mem)?;
+        let mut failures = Vec::new();
+
+        for chunk in chunks(&source) {
+            // evaluate every expression, returning the printed result of the last one
+            let result = (|| -> Result<Option<String>, RuntimeError> {
+                let mut last = None;
+                for expr in parse_all(mem, &chunk.code)? {
+                    let function = compile(mem, expr)?;
+                    last = Some(format!("{}", thread.quick_vm_eval(mem, function)?));
+                }
+                Ok(last)
+            })();
+
+            let failure = match (&chunk.expected, result) {
+                (Some(_), Ok(None)) => Some(Failure::new(
+                    chunk.line,
+                    String::from("annotation does not follow an expression"),
+                )),
+                (Some(expected), Ok(Some(actual))) => check(expected, Ok(actual), chunk.line),
+                (Some(expected), Err(e)) => check(expected, Err(e), chunk.line),
+                (None, Ok(_)) => None,
+                (None, Err(e)) => Some(Failure::new(chunk.line, format!("unexpected {}", e))),
+            };
+
+            failures.extend(failure);
+        }
+
+        Ok(failures)
+    }
+}
+
+/// Evaluate a script, returning every annotation that did not match
+pub fn run_script(source: &str) -> Result<Vec<Failure>, RuntimeError> {
+    let mem = Memory::new();
+    mem.mutate(&RunScript {}, String::from(source))
+}
+
+/// Evaluate a script file, returning every annotation that did not match
+pub fn run_file(path: &Path) -> Result<Vec<Failure>, RuntimeError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| RuntimeError::new(ErrorKind::IOError(format!("{}: {}", path.display(), e))))?;
+    run_script(&source)
+}
+
+/// Find every `.lisp` script in a directory, sorted by name
+pub fn discover(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut scripts = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "lisp") {
+            scripts.push(path);
+        }
+    }
+
+    scripts.sort();
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_annotations() {
+        assert_eq!(
+            Expected::parse("; => (a b)"),
+            Some(Expected::Value(String::from("(a b)")))
+        );
+        assert_eq!(
+            Expected::parse("; !! EvalError"),
+            Some(Expected::Error {
+                kind: String::from("EvalError"),
+                message: None
+            })
+        );
+        assert_eq!(
+            Expected::parse("; !! ParseError: Unmatched"),
+            Some(Expected::Error {
+                kind: String::from("ParseError"),
+                message: Some(String::from("Unmatched"))
+            })
+        );
+        assert_eq!(Expected::parse("; a comment"), None);
+    }
+
+    #[test]
+    fn passing_script() {
+        let script = "(def sq (x) (* x x))\n(sq 4) ; => 16\n\n(car (quote a))\n; !! EvalError\n";
+        assert_eq!(run_script(script).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn failing_script() {
+        let script = "(+ 1 2) ; => 4\n(+ 1 1) ; !! EvalError\n(car (quote a))\n";
+        let failures = run_script(script).unwrap();
+
+        assert_eq!(failures.len(), 3);
+        assert_eq!(
+            failures[0],
+            Failure::new(1, String::from("expected 4, got 3"))
+        );
+        assert_eq!(
+            failures[1],
+            Failure::new(2, String::from("expected EvalError, got 2"))
+        );
+        assert_eq!(failures[2].line, 3);
+    }
+}
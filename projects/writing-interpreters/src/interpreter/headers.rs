@@ -12,7 +12,7 @@ use super::{
     symbol::Symbol,
     taggedptr::FatPtr,
     text::Text,
-    vm::{CallFrameList, Thread, Upvalue},
+    vm::{CallFrameList, Coroutine, Thread, Upvalue},
     ArrayU16, ArrayU32, ArrayU8,
 };
 
@@ -29,6 +29,7 @@ pub enum TypeList {
     ArrayU32,
     ByteCode,
     CallFrameList,
+    Coroutine,
     Dict,
     Function,
     InstructionStream,
@@ -67,6 +68,9 @@ impl ObjectHeader {
             TypeList::ArrayU8 => FatPtr::ArrayU8(RawPtr::untag(object_addr.cast::<ArrayU8>())),
             TypeList::ArrayU16 => FatPtr::ArrayU16(RawPtr::untag(object_addr.cast::<ArrayU16>())),
             TypeList::ArrayU32 => FatPtr::ArrayU32(RawPtr::untag(object_addr.cast::<ArrayU32>())),
+            TypeList::Coroutine => {
+                FatPtr::Coroutine(RawPtr::untag(object_addr.cast::<Coroutine>()))
+            }
             TypeList::Dict => FatPtr::Dict(RawPtr::untag(object_addr.cast::<Dict>())),
             TypeList::Function => FatPtr::Function(RawPtr::untag(object_addr.cast::<Function>())),
             TypeList::List => FatPtr::List(RawPtr::untag(object_addr.cast::<List>())),
@@ -148,6 +152,7 @@ macro_rules! declare_allocobject {
 declare_allocobject!(ArrayU16, ArrayU16);
 declare_allocobject!(ByteCode, ByteCode);
 declare_allocobject!(CallFrameList, CallFrameList);
+declare_allocobject!(Coroutine, Coroutine);
 declare_allocobject!(Dict, Dict);
 declare_allocobject!(Function, Function);
 declare_allocobject!(InstructionStream, InstructionStream);
@@ -155,5 +160,6 @@ declare_allocobject!(List, List);
 declare_allocobject!(Pair, Pair);
 declare_allocobject!(Partial, Partial);
 declare_allocobject!(Symbol, Symbol);
+declare_allocobject!(Text, Text);
 declare_allocobject!(Thread, Thread);
 declare_allocobject!(Upvalue, Upvalue);
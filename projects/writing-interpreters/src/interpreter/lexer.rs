@@ -13,6 +13,7 @@ const LF: char = '\n';
 const DOT: char = '.';
 const DOUBLE_QUOTE: char = '"';
 const SINGLE_QUOTE: char = '\'';
+const SEMICOLON: char = ';';
 
 #[derive(Debug, PartialEq)]
 pub enum TokenType {
@@ -89,6 +90,16 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError> {
                 column = 0;
                 current = chars.next();
             }
+            Some(SEMICOLON) => {
+                // comments run to the end of the line
+                while let Some(c) = current {
+                    if c == CR || c == LF {
+                        break;
+                    }
+                    column += 1;
+                    current = chars.next();
+                }
+            }
             Some(c) => {
                 let symbol_start_column = column;
                 let mut symbol = String::new();
@@ -134,7 +145,16 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError> {
 }
 
 fn is_terminating(c: char) -> bool {
-    let terminating = [OPEN_PAREN, CLOSE_PAREN, SPACE, TAB, CR, LF, DOUBLE_QUOTE];
+    let terminating = [
+        OPEN_PAREN,
+        CLOSE_PAREN,
+        SPACE,
+        TAB,
+        CR,
+        LF,
+        DOUBLE_QUOTE,
+        SEMICOLON,
+    ];
     terminating.iter().any(|t| *t == c)
 }
 
@@ -196,4 +216,23 @@ mod test {
             assert!(false, "unexpected error");
         }
     }
+
+    #[test]
+    fn lexer_comments() {
+        if let Ok(tokens) = tokenize("; leading\n(foo ; trailing\nbar;adjacent\n)") {
+            assert!(tokens.len() == 4);
+            assert_eq!(tokens[0], Token::new(spos(2, 0), TokenType::OpenParen));
+            assert_eq!(
+                tokens[1],
+                Token::new(spos(2, 1), TokenType::Symbol(String::from("foo")))
+            );
+            assert_eq!(
+                tokens[2],
+                Token::new(spos(3, 0), TokenType::Symbol(String::from("bar")))
+            );
+            assert_eq!(tokens[3], Token::new(spos(4, 0), TokenType::CloseParen));
+        } else {
+            assert!(false, "unexpected error");
+        }
+    }
 }
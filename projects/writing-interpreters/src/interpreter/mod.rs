@@ -3,9 +3,12 @@ pub mod array;
 pub mod bytecode;
 pub mod compiler;
 pub mod containers;
+pub mod conversion;
 pub mod dict;
+pub mod embed;
 pub mod error;
 pub mod function;
+pub mod golden;
 pub mod hashable;
 pub mod headers;
 pub mod lexer;
@@ -26,6 +29,8 @@ pub mod text;
 pub mod vm;
 
 pub use array::{ArrayU16, ArrayU32, ArrayU8};
+pub use conversion::{FromValue, IntoArgs, IntoValue};
+pub use embed::Interpreter;
 pub use error::RuntimeError;
 pub use headers::TypeList;
 pub use memory::{Mutator, MutatorView};
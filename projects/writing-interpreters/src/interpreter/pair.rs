@@ -4,7 +4,7 @@ use crate::interpreter::{taggedptr::Value, ScopedPtr};
 
 use super::{
     error::{err_eval, SourcePos},
-    printer::Print,
+    printer::{print_with, Print, PrintOptions},
     safeptr::{MutatorScope, TaggedCellPtr, TaggedScopedPtr},
     MutatorView, RuntimeError,
 };
@@ -68,28 +68,9 @@ impl Pair {
 }
 
 impl Print for Pair {
-    fn print<'guard>(
-        &self,
-        guard: &'guard dyn MutatorScope,
-        f: &mut fmt::Formatter,
-    ) -> fmt::Result {
-        let mut tail = ScopedPtr::new(guard, self);
-
-        write!(f, "({}", tail.first.get(guard))?;
-
-        while let Value::Pair(next) = *tail.second.get(guard) {
-            tail = next;
-            write!(f, " {}", tail.first.get(guard))?;
-        }
-
-        // clunky way to print anything but nil
-        let second = *tail.second.get(guard);
-        match second {
-            Value::Nil => (),
-            _ => write!(f, " . {}", second)?,
-        }
-
-        write!(f, ")")
+    fn print(&self, guard: &dyn MutatorScope, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = Value::Pair(ScopedPtr::new(guard, self));
+        f.write_str(&print_with(guard, value, &PrintOptions::default()))
     }
 
     // In debug print, use dot notation
@@ -20,6 +20,22 @@ pub fn parse<'guard>(
     parse_tokens(mem, tokenize(input)?)
 }
 
+/// Parse the given string into a sequence of ASTs, one for each top level expression
+pub fn parse_all<'guard>(
+    mem: &'guard MutatorView,
+    input: &str,
+) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+    let tokens = tokenize(input)?;
+    let mut peekable = tokens.iter().peekable();
+
+    let mut exprs = Vec::new();
+    while peekable.peek().is_some() {
+        exprs.push(parse_sexpr(mem, &mut peekable)?);
+    }
+
+    Ok(exprs)
+}
+
 fn parse_tokens<'guard>(
     mem: &'guard MutatorView,
     tokens: Vec<Token>,
@@ -375,4 +391,26 @@ mod test {
         let expect = String::from("(+ 1 2)");
         check(&input, &expect);
     }
+
+    #[test]
+    fn parse_all_top_level_exprs() {
+        let mem = Memory::new();
+
+        struct Test {}
+
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(&self, mem: &MutatorView, _: Self::Input) -> Result<Self::Output, RuntimeError> {
+                let exprs = parse_all(mem, "(a b)\n c\n(d\n e)")?;
+                let printed: Vec<String> = exprs.iter().map(|expr| print(**expr)).collect();
+                assert_eq!(printed, vec!["(a b)", "c", "(d e)"]);
+
+                Ok(())
+            }
+        }
+
+        mem.mutate(&Test {}, ()).unwrap();
+    }
 }
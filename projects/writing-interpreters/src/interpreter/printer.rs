@@ -1,20 +1,15 @@
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
 use super::{safeptr::MutatorScope, taggedptr::Value};
 
 /// Trait for using a `Value` lifted pointer in the `Display` trait
 pub trait Print {
-    fn print<'guard>(
-        &self,
-        _guard: &'guard dyn MutatorScope,
-        f: &mut fmt::Formatter,
-    ) -> fmt::Result;
-
-    fn debug<'guard>(
-        &self,
-        _guard: &'guard dyn MutatorScope,
-        f: &mut fmt::Formatter,
-    ) -> fmt::Result {
+    fn print(&self, _guard: &dyn MutatorScope, f: &mut fmt::Formatter) -> fmt::Result;
+
+    fn debug(&self, _guard: &dyn MutatorScope, f: &mut fmt::Formatter) -> fmt::Result {
         self.print(_guard, f)
     }
 
@@ -34,3 +29,609 @@ pub fn print(value: Value) -> String {
 pub fn debug(value: Value) -> String {
     format!("{:?}", value)
 }
+
+/// Nesting depth beyond which compound values are always printed as `...`, so that printing
+/// deeply nested values can't exhaust the stack
+pub const MAX_PRINT_DEPTH: usize = 256;
+
+/// Limits and layout for printing compound values
+#[derive(Clone, Debug)]
+pub struct PrintOptions {
+    /// Nesting depth beyond which compound values are printed as `...`, which is never more
+    /// than `MAX_PRINT_DEPTH`
+    pub max_depth: Option<usize>,
+    /// Number of items printed from each list or dict before the rest are elided with `...`
+    pub max_length: Option<usize>,
+    /// Wrap lists that don't fit in this many columns, one item per line
+    pub width: Option<usize>,
+    /// Columns by which wrapped items are indented relative to their opening bracket
+    pub indent: usize,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            max_depth: None,
+            max_length: None,
+            width: None,
+            indent: 2,
+        }
+    }
+}
+
+impl PrintOptions {
+    /// Options for pretty printing wrapped to the given width
+    pub fn pretty(width: usize) -> PrintOptions {
+        PrintOptions {
+            width: Some(width),
+            ..PrintOptions::default()
+        }
+    }
+}
+
+/// Print a value according to the given options.
+///
+/// Pairs and Dicts that are reachable from themselves are labelled on first appearance with
+/// `#n=` and subsequently referred to as `#n#` so that circular structures print finitely.
+pub fn print_with<'guard>(
+    guard: &'guard dyn MutatorScope,
+    value: Value<'guard>,
+    options: &PrintOptions,
+) -> String {
+    let mut printer = Printer {
+        options,
+        cyclic: HashSet::new(),
+        labels: HashMap::new(),
+    };
+
+    printer.find_cycles(guard, value, 0, &mut HashSet::new(), &mut HashSet::new());
+    let doc = printer.build(guard, value, 0);
+
+    let mut output = String::new();
+    printer.layout(&doc, 0, 0, &mut output);
+    output
+}
+
+/// An intermediate rendering of a value: either a fully printed atom or a bracketed group of
+/// items that may be laid out flat or wrapped across lines
+enum Doc {
+    Atom(String),
+    Group {
+        open: String,
+        items: Vec<Doc>,
+        tail: Option<Box<Doc>>,
+        close: &'static str,
+        separator: &'static str,
+        /// Columns taken by the whole group laid out flat
+        width: usize,
+    },
+}
+
+impl Doc {
+    fn group(
+        open: String,
+        items: Vec<Doc>,
+        tail: Option<Box<Doc>>,
+        close: &'static str,
+        separator: &'static str,
+    ) -> Doc {
+        // the widths of the items were found as they were built, so this is linear overall
+        let mut width = open.chars().count() + close.len();
+        width += items.iter().map(Doc::width).sum::<usize>();
+        width += separator.len() * items.len().saturating_sub(1);
+        if let Some(tail) = &tail {
+            width += " . ".len() + tail.width();
+        }
+
+        Doc::Group {
+            open,
+            items,
+            tail,
+            close,
+            separator,
+            width,
+        }
+    }
+
+    /// Columns taken by the document laid out flat
+    fn width(&self) -> usize {
+        match self {
+            Doc::Atom(text) => text.chars().count(),
+            Doc::Group { width, .. } => *width,
+        }
+    }
+
+    /// Render the whole document on a single line
+    fn flat(&self, output: &mut String) {
+        match self {
+            Doc::Atom(text) => output.push_str(text),
+            Doc::Group {
+                open,
+                items,
+                tail,
+                close,
+                separator,
+                ..
+            } => {
+                output.push_str(open);
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        output.push_str(separator);
+                    }
+                    item.flat(output);
+                }
+                if let Some(tail) = tail {
+                    output.push_str(" . ");
+                    tail.flat(output);
+                }
+                output.push_str(close);
+            }
+        }
+    }
+}
+
+struct Printer<'options> {
+    options: &'options PrintOptions,
+    /// Objects that can be reached from themselves
+    cyclic: HashSet<usize>,
+    /// Labels assigned, in print order, to cyclic objects that have been printed
+    labels: HashMap<usize, usize>,
+}
+
+/// The address of a heap object, used to identify it when searching for cycles
+fn address<T>(object: &T) -> usize {
+    object as *const T as usize
+}
+
+impl Printer<'_> {
+    /// Depth first search marking every object that is found again while still on the current
+    /// path. List spines are walked iteratively so long lists don't exhaust the stack, and
+    /// nothing deeper than is printed is searched.
+    fn find_cycles<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        value: Value<'guard>,
+        depth: usize,
+        path: &mut HashSet<usize>,
+        done: &mut HashSet<usize>,
+    ) {
+        match value {
+            Value::Pair(_) | Value::Dict(_) if self.too_deep(depth) => (),
+
+            Value::Pair(pair) => {
+                let mut spine = Vec::new();
+                let mut next = Some(pair);
+
+                while let Some(pair) = next {
+                    let addr = address(&*pair);
+                    if path.contains(&addr) {
+                        self.cyclic.insert(addr);
+                        break;
+                    }
+                    if done.contains(&addr) {
+                        break;
+                    }
+
+                    path.insert(addr);
+                    spine.push(addr);
+
+                    self.find_cycles(guard, *pair.first.get(guard), depth + 1, path, done);
+
+                    next = match *pair.second.get(guard) {
+                        Value::Pair(next) => Some(next),
+                        other => {
+                            self.find_cycles(guard, other, depth, path, done);
+                            None
+                        }
+                    };
+                }
+
+                for addr in spine {
+                    path.remove(&addr);
+                    done.insert(addr);
+                }
+            }
+
+            Value::Dict(dict) => {
+                let addr = address(&*dict);
+                if path.contains(&addr) {
+                    self.cyclic.insert(addr);
+                    return;
+                }
+                if done.contains(&addr) {
+                    return;
+                }
+
+                path.insert(addr);
+                for (key, value) in dict.entries(guard) {
+                    self.find_cycles(guard, *key, depth + 1, path, done);
+                    self.find_cycles(guard, *value, depth + 1, path, done);
+                }
+                path.remove(&addr);
+                done.insert(addr);
+            }
+
+            _ => (),
+        }
+    }
+
+    /// If the object is part of a cycle, return its reference if it has already been printed.
+    /// Otherwise return the label definition prefix, which is empty for acyclic objects.
+    fn label(&mut self, addr: usize) -> Result<String, Doc> {
+        if let Some(label) = self.labels.get(&addr) {
+            return Err(Doc::Atom(format!("#{}#", label)));
+        }
+
+        if self.cyclic.contains(&addr) {
+            let label = self.labels.len();
+            self.labels.insert(addr, label);
+            Ok(format!("#{}=", label))
+        } else {
+            Ok(String::new())
+        }
+    }
+
+    fn too_deep(&self, depth: usize) -> bool {
+        depth >= MAX_PRINT_DEPTH || self.options.max_depth.is_some_and(|max| depth >= max)
+    }
+
+    fn too_long(&self, length: usize) -> bool {
+        self.options.max_length.is_some_and(|max| length >= max)
+    }
+
+    fn build<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        value: Value<'guard>,
+        depth: usize,
+    ) -> Doc {
+        match value {
+            Value::Pair(_) | Value::Dict(_) if self.too_deep(depth) => {
+                Doc::Atom(String::from("..."))
+            }
+
+            Value::Pair(mut pair) => {
+                let open = match self.label(address(&*pair)) {
+                    Ok(label) => label + "(",
+                    Err(reference) => return reference,
+                };
+
+                let mut items = Vec::new();
+                let mut tail = None;
+
+                loop {
+                    items.push(self.build(guard, *pair.first.get(guard), depth + 1));
+
+                    match *pair.second.get(guard) {
+                        Value::Nil => break,
+                        // a cyclic pair in the spine must be printed as a dotted tail so that
+                        // it can carry a label
+                        Value::Pair(next) if !self.cyclic.contains(&address(&*next)) => {
+                            if self.too_long(items.len()) {
+                                items.push(Doc::Atom(String::from("...")));
+                                break;
+                            }
+                            pair = next;
+                        }
+                        // and it nests in the output like any other, so it goes a level deeper
+                        other => {
+                            tail = Some(Box::new(self.build(guard, other, depth + 1)));
+                            break;
+                        }
+                    }
+                }
+
+                Doc::group(open, items, tail, ")", " ")
+            }
+
+            Value::Dict(dict) => {
+                let open = match self.label(address(&*dict)) {
+                    Ok(label) => label + "{",
+                    Err(reference) => return reference,
+                };
+
+                let mut items = Vec::new();
+                for (key, value) in dict.entries(guard) {
+                    if self.too_long(items.len()) {
+                        items.push(Doc::Atom(String::from("...")));
+                        break;
+                    }
+
+                    let entry = vec![
+                        self.build(guard, *key, depth + 1),
+                        self.build(guard, *value, depth + 1),
+                    ];
+                    items.push(Doc::group(String::new(), entry, None, "", " "));
+                }
+
+                Doc::group(open, items, None, "}", ", ")
+            }
+
+            _ => Doc::Atom(format!("{}", value)),
+        }
+    }
+
+    /// Render a document starting at the given column, wrapping groups that would overflow the
+    /// configured width so that each item is on its own line. `trailing` columns must follow the
+    /// document on its last line, such as the brackets closing the groups it ends.
+    fn layout(&self, doc: &Doc, column: usize, trailing: usize, output: &mut String) {
+        let fits = match self.options.width {
+            Some(width) => column + doc.width() + trailing <= width,
+            None => true,
+        };
+
+        match doc {
+            Doc::Group {
+                open,
+                items,
+                tail,
+                close,
+                separator,
+                ..
+            } if !fits && !items.is_empty() => {
+                let indent = column + self.options.indent;
+                let separator = separator.trim_end();
+                let closing = close.len() + trailing;
+                // each item is followed on its line by a separator, except the last, which is
+                // followed by the close or by nothing if a dotted tail comes after it
+                let after = |index: usize| match (index + 1 < items.len(), tail) {
+                    (true, _) => separator.len(),
+                    (false, Some(_)) => 0,
+                    (false, None) => closing,
+                };
+
+                output.push_str(open);
+                self.layout(&items[0], column + open.chars().count(), after(0), output);
+
+                for (index, item) in items.iter().enumerate().skip(1) {
+                    output.push_str(separator);
+                    newline(output, indent);
+                    self.layout(item, indent, after(index), output);
+                }
+
+                if let Some(tail) = tail {
+                    newline(output, indent);
+                    output.push_str(". ");
+                    self.layout(tail, indent + 2, closing, output);
+                }
+
+                output.push_str(close);
+            }
+
+            _ => doc.flat(output),
+        }
+    }
+}
+
+fn newline(output: &mut String, indent: usize) {
+    output.push('\n');
+    output.extend(std::iter::repeat_n(' ', indent));
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{
+        containers::HashIndexedAnyContainer,
+        dict::Dict,
+        memory::{Memory, Mutator, MutatorView},
+        pair::Pair,
+        parser::parse,
+        safeptr::TaggedScopedPtr,
+        RuntimeError,
+    };
+    use super::*;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    /// Point the tail of the last pair in a list at the given value
+    fn set_last_tail<'guard>(
+        mem: &'guard MutatorView,
+        list: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) {
+        let mut tail = list;
+        while let Value::Pair(pair) = *tail {
+            match *pair.second.get(mem) {
+                Value::Pair(_) => tail = pair.second.get(mem),
+                _ => {
+                    pair.second.set(value);
+                    return;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn print_circular_list() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let list = parse(mem, "(a b c)")?;
+            set_last_tail(mem, list, list);
+            assert_eq!(format!("{}", list), "#0=(a b c . #0#)");
+
+            // the cycle only includes the tail of the outer list
+            let outer = parse(mem, "(x y)")?;
+            let inner = parse(mem, "(z)")?;
+            set_last_tail(mem, outer, inner);
+            set_last_tail(mem, inner, inner);
+            assert_eq!(format!("{}", outer), "(x y . #0=(z . #0#))");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn print_list_containing_itself() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let list = parse(mem, "(a b)")?;
+            if let Value::Pair(pair) = *list {
+                pair.first.set(list);
+            }
+            assert_eq!(format!("{}", list), "#0=(#0# b)");
+
+            // shared but acyclic structure is printed in full each time
+            let shared = parse(mem, "(s)")?;
+            let outer = parse(mem, "(a b)")?;
+            if let Value::Pair(pair) = *outer {
+                pair.first.set(shared);
+                if let Value::Pair(second) = *pair.second.get(mem) {
+                    second.first.set(shared);
+                }
+            }
+            assert_eq!(format!("{}", outer), "((s) (s))");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn print_dict_containing_itself() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let dict = Dict::alloc(mem)?;
+            dict.assoc(mem, mem.lookup_sym("self"), dict.as_tagged(mem))?;
+            assert_eq!(format!("{}", dict.as_tagged(mem)), "#0={self #0#}");
+
+            let empty = Dict::alloc(mem)?;
+            assert_eq!(format!("{}", empty.as_tagged(mem)), "{}");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn print_with_limits() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let list = parse(mem, "(a (b (c (d))) e f g)")?;
+
+            let options = PrintOptions {
+                max_length: Some(3),
+                ..PrintOptions::default()
+            };
+            assert_eq!(print_with(mem, *list, &options), "(a (b (c (d))) e ...)");
+
+            let options = PrintOptions {
+                max_depth: Some(2),
+                ..PrintOptions::default()
+            };
+            assert_eq!(print_with(mem, *list, &options), "(a (b ...) e f g)");
+
+            let circular = parse(mem, "(1 2)")?;
+            set_last_tail(mem, circular, circular);
+            let options = PrintOptions {
+                max_length: Some(1),
+                ..PrintOptions::default()
+            };
+            assert_eq!(print_with(mem, *circular, &options), "#0=(1 ...)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn pretty_print_wraps_to_width() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let list = parse(
+                mem,
+                "(def fact (n) (cond (is? n 0) 1 true (* n (fact (+ n -1)))))",
+            )?;
+
+            assert_eq!(
+                print_with(mem, *list, &PrintOptions::pretty(80)),
+                "(def fact (n) (cond (is? n 0) 1 true (* n (fact (+ n -1)))))"
+            );
+
+            assert_eq!(
+                print_with(mem, *list, &PrintOptions::pretty(40)),
+                "(def\n  fact\n  (n)\n  (cond\n    (is? n 0)\n    1\n    true\n    (* n (fact (+ n -1)))))"
+            );
+
+            // the inner list fits in 13 columns, but not with the bracket closing the outer one
+            let list = parse(mem, "(aaaa (bbbb cccc))")?;
+            assert_eq!(
+                print_with(mem, *list, &PrintOptions::pretty(13)),
+                "(aaaa\n  (bbbb\n    cccc))"
+            );
+
+            let list = parse(mem, "(aa (bb cc dd) (ee (ff gg)) hh)")?;
+            for line in print_with(mem, *list, &PrintOptions::pretty(12)).lines() {
+                assert!(line.len() <= 12, "{:?} is too wide", line);
+            }
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn print_deeply_nested_list() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let mut list = mem.nil();
+            for _ in 0..100_000 {
+                list = Pair::cons(mem, list, mem.nil())?;
+            }
+
+            let printed = format!("{}", list);
+            assert!(printed.starts_with(&"(".repeat(MAX_PRINT_DEPTH)));
+            assert!(printed.contains("..."));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn print_long_list_linked_both_ways() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let mut list = mem.nil();
+            for _ in 0..100_000 {
+                list = Pair::cons(mem, mem.nil(), list)?;
+            }
+            // each pair's head points back at the pair before it, so every pair is cyclic and
+            // printed as a labelled dotted tail
+            let mut previous = list;
+            while let Value::Pair(pair) = *previous {
+                let next = pair.second.get(mem);
+                if let Value::Pair(next_pair) = *next {
+                    next_pair.first.set(previous);
+                }
+                previous = next;
+            }
+
+            let printed = format!("{}", list);
+            assert!(printed.starts_with("#0=(nil . #1=(#0# . #2=(#1# . "));
+            assert!(printed.contains("..."));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
@@ -1,8 +1,32 @@
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    fs,
+    rc::Rc,
+};
+
+use rustyline::{
+    completion::Completer,
+    highlight::{Highlighter, MatchingBracketHighlighter},
+    hint::Hinter,
+    validate::Validator,
+    Context, Helper,
+};
+
 use super::{
-    compiler::compile, error::ErrorKind, parser::parse, safeptr::TaggedScopedPtr, vm::Thread,
+    compiler::{compile, SPECIAL_FORMS},
+    error::ErrorKind,
+    lexer::{tokenize, TokenType},
+    parser::{parse, parse_all},
+    printer::{print_with, PrintOptions},
+    safeptr::TaggedScopedPtr,
+    vm::Thread,
     CellPtr, Mutator, MutatorView, RuntimeError,
 };
 
+/// Width used by `:pretty` when none is given
+const DEFAULT_PRETTY_WIDTH: usize = 80;
+
 /// A mutator that returns a Repl instance
 pub struct RepMaker {}
 
@@ -18,14 +42,81 @@ impl Mutator for RepMaker {
 /// Mutator that implements the VM
 pub struct ReadEvalPrint {
     main_thread: CellPtr<Thread>,
+    /// Width that results are pretty printed to, if pretty printing is on
+    pretty_width: Cell<Option<usize>>,
 }
 
 impl ReadEvalPrint {
     pub fn alloc(mem: &MutatorView) -> Result<ReadEvalPrint, RuntimeError> {
         Ok(ReadEvalPrint {
             main_thread: CellPtr::new_with(Thread::alloc(mem)?),
+            pretty_width: Cell::new(None),
         })
     }
+
+    /// Return a mutator that lists the names bound in the main thread's globals
+    pub fn global_names(&self) -> GlobalNames<'_> {
+        GlobalNames { rep: self }
+    }
+
+    /// Parse, compile and evaluate a single expression, optionally printing each stage
+    fn eval<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        line: &str,
+        debug: bool,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let thread = self.main_thread.get(mem);
+        let value = parse(mem, line)?;
+
+        if debug {
+            println!(
+                "# Debug\n## Input:\n```\n{}\n```\n## Parsed:\n```\n{:?}\n```",
+                line, value
+            );
+        }
+
+        let function = compile(mem, value)?;
+
+        if debug {
+            println!("## Compiled:\n```\n{:?}\n```", function);
+        }
+
+        let value = thread.quick_vm_eval(mem, function)?;
+
+        if debug {
+            println!("## Evaluated:\n```\n{:?}\n```\n", value);
+        }
+
+        Ok(value)
+    }
+
+    /// Evaluate every top level expression in a source file, stopping at the first error
+    fn load(&self, mem: &MutatorView, path: &str) -> Result<(), RuntimeError> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                println!("error: could not read {}: {}", path, e);
+                return Ok(());
+            }
+        };
+
+        let thread = self.main_thread.get(mem);
+
+        match (|| -> Result<usize, RuntimeError> {
+            let exprs = parse_all(mem, &source)?;
+            for expr in &exprs {
+                let function = compile(mem, *expr)?;
+                thread.quick_vm_eval(mem, function)?;
+            }
+            Ok(exprs.len())
+        })() {
+            Ok(count) => println!("Loaded {} expressions from {}", count, path),
+            Err(e) => print_repl_error(e, &source)?,
+        }
+
+        Ok(())
+    }
 }
 
 impl Mutator for ReadEvalPrint {
@@ -33,7 +124,45 @@ impl Mutator for ReadEvalPrint {
     type Output = ();
 
     fn run(&self, mem: &MutatorView, line: String) -> Result<(), RuntimeError> {
-        let thread = self.main_thread.get(mem);
+        // Meta commands operate on the repl itself rather than being evaluated
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(":load"), Some(path)) => return self.load(mem, path),
+            (Some(":load"), None) => {
+                println!("usage: :load <file>");
+                return Ok(());
+            }
+            (Some(":globals"), _) => {
+                for name in self.main_thread.get(mem).global_names(mem) {
+                    println!("{}", name);
+                }
+                return Ok(());
+            }
+            (Some(":pretty"), arg) => {
+                match arg {
+                    None => self.pretty_width.set(Some(DEFAULT_PRETTY_WIDTH)),
+                    Some("off") => self.pretty_width.set(None),
+                    Some(width) => match width.parse() {
+                        Ok(width) if width > 0 => self.pretty_width.set(Some(width)),
+                        _ => {
+                            println!("usage: :pretty [<width>|off]");
+                            return Ok(());
+                        }
+                    },
+                }
+                match self.pretty_width.get() {
+                    Some(width) => println!("Pretty printing to {} columns", width),
+                    None => println!("Pretty printing is off"),
+                }
+                return Ok(());
+            }
+            (Some(":reset"), _) => {
+                self.main_thread.set(Thread::alloc(mem)?);
+                println!("Cleared all global bindings");
+                return Ok(());
+            }
+            _ => (),
+        }
 
         // If the first 2 chars of the line are ":d", then the user has requested a debug
         // representation
@@ -43,44 +172,176 @@ impl Mutator for ReadEvalPrint {
             (line.as_str(), false)
         };
 
-        match (|mem, line| -> Result<TaggedScopedPtr, RuntimeError> {
-            let value = parse(mem, line)?;
+        match self.eval(mem, line, debug) {
+            Ok(value) => match self.pretty_width.get() {
+                Some(width) => {
+                    println!("{}", print_with(mem, *value, &PrintOptions::pretty(width)))
+                }
+                None => println!("{}", value),
+            },
+            Err(e) => print_repl_error(e, line)?,
+        }
 
-            if debug {
-                println!(
-                    "# Debug\n## Input:\n```\n{}\n```\n## Parsed:\n```\n{:?}\n```",
-                    line, value
-                );
-            }
+        Ok(())
+    }
+}
 
-            let function = compile(mem, value)?;
+/// Print non-fatal repl errors in the context of their source, returning any fatal error
+fn print_repl_error(e: RuntimeError, source: &str) -> Result<(), RuntimeError> {
+    match e.error_kind() {
+        ErrorKind::LexerError(_) => e.print_with_source(source),
+        ErrorKind::ParseError(_) => e.print_with_source(source),
+        ErrorKind::EvalError(_) => e.print_with_source(source),
+        _ => return Err(e),
+    }
 
-            if debug {
-                println!("## Compiled:\n```\n{:?}\n```", function);
-            }
+    Ok(())
+}
 
-            let value = thread.quick_vm_eval(mem, function)?;
+/// Mutator that returns the names bound in the main thread's globals
+pub struct GlobalNames<'rep> {
+    rep: &'rep ReadEvalPrint,
+}
 
-            if debug {
-                println!("## Evaluated:\n```\n{:?}\n```\n", value);
-            }
+impl<'rep> Mutator for GlobalNames<'rep> {
+    type Input = ();
+    type Output = Vec<String>;
 
-            Ok(value)
-        })(mem, &line)
-        {
-            Ok(value) => println!("{}", value),
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<Vec<String>, RuntimeError> {
+        Ok(self.rep.main_thread.get(mem).global_names(mem))
+    }
+}
 
-            Err(e) => {
-                match e.error_kind() {
-                    // non-fatal repl errors
-                    ErrorKind::LexerError(_) => e.print_with_source(&line),
-                    ErrorKind::ParseError(_) => e.print_with_source(&line),
-                    ErrorKind::EvalError(_) => e.print_with_source(&line),
-                    _ => return Err(e),
-                }
-            }
+/// Return true if the input has unclosed parentheses and more lines should be read before
+/// evaluating it. Parentheses are counted from the lexer's tokens, so those in comments don't
+/// count. Input that doesn't lex is complete, for evaluation to report.
+pub fn is_incomplete(input: &str) -> bool {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+
+    let mut depth = 0;
+    for token in tokens {
+        match token.token {
+            TokenType::OpenParen => depth += 1,
+            TokenType::CloseParen => depth -= 1,
+            _ => (),
         }
+    }
+    depth > 0
+}
 
-        Ok(())
+/// rustyline helper providing completion of global names and special forms, and highlighting
+/// of matching parentheses
+pub struct ReplHelper {
+    /// Global names, refreshed by the repl loop after each evaluation
+    globals: Rc<RefCell<Vec<String>>>,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl ReplHelper {
+    pub fn new(globals: Rc<RefCell<Vec<String>>>) -> ReplHelper {
+        ReplHelper {
+            globals,
+            brackets: MatchingBracketHighlighter::new(),
+        }
+    }
+
+    /// Return every known name that starts with the given prefix, sorted and deduplicated
+    fn candidates(&self, prefix: &str) -> Vec<String> {
+        let globals = self.globals.borrow();
+        let mut candidates: Vec<String> = SPECIAL_FORMS
+            .iter()
+            .copied()
+            .chain(globals.iter().map(|name| name.as_str()))
+            .filter(|name| name.starts_with(prefix))
+            .map(String::from)
+            .collect();
+
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // the word being completed begins after the last delimiter before the cursor
+        let start = line[..pos]
+            .rfind(|c: char| c == '(' || c == ')' || c.is_whitespace())
+            .map_or(0, |index| index + 1);
+
+        Ok((start, self.candidates(&line[start..pos])))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize) -> bool {
+        self.brackets.highlight_char(line, pos)
+    }
+}
+
+impl Hinter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::super::memory::Memory;
+    use super::*;
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("(def f (x)"));
+        assert!(is_incomplete("(def f (x)\n  (+ x"));
+        assert!(!is_incomplete("(def f (x)\n  (+ x 1))"));
+        assert!(!is_incomplete("a"));
+        assert!(!is_incomplete(")"));
+
+        // parentheses in comments don't count
+        assert!(!is_incomplete("(f x) ; ("));
+        assert!(is_incomplete("(f x\n; )"));
+    }
+
+    #[test]
+    fn pretty_command() {
+        let mem = Memory::new();
+        let rep = mem.mutate(&RepMaker {}, ()).unwrap();
+        assert_eq!(rep.pretty_width.get(), None);
+
+        mem.mutate(&rep, String::from(":pretty")).unwrap();
+        assert_eq!(rep.pretty_width.get(), Some(DEFAULT_PRETTY_WIDTH));
+        mem.mutate(&rep, String::from(":pretty 40")).unwrap();
+        assert_eq!(rep.pretty_width.get(), Some(40));
+        mem.mutate(&rep, String::from(":pretty wide")).unwrap();
+        assert_eq!(rep.pretty_width.get(), Some(40));
+        mem.mutate(&rep, String::from(":pretty off")).unwrap();
+        assert_eq!(rep.pretty_width.get(), None);
+    }
+
+    #[test]
+    fn complete_special_forms_and_globals() {
+        let globals = Rc::new(RefCell::new(vec![String::from("fact")]));
+        let helper = ReplHelper::new(globals.clone());
+
+        assert_eq!(helper.candidates("con"), vec!["cond", "cons"]);
+        assert_eq!(helper.candidates("fa"), vec!["fact"]);
+
+        globals.borrow_mut().push(String::from("car"));
+        assert_eq!(helper.candidates("ca"), vec!["car"]);
     }
 }
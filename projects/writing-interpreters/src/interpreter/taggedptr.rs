@@ -14,7 +14,7 @@ use super::{
     safeptr::MutatorScope,
     symbol::Symbol,
     text::Text,
-    vm::Upvalue,
+    vm::{Coroutine, Upvalue},
     ArrayU16, ArrayU32, ArrayU8, ScopedPtr,
 };
 
@@ -26,6 +26,7 @@ pub enum Value<'guard> {
     ArrayU8(ScopedPtr<'guard, ArrayU8>),
     ArrayU16(ScopedPtr<'guard, ArrayU16>),
     ArrayU32(ScopedPtr<'guard, ArrayU32>),
+    Coroutine(ScopedPtr<'guard, Coroutine>),
     Dict(ScopedPtr<'guard, Dict>),
     Function(ScopedPtr<'guard, Function>),
     List(ScopedPtr<'guard, List>),
@@ -54,6 +55,7 @@ impl<'guard> fmt::Display for Value<'guard> {
             Value::ArrayU8(a) => a.print(self, f),
             Value::ArrayU16(a) => a.print(self, f),
             Value::ArrayU32(a) => a.print(self, f),
+            Value::Coroutine(c) => c.print(self, f),
             Value::Dict(d) => d.print(self, f),
             Value::Function(n) => n.print(self, f),
             Value::Partial(p) => p.print(self, f),
@@ -69,6 +71,7 @@ impl<'guard> fmt::Debug for Value<'guard> {
             Value::ArrayU8(a) => a.debug(self, f),
             Value::ArrayU16(a) => a.debug(self, f),
             Value::ArrayU32(a) => a.debug(self, f),
+            Value::Coroutine(c) => c.debug(self, f),
             Value::Dict(d) => d.debug(self, f),
             Value::Function(n) => n.debug(self, f),
             Value::List(a) => a.debug(self, f),
@@ -91,6 +94,7 @@ pub enum FatPtr {
     ArrayU8(RawPtr<ArrayU8>),
     ArrayU16(RawPtr<ArrayU16>),
     ArrayU32(RawPtr<ArrayU32>),
+    Coroutine(RawPtr<Coroutine>),
     Dict(RawPtr<Dict>),
     Function(RawPtr<Function>),
     List(RawPtr<List>),
@@ -117,6 +121,9 @@ impl FatPtr {
             FatPtr::ArrayU32(raw_ptr) => {
                 Value::ArrayU32(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::Coroutine(raw_ptr) => {
+                Value::Coroutine(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
             FatPtr::Dict(raw_ptr) => Value::Dict(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
             FatPtr::Function(raw_ptr) => {
                 Value::Function(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
@@ -155,6 +162,7 @@ macro_rules! fatptr_from_rawptr {
 fatptr_from_rawptr!(ArrayU8, ArrayU8);
 fatptr_from_rawptr!(ArrayU16, ArrayU16);
 fatptr_from_rawptr!(ArrayU32, ArrayU32);
+fatptr_from_rawptr!(Coroutine, Coroutine);
 fatptr_from_rawptr!(Dict, Dict);
 fatptr_from_rawptr!(Function, Function);
 fatptr_from_rawptr!(List, List);
@@ -244,6 +252,7 @@ impl From<FatPtr> for TaggedPtr {
             FatPtr::ArrayU8(raw) => TaggedPtr::object(raw),
             FatPtr::ArrayU16(raw) => TaggedPtr::object(raw),
             FatPtr::ArrayU32(raw) => TaggedPtr::object(raw),
+            FatPtr::Coroutine(raw) => TaggedPtr::object(raw),
             FatPtr::Dict(raw) => TaggedPtr::object(raw),
             FatPtr::Function(raw) => TaggedPtr::object(raw),
             FatPtr::List(raw) => TaggedPtr::object(raw),
@@ -1,10 +1,59 @@
-use std::fmt;
+use std::{fmt, hash::Hash, hash::Hasher, str};
 
-use super::{printer::Print, safeptr::MutatorScope};
+use crate::memory::ArraySize;
+
+use super::{
+    array::ArrayU8,
+    containers::{Container, StackContainer},
+    hashable::Hashable,
+    printer::Print,
+    safeptr::MutatorScope,
+    MutatorView, RuntimeError,
+};
 
 /// While Text is somewhat similar to Symbol, it is instead garbage-collected heap allocated and not interned.
-#[derive(Copy, Clone)]
-pub struct Text {}
+#[derive(Clone)]
+pub struct Text {
+    content: ArrayU8,
+}
+
+impl Text {
+    /// Create an empty Text string object
+    pub fn new_empty() -> Text {
+        Text {
+            content: ArrayU8::new(),
+        }
+    }
+
+    /// Initialize a Text object from a &str slice
+    pub fn new_from_str(mem: &MutatorView, from_str: &str) -> Result<Text, RuntimeError> {
+        if from_str.is_empty() {
+            return Ok(Text::new_empty());
+        }
+
+        let content = ArrayU8::with_capacity(mem, from_str.len() as ArraySize)?;
+        for byte in from_str.bytes() {
+            content.push(mem, byte)?;
+        }
+
+        Ok(Text { content })
+    }
+
+    /// Return the underlying string slice. The content is only ever written from a valid &str
+    /// and is never modified afterwards.
+    pub fn as_str(&self, guard: &dyn MutatorScope) -> &str {
+        unsafe {
+            let slice = self.content.as_slice(guard);
+            str::from_utf8_unchecked(slice)
+        }
+    }
+}
+
+impl Hashable for Text {
+    fn hash<H: Hasher>(&self, guard: &dyn MutatorScope, h: &mut H) {
+        self.as_str(guard).hash(h)
+    }
+}
 
 impl Print for Text {
     fn print<'guard>(
@@ -12,6 +61,6 @@ impl Print for Text {
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        write!(f, "\"(unimplemented)\"")
+        write!(f, "\"{}\"", self.as_str(guard))
     }
 }
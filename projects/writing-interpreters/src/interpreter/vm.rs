@@ -1,4 +1,4 @@
-use std::cell::Cell;
+use std::{cell::Cell, fmt};
 
 use crate::memory::ArraySize;
 
@@ -14,6 +14,7 @@ use super::{
     function::{Function, Partial},
     list::List,
     pair::Pair,
+    printer::Print,
     safeptr::{MutatorScope, TaggedCellPtr, TaggedScopedPtr},
     taggedptr::{TaggedPtr, Value},
     CellPtr, MutatorView, RuntimeError, ScopedPtr,
@@ -65,6 +66,12 @@ pub enum EvalStatus<'guard> {
     Pending,
     /// Eval is complete, here is the resulting value
     Return(TaggedScopedPtr<'guard>),
+    /// Eval is suspended by a coroutine yield. The value passed to the next resume must be
+    /// written to the absolute stack index `resume_location`.
+    Yield {
+        value: TaggedScopedPtr<'guard>,
+        resume_location: ArraySize,
+    },
 }
 
 /// A closure upvalue as generally described by Lua 5.1 implementation.
@@ -142,6 +149,17 @@ impl Thread {
     /// bytecode yet.
     pub fn alloc<'guard>(
         mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Thread>, RuntimeError> {
+        // create an empty globals dict
+        let globals = Dict::alloc(mem)?;
+
+        Thread::alloc_with_globals(mem, globals)
+    }
+
+    /// Allocate a new Thread that shares the given globals dict with other Threads
+    fn alloc_with_globals<'guard>(
+        mem: &'guard MutatorView,
+        globals: ScopedPtr<'guard, Dict>,
     ) -> Result<ScopedPtr<'guard, Thread>, RuntimeError> {
         // create an empty stack frame array
         let frames = CallFrameList::alloc_with_capacity(mem, 16)?;
@@ -153,9 +171,6 @@ impl Thread {
         // create an empty upvalue stack->heap mapping
         let upvalues = Dict::alloc(mem)?;
 
-        // create an empty globals dict
-        let globals = Dict::alloc(mem)?;
-
         // create an empty instruction stream
         let blank_code = ByteCode::alloc(mem)?;
         let instr = InstructionStream::alloc(mem, blank_code)?;
@@ -170,6 +185,23 @@ impl Thread {
         })
     }
 
+    /// Return the names of all symbols bound in the globals dict, sorted
+    pub fn global_names(&self, guard: &dyn MutatorScope) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .globals
+            .get(guard)
+            .entries(guard)
+            .into_iter()
+            .filter_map(|(key, _)| match *key {
+                Value::Symbol(s) => Some(String::from(s.as_str(guard))),
+                _ => None,
+            })
+            .collect();
+
+        names.sort();
+        names
+    }
+
     /// Evaluate a Function completely, returning the result. The Function passed in should expect
     /// no arguments.
     pub fn quick_vm_eval<'guard>(
@@ -177,42 +209,98 @@ impl Thread {
         mem: &'guard MutatorView,
         function: ScopedPtr<'guard, Function>,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
-        let mut status = EvalStatus::Pending;
-
         let frames = self.frames.get(mem);
         frames.push(mem, CallFrame::new_main(function))?;
 
-        let code = function.code(mem);
+        self.instr.get(mem).switch_frame(function.code(mem), 0);
+
+        match self.run(mem)? {
+            EvalStatus::Return(value) => Ok(value),
+            _ => {
+                // Only a coroutine can be suspended, unwind the main thread
+                self.frames.get(mem).clear(mem)?;
+                self.stack_base.set(0);
+                Err(err_eval("Cannot yield outside of a coroutine"))
+            }
+        }
+    }
+
+    /// Set up the outermost call frame for a Function or Partial that takes at most one more
+    /// argument, placing `arg` in the argument register if one is expected
+    fn enter<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        callable: TaggedScopedPtr<'guard>,
+        arg: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        let stack = self.stack.get(mem);
+
+        let (function, arity, arg_reg) = match *callable {
+            Value::Function(function) => (function, function.arity(), FIRST_ARG_REG),
+            Value::Partial(partial) => {
+                // copy the closure environment and the partially applied args to the registers
+                IndexedAnyContainer::set(
+                    &*stack,
+                    mem,
+                    ENV_REG as ArraySize,
+                    partial.closure_env().get(mem),
+                )?;
+
+                let args = partial.args(mem);
+                for index in 0..args.length() {
+                    let arg = IndexedAnyContainer::get(&*args, mem, index)?;
+                    IndexedAnyContainer::set(
+                        &*stack,
+                        mem,
+                        FIRST_ARG_REG as ArraySize + index,
+                        arg,
+                    )?;
+                }
 
-        while status == EvalStatus::Pending {
-            status = self.vm_eval_stream(mem, code, 1024)?;
-            match status {
-                EvalStatus::Return(value) => return Ok(value),
-                _ => (),
+                (
+                    partial.function(mem),
+                    partial.arity(),
+                    FIRST_ARG_REG + partial.used() as usize,
+                )
             }
+            _ => return Err(err_eval("Type is not callable")),
+        };
+
+        if arity == 1 {
+            IndexedAnyContainer::set(&*stack, mem, arg_reg as ArraySize, arg)?;
         }
 
-        Err(err_eval("Unexpected end of evaluation"))
+        self.frames
+            .get(mem)
+            .push(mem, CallFrame::new_main(function))?;
+        self.stack_base.set(0);
+        self.instr.get(mem).switch_frame(function.code(mem), 0);
+
+        Ok(())
+    }
+
+    /// Execute instructions until the outermost function returns or the thread yields
+    fn run<'guard>(&self, mem: &'guard MutatorView) -> Result<EvalStatus<'guard>, RuntimeError> {
+        loop {
+            match self.vm_eval_stream(mem, 1024)? {
+                EvalStatus::Pending => (),
+                status => return Ok(status),
+            }
+        }
     }
 
-    /// Given ByteCode, execute up to max_instr more instructions
+    /// Execute up to max_instr more instructions from the current instruction stream
     fn vm_eval_stream<'guard>(
         &self,
         mem: &'guard MutatorView,
-        code: ScopedPtr<'guard, ByteCode>,
         max_instr: ArraySize,
     ) -> Result<EvalStatus<'guard>, RuntimeError> {
-        let instr = self.instr.get(mem);
-        // TODO this is broken logic, this function shouldn't switch back to this code object every
-        // time it is called
-        instr.switch_frame(code, 0);
-
         for _ in 0..max_instr {
             match self.eval_next_instr(mem) {
                 // Evaluation paused or completed without error
                 Ok(exit_cond) => match exit_cond {
-                    EvalStatus::Return(value) => return Ok(EvalStatus::Return(value)),
-                    _ => (),
+                    EvalStatus::Pending => (),
+                    status => return Ok(status),
                 },
 
                 // Evaluation hit an error
@@ -265,11 +353,11 @@ impl Thread {
                 Opcode::Add { dest, left, right } => {
                     let left = match *window[left as usize].get(mem) {
                         Value::Number(n) => n,
-                        _ => todo!(),
+                        _ => return Err(err_eval("Parameter to Add is not a number")),
                     };
                     let right = match *window[right as usize].get(mem) {
                         Value::Number(n) => n,
-                        _ => todo!(),
+                        _ => return Err(err_eval("Parameter to Add is not a number")),
                     };
                     let result = left + right;
                     let result = TaggedScopedPtr::new(mem, TaggedPtr::number(result));
@@ -278,11 +366,11 @@ impl Thread {
                 Opcode::Mul { dest, left, right } => {
                     let left = match *window[left as usize].get(mem) {
                         Value::Number(n) => n,
-                        _ => todo!(),
+                        _ => return Err(err_eval("Parameter to Mul is not a number")),
                     };
                     let right = match *window[right as usize].get(mem) {
                         Value::Number(n) => n,
-                        _ => todo!(),
+                        _ => return Err(err_eval("Parameter to Mul is not a number")),
                     };
                     let result = left * right;
                     let result = TaggedScopedPtr::new(mem, TaggedPtr::number(result));
@@ -641,6 +729,36 @@ impl Thread {
                         _ => return Err(err_eval("Type is not callable")),
                     }
                 }
+                // Create a Coroutine that will call the function in the `function` register in
+                // its own execution context when first resumed
+                Opcode::MakeCoroutine { dest, function } => {
+                    let function = window[function as usize].get(mem);
+                    let coroutine = Coroutine::alloc(mem, globals, function)?;
+                    window[dest as usize].set(coroutine.as_tagged(mem));
+                }
+                // Suspend this thread, passing the `src` register value to the resumer. The
+                // value given to the next resume will be written to the `dest` register.
+                Opcode::Yield { dest, src } => {
+                    return Ok(EvalStatus::Yield {
+                        value: window[src as usize].get(mem),
+                        resume_location: (stack_base + dest as usize) as ArraySize,
+                    });
+                }
+                // Run the Coroutine in the `coroutine` register until it yields or returns,
+                // putting the yielded or returned value in the `dest` register
+                Opcode::Resume {
+                    dest,
+                    coroutine,
+                    value,
+                } => {
+                    let coroutine = window[coroutine as usize].get(mem);
+                    let Value::Coroutine(coroutine) = *coroutine else {
+                        return Err(err_eval("Cannot resume a non-Coroutine type"));
+                    };
+
+                    let result = coroutine.resume(mem, window[value as usize].get(mem))?;
+                    window[dest as usize].set(result);
+                }
             }
 
             Ok(EvalStatus::Pending)
@@ -694,6 +812,127 @@ impl Thread {
     }
 }
 
+/// The lifecycle of a Coroutine
+#[derive(Copy, Clone, PartialEq)]
+pub enum CoroutineStatus {
+    /// The function has not been called yet
+    Created,
+    /// The Coroutine is executing, possibly having resumed another Coroutine
+    Running,
+    /// The Coroutine has yielded and is waiting to be resumed
+    Suspended,
+    /// The function has returned or raised an error
+    Dead,
+}
+
+/// A Coroutine is a function call with its own execution Thread that can be suspended with
+/// `(yield v)` and continued with `(resume co v)`. Globals are shared with the Thread that
+/// created it.
+pub struct Coroutine {
+    /// The execution context that the function runs in
+    thread: CellPtr<Thread>,
+    /// The Function or Partial that is called on the first resume
+    function: TaggedCellPtr,
+    status: Cell<CoroutineStatus>,
+    /// Absolute stack index where the value of the next resume is written while suspended
+    resume_location: Cell<ArraySize>,
+}
+
+impl Coroutine {
+    /// Allocate a new Coroutine on the heap. The function must need no more than one argument,
+    /// which is given the value of the first resume.
+    fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        globals: ScopedPtr<'guard, Dict>,
+        function: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, Coroutine>, RuntimeError> {
+        let arity = match *function {
+            Value::Function(f) => f.arity(),
+            Value::Partial(p) => p.arity(),
+            _ => return Err(err_eval("Cannot make a coroutine from a non-callable type")),
+        };
+
+        if arity > 1 {
+            return Err(err_eval(&format!(
+                "A coroutine function can take at most 1 argument, {} takes {}",
+                function, arity
+            )));
+        }
+
+        mem.alloc(Coroutine {
+            thread: CellPtr::new_with(Thread::alloc_with_globals(mem, globals)?),
+            function: TaggedCellPtr::new_with(function),
+            status: Cell::new(CoroutineStatus::Created),
+            resume_location: Cell::new(0),
+        })
+    }
+
+    /// Continue execution until the next yield or the function returns, passing `value` in
+    /// as the function argument on the first resume or as the result of the pending yield
+    fn resume<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let thread = self.thread.get(mem);
+
+        match self.status.get() {
+            CoroutineStatus::Created => thread.enter(mem, self.function.get(mem), value)?,
+            CoroutineStatus::Suspended => {
+                let stack = thread.stack.get(mem);
+                IndexedAnyContainer::set(&*stack, mem, self.resume_location.get(), value)?;
+            }
+            CoroutineStatus::Running => {
+                return Err(err_eval(
+                    "Cannot resume a coroutine that is already running",
+                ))
+            }
+            CoroutineStatus::Dead => {
+                return Err(err_eval("Cannot resume a coroutine that has finished"))
+            }
+        }
+
+        self.status.set(CoroutineStatus::Running);
+
+        match thread.run(mem) {
+            Ok(EvalStatus::Yield {
+                value,
+                resume_location,
+            }) => {
+                self.status.set(CoroutineStatus::Suspended);
+                self.resume_location.set(resume_location);
+                Ok(value)
+            }
+            Ok(EvalStatus::Return(value)) => {
+                self.status.set(CoroutineStatus::Dead);
+                Ok(value)
+            }
+            Ok(EvalStatus::Pending) => unreachable!(),
+            Err(e) => {
+                self.status.set(CoroutineStatus::Dead);
+                Err(e)
+            }
+        }
+    }
+
+    /// Return the current lifecycle state
+    pub fn status(&self) -> CoroutineStatus {
+        self.status.get()
+    }
+}
+
+impl Print for Coroutine {
+    fn print(&self, _guard: &dyn MutatorScope, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status.get() {
+            CoroutineStatus::Created => "created",
+            CoroutineStatus::Running => "running",
+            CoroutineStatus::Suspended => "suspended",
+            CoroutineStatus::Dead => "dead",
+        };
+        write!(f, "(Coroutine {})", status)
+    }
+}
+
 impl CallFrame {
     /// Instantiate an outer-level call frame at the beginning of the stack
     pub fn new_main<'guard>(main_fn: ScopedPtr<'guard, Function>) -> CallFrame {
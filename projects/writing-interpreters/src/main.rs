@@ -1,7 +1,11 @@
-use std::process;
+use std::{cell::RefCell, process, rc::Rc};
 
 use rustyline::{error::ReadlineError, Editor};
-use writing_interpreters::interpreter::{memory::Memory, repl::RepMaker, RuntimeError};
+use writing_interpreters::interpreter::{
+    memory::Memory,
+    repl::{is_incomplete, RepMaker, ReplHelper},
+    RuntimeError,
+};
 
 /// Read a line at a time, printing the input back out
 fn read_print_loop() -> Result<(), RuntimeError> {
@@ -14,9 +18,12 @@ fn read_print_loop() -> Result<(), RuntimeError> {
         None => None,
     };
 
-    // () means no completion support (TODO)
-    // Another TODO - find a more suitable alternative to rustyline
-    let mut reader = Editor::<()>::new();
+    // global names for completion, refreshed after each evaluation
+    let globals = Rc::new(RefCell::new(Vec::new()));
+
+    // TODO - find a more suitable alternative to rustyline
+    let mut reader = Editor::<ReplHelper>::new();
+    reader.set_helper(Some(ReplHelper::new(globals.clone())));
 
     // Try to load the repl history file
     if let Some(ref path) = history_file {
@@ -29,15 +36,35 @@ fn read_print_loop() -> Result<(), RuntimeError> {
     let rep_maker = RepMaker {};
     let rep = mem.mutate(&rep_maker, ())?;
 
+    // lines are buffered until parentheses balance
+    let mut input = String::new();
+
     // repl
     loop {
-        let readline = reader.readline("> ");
+        let prompt = if input.is_empty() { "> " } else { ".. " };
+        let readline = reader.readline(prompt);
 
         match readline {
             // valid input
             Ok(line) => {
-                reader.add_history_entry(&line);
-                mem.mutate(&rep, line)?;
+                if !input.is_empty() {
+                    input.push('\n');
+                }
+                input.push_str(&line);
+
+                if is_incomplete(&input) {
+                    continue;
+                }
+
+                let input = std::mem::take(&mut input);
+                reader.add_history_entry(input.as_str());
+                mem.mutate(&rep, input)?;
+                *globals.borrow_mut() = mem.mutate(&rep.global_names(), ())?;
+            }
+
+            // Ctrl-C abandons a partially entered expression
+            Err(ReadlineError::Interrupted) if !input.is_empty() => {
+                input.clear();
             }
 
             // some kind of program termination condition
@@ -0,0 +1,9 @@
+; Integer arithmetic special forms
+
+(+ 1 2) ; => 3
+(* 3 (+ 1 2)) ; => 9
+(+ 5 -7) ; => -2
+
+; operands must be numbers
+(+ 1 (quote a))
+; !! EvalError
@@ -0,0 +1,11 @@
+; Coroutines suspend at each yield and continue from there when resumed
+
+(def twice (x) (+ (yield x) (yield (* x 2))))
+(set (quote co) (coroutine twice))
+
+(resume co 1) ; => 1
+(resume co 10) ; => 2
+(resume co 5) ; => 15
+(resume co 0) ; !! EvalError
+
+(yield 1) ; !! EvalError: outside of a coroutine
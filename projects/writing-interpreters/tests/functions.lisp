@@ -0,0 +1,19 @@
+; Function definition, recursion and partial application
+
+(def fact (n)
+  (cond (is? n 0) 1
+        true (* n (fact (+ n -1)))))
+
+(fact 5) ; => 120
+
+((lambda (x) (* x x)) 7) ; => 49
+
+(let ((a 1) (b 2)) (+ a b)) ; => 3
+
+; calling with too few arguments returns a partial application
+(def add (a b) (+ a b))
+(set (quote inc) (add 1))
+(inc 9) ; => 10
+
+(undefined-function 1)
+; !! EvalError
@@ -0,0 +1,26 @@
+use std::path::Path;
+
+use writing_interpreters::interpreter::golden::{discover, run_file};
+
+/// Evaluate every `tests/*.lisp` script, checking each `; =>` and `; !!` annotation
+#[test]
+fn golden_scripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let scripts = discover(&dir).unwrap();
+    assert!(!scripts.is_empty(), "no scripts found in {}", dir.display());
+
+    let mut report = String::new();
+
+    for script in &scripts {
+        match run_file(script) {
+            Ok(failures) => {
+                for failure in failures {
+                    report.push_str(&format!("{}: {}\n", script.display(), failure));
+                }
+            }
+            Err(e) => report.push_str(&format!("{}: {}\n", script.display(), e)),
+        }
+    }
+
+    assert!(report.is_empty(), "\n{}", report);
+}
@@ -0,0 +1,14 @@
+; Pair construction and access
+
+(cons 1 (cons 2 nil)) ; => (1 2)
+(cons 1 2) ; => (1 . 2)
+(car (quote (a b c))) ; => a
+(cdr (quote (a b c))) ; => (b c)
+(car nil) ; => nil
+
+(atom? (quote a)) ; => true
+(atom? (quote (a))) ; => nil
+(nil? nil) ; => true
+(is? (quote a) (quote a)) ; => true
+
+(car (quote a)) ; !! EvalError: not a list